@@ -0,0 +1,79 @@
+use super::CHANNELS;
+use alloc::vec::Vec;
+use firefly_hal::{Dir, DirImpl};
+
+/// The name of the file in the app data dir into which the audio is captured.
+pub(crate) const CAPTURE_FILE: &str = "capture.wav";
+/// The file in the app data dir into which the samples are streamed during the capture.
+pub(crate) const CAPTURE_TMP: &str = "capture.tmp";
+const SAMPLE_RATE: u32 = 44_100;
+const BITS_PER_SAMPLE: u16 = 16;
+
+/// The ongoing capture of the audio output.
+///
+/// The WAV header must have the size of the data but the file system
+/// doesn't support seeking back to fix the header. So, the samples
+/// are streamed into a temporary file which stays open until the capture
+/// is stopped. Then the WAV file is assembled from the header
+/// and the content of the temporary file.
+pub(crate) struct AudioCapture {
+    pub stream: <DirImpl as Dir>::Write,
+    /// How many bytes of samples are written.
+    pub size: u32,
+}
+
+/// The header of a 16-bit stereo PCM WAV file with the given size of the data.
+pub(crate) fn wav_header(data_size: u32) -> [u8; 44] {
+    let channels = CHANNELS as u16;
+    let block_align = channels * BITS_PER_SAMPLE / 8;
+    let byte_rate = SAMPLE_RATE * u32::from(block_align);
+    let mut header = [0u8; 44];
+    header[0..4].copy_from_slice(b"RIFF");
+    let riff_size = data_size.saturating_add(36);
+    header[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    header[8..12].copy_from_slice(b"WAVE");
+    header[12..16].copy_from_slice(b"fmt ");
+    header[16..20].copy_from_slice(&16u32.to_le_bytes());
+    // audio format: PCM
+    header[20..22].copy_from_slice(&1u16.to_le_bytes());
+    header[22..24].copy_from_slice(&channels.to_le_bytes());
+    header[24..28].copy_from_slice(&SAMPLE_RATE.to_le_bytes());
+    header[28..32].copy_from_slice(&byte_rate.to_le_bytes());
+    header[32..34].copy_from_slice(&block_align.to_le_bytes());
+    header[34..36].copy_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+    header[36..40].copy_from_slice(b"data");
+    header[40..44].copy_from_slice(&data_size.to_le_bytes());
+    header
+}
+
+/// Encode the interleaved audio samples as WAV data.
+pub(crate) fn encode_samples(buf: &[i16]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(buf.len() * 2);
+    for sample in buf {
+        raw.extend_from_slice(&sample.to_le_bytes());
+    }
+    raw
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wav_header() {
+        let header = wav_header(400);
+        assert_eq!(&header[..4], b"RIFF");
+        assert_eq!(&header[4..8], &436u32.to_le_bytes());
+        assert_eq!(&header[40..44], &400u32.to_le_bytes());
+        assert_eq!(&header[36..40], b"data");
+        assert_eq!(header[22], 2);
+        assert_eq!(&header[28..32], &176_400u32.to_le_bytes());
+        assert_eq!(header[32], 4);
+    }
+
+    #[test]
+    fn test_encode_samples() {
+        let raw = encode_samples(&[1, -1, 0x1234]);
+        assert_eq!(raw, [1, 0, 0xff, 0xff, 0x34, 0x12]);
+    }
+}
@@ -0,0 +1,370 @@
+use super::mul_add;
+use alloc::vec;
+use alloc::vec::Vec;
+use firefly_audio::{Frame, Processor, Sample};
+
+/// The longest supported delay, in samples (half a second).
+const MAX_DELAY: u32 = 22_050;
+/// Lengths of the reverb comb filters, in samples. Mutually prime to avoid resonance.
+const COMBS: [usize; 4] = [1116, 1188, 1277, 1356];
+/// Lengths of the reverb all-pass filters, in samples.
+const ALL_PASSES: [usize; 2] = [556, 441];
+
+/// Apply the function to each pair of left and right values of the frame.
+///
+/// Mono frames are treated as stereo frames with the same value in both channels.
+fn map_frame<F>(frame: Frame, mut f: F) -> Frame
+where
+    F: FnMut(f32, f32) -> (f32, f32),
+{
+    let left = frame.left.to_array();
+    let right = match frame.right {
+        Some(right) => right.to_array(),
+        None => left,
+    };
+    let mut out_left = [0f32; 8];
+    let mut out_right = [0f32; 8];
+    for i in 0..8 {
+        (out_left[i], out_right[i]) = f(left[i], right[i]);
+    }
+    Frame::stereo(Sample::new(out_left), Sample::new(out_right))
+}
+
+/// Echo: mixes the input with its delayed copy.
+pub(crate) struct Delay {
+    left: Vec<f32>,
+    right: Vec<f32>,
+    pos: usize,
+    /// The delay in samples, up to the buffer size.
+    time: usize,
+    feedback: f32,
+    mix: f32,
+}
+
+impl Delay {
+    pub fn new(time: u32, feedback: f32, mix: f32) -> Self {
+        let size = time.clamp(1, MAX_DELAY) as usize;
+        Self {
+            left: vec![0.; size],
+            right: vec![0.; size],
+            pos: 0,
+            time: size,
+            feedback: feedback.clamp(0., 0.99),
+            mix: mix.clamp(0., 1.),
+        }
+    }
+}
+
+impl Processor for Delay {
+    fn set(&mut self, param: u8, val: f32) {
+        match param {
+            // The buffer is allocated on creation, the delay can only go shorter.
+            0 => self.time = (val as usize).clamp(1, self.left.len()),
+            1 => self.feedback = val.clamp(0., 0.99),
+            2 => self.mix = val.clamp(0., 1.),
+            _ => {}
+        }
+    }
+
+    fn reset(&mut self) {
+        self.left.fill(0.);
+        self.right.fill(0.);
+        self.pos = 0;
+    }
+
+    fn process_frame(&mut self, frame: Frame) -> Option<Frame> {
+        let frame = map_frame(frame, |l, r| {
+            let size = self.left.len();
+            let read = (self.pos + size - self.time) % size;
+            let (dl, dr) = (self.left[read], self.right[read]);
+            self.left[self.pos] = mul_add(dl, self.feedback, l);
+            self.right[self.pos] = mul_add(dr, self.feedback, r);
+            self.pos = (self.pos + 1) % size;
+            (mul_add(dl - l, self.mix, l), mul_add(dr - r, self.mix, r))
+        });
+        Some(frame)
+    }
+}
+
+/// A feedback comb filter with a low-pass in the feedback loop.
+struct Comb {
+    buf: Vec<f32>,
+    pos: usize,
+    store: f32,
+}
+
+impl Comb {
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let out = self.buf[self.pos];
+        self.store = mul_add(self.store - out, damping, out);
+        self.buf[self.pos] = mul_add(self.store, feedback, input);
+        self.pos = (self.pos + 1) % self.buf.len();
+        out
+    }
+}
+
+struct AllPass {
+    buf: Vec<f32>,
+    pos: usize,
+}
+
+impl AllPass {
+    fn process(&mut self, input: f32) -> f32 {
+        let delayed = self.buf[self.pos];
+        let out = delayed - input;
+        self.buf[self.pos] = mul_add(delayed, 0.5, input);
+        self.pos = (self.pos + 1) % self.buf.len();
+        out
+    }
+}
+
+/// A small Schroeder reverb: parallel comb filters followed by all-pass filters.
+///
+/// The reverb tail is mono and is added to both channels.
+pub(crate) struct Reverb {
+    combs: Vec<Comb>,
+    all_passes: Vec<AllPass>,
+    /// The room size, from 0 to 1.
+    room: f32,
+    damping: f32,
+    mix: f32,
+}
+
+impl Reverb {
+    pub fn new(room: f32, damping: f32, mix: f32) -> Self {
+        let combs = COMBS
+            .iter()
+            .map(|size| Comb {
+                buf: vec![0.; *size],
+                pos: 0,
+                store: 0.,
+            })
+            .collect();
+        let all_passes = ALL_PASSES
+            .iter()
+            .map(|size| AllPass {
+                buf: vec![0.; *size],
+                pos: 0,
+            })
+            .collect();
+        Self {
+            combs,
+            all_passes,
+            room: room.clamp(0., 1.),
+            damping: damping.clamp(0., 1.),
+            mix: mix.clamp(0., 1.),
+        }
+    }
+}
+
+impl Processor for Reverb {
+    fn set(&mut self, param: u8, val: f32) {
+        match param {
+            0 => self.room = val.clamp(0., 1.),
+            1 => self.damping = val.clamp(0., 1.),
+            2 => self.mix = val.clamp(0., 1.),
+            _ => {}
+        }
+    }
+
+    fn reset(&mut self) {
+        for comb in &mut self.combs {
+            comb.buf.fill(0.);
+            comb.store = 0.;
+        }
+        for all_pass in &mut self.all_passes {
+            all_pass.buf.fill(0.);
+        }
+    }
+
+    fn process_frame(&mut self, frame: Frame) -> Option<Frame> {
+        let feedback = mul_add(self.room, 0.28, 0.7);
+        let frame = map_frame(frame, |l, r| {
+            let input = (l + r) * 0.5;
+            let mut wet = 0.;
+            for comb in &mut self.combs {
+                wet += comb.process(input, feedback, self.damping);
+            }
+            wet /= self.combs.len() as f32;
+            for all_pass in &mut self.all_passes {
+                wet = all_pass.process(wet);
+            }
+            (mul_add(wet - l, self.mix, l), mul_add(wet - r, self.mix, r))
+        });
+        Some(frame)
+    }
+}
+
+/// Reduces the bit depth and the sample rate for the lo-fi sound.
+pub(crate) struct Bitcrusher {
+    /// The number of quantization levels.
+    levels: f32,
+    /// For how many samples each sample is repeated.
+    downsample: u32,
+    counter: u32,
+    held: (f32, f32),
+}
+
+impl Bitcrusher {
+    pub fn new(bits: u32, downsample: u32) -> Self {
+        let mut crusher = Self {
+            levels: 0.,
+            downsample: 1,
+            counter: 0,
+            held: (0., 0.),
+        };
+        crusher.set(0, bits as f32);
+        crusher.set(1, downsample as f32);
+        crusher
+    }
+}
+
+impl Processor for Bitcrusher {
+    fn set(&mut self, param: u8, val: f32) {
+        match param {
+            0 => {
+                let bits = (val as u32).clamp(1, 16);
+                self.levels = (1u32 << (bits - 1)) as f32;
+            }
+            1 => self.downsample = (val as u32).clamp(1, 64),
+            _ => {}
+        }
+    }
+
+    fn reset(&mut self) {
+        self.counter = 0;
+        self.held = (0., 0.);
+    }
+
+    fn process_frame(&mut self, frame: Frame) -> Option<Frame> {
+        let frame = map_frame(frame, |l, r| {
+            if self.counter == 0 {
+                let levels = self.levels;
+                let quantize = |v: f32| (v * levels).round() / levels;
+                self.held = (quantize(l), quantize(r));
+            }
+            self.counter = (self.counter + 1) % self.downsample;
+            self.held
+        });
+        Some(frame)
+    }
+}
+
+/// Dynamic range compressor.
+///
+/// Reduces the volume of everything above the threshold. With a very high ratio,
+/// works as a limiter, preventing clipping on the master bus.
+pub(crate) struct Compressor {
+    threshold: f32,
+    ratio: f32,
+    /// How fast the gain reduction kicks in, from 0 to 1.
+    attack: f32,
+    /// How fast the gain reduction goes away, from 0 to 1.
+    release: f32,
+    /// The current peak level of the input.
+    envelope: f32,
+}
+
+impl Compressor {
+    /// Attack and release are in samples.
+    pub fn new(threshold: f32, ratio: f32, attack: u32, release: u32) -> Self {
+        let mut compressor = Self {
+            threshold: 1.,
+            ratio: 1.,
+            attack: 1.,
+            release: 1.,
+            envelope: 0.,
+        };
+        compressor.set(0, threshold);
+        compressor.set(1, ratio);
+        compressor.set(2, attack as f32);
+        compressor.set(3, release as f32);
+        compressor
+    }
+}
+
+impl Processor for Compressor {
+    fn set(&mut self, param: u8, val: f32) {
+        match param {
+            0 => self.threshold = val.clamp(0.001, 1.),
+            1 => self.ratio = val.max(1.),
+            2 => self.attack = 1. / val.max(1.),
+            3 => self.release = 1. / val.max(1.),
+            _ => {}
+        }
+    }
+
+    fn reset(&mut self) {
+        self.envelope = 0.;
+    }
+
+    fn process_frame(&mut self, frame: Frame) -> Option<Frame> {
+        let frame = map_frame(frame, |l, r| {
+            let peak = l.abs().max(r.abs());
+            let speed = if peak > self.envelope {
+                self.attack
+            } else {
+                self.release
+            };
+            self.envelope += (peak - self.envelope) * speed;
+            if self.envelope <= self.threshold {
+                return (l, r);
+            }
+            let target = self.threshold + (self.envelope - self.threshold) / self.ratio;
+            let gain = target / self.envelope;
+            (l * gain, r * gain)
+        });
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mono(val: f32) -> Frame {
+        Frame::mono(Sample::new([val; 8]))
+    }
+
+    fn left(frame: &Frame) -> [f32; 8] {
+        frame.left.to_array()
+    }
+
+    #[test]
+    fn test_delay() {
+        let mut delay = Delay::new(4, 0., 1.);
+        let frame = delay.process_frame(mono(1.)).unwrap();
+        assert_eq!(left(&frame), [0., 0., 0., 0., 1., 1., 1., 1.]);
+        delay.reset();
+        let frame = delay.process_frame(mono(0.5)).unwrap();
+        assert_eq!(left(&frame)[0], 0.);
+    }
+
+    #[test]
+    fn test_bitcrusher() {
+        let mut crusher = Bitcrusher::new(2, 4);
+        let input = Frame::mono(Sample::new([0.1, 0.9, 0.9, 0.9, 0.6, 0., 0., 0.]));
+        let frame = crusher.process_frame(input).unwrap();
+        assert_eq!(left(&frame), [0., 0., 0., 0., 0.5, 0.5, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn test_compressor() {
+        let mut limiter = Compressor::new(0.5, 1000., 1, 1000);
+        let frame = limiter.process_frame(mono(1.)).unwrap();
+        for val in left(&frame) {
+            assert!(val > 0.49 && val < 0.51);
+        }
+        let frame = limiter.process_frame(mono(0.2)).unwrap();
+        for val in left(&frame) {
+            assert!(val < 0.2);
+        }
+    }
+
+    #[test]
+    fn test_reverb() {
+        let mut reverb = Reverb::new(0.5, 0.5, 0.);
+        let frame = reverb.process_frame(mono(0.3)).unwrap();
+        assert_eq!(left(&frame), [0.3; 8]);
+    }
+}
@@ -0,0 +1,241 @@
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::Cell;
+use firefly_audio::modulators::Modulator;
+
+const SAMPLE_RATE: f32 = 44_100.;
+
+/// The gate of an [`Envelope`], opened and closed by the app.
+///
+/// When the gate opens, the envelope goes through attack and decay
+/// and holds at the sustain level. When it closes, the envelope releases.
+#[derive(Default)]
+pub(crate) struct Gate {
+    open: Cell<bool>,
+}
+
+impl Gate {
+    pub fn set(&self, open: bool) {
+        self.open.set(open);
+    }
+}
+
+/// ADSR envelope.
+///
+/// Attack, decay, and release are in samples, sustain is the level from 0 to 1.
+pub(crate) struct Envelope {
+    attack: u32,
+    decay: u32,
+    sustain: f32,
+    release: u32,
+    gate: Rc<Gate>,
+    /// The gate state seen on the last call.
+    was_open: Cell<bool>,
+    /// When the gate last changed its state.
+    changed_at: Cell<u32>,
+    /// The envelope level when the gate last changed its state.
+    changed_level: Cell<f32>,
+    /// The last produced level.
+    level: Cell<f32>,
+}
+
+impl Envelope {
+    pub fn new(attack: u32, decay: u32, sustain: f32, release: u32, gate: Rc<Gate>) -> Self {
+        Self {
+            attack,
+            decay,
+            sustain: sustain.clamp(0., 1.),
+            release,
+            gate,
+            was_open: Cell::new(false),
+            changed_at: Cell::new(0),
+            changed_level: Cell::new(0.),
+            level: Cell::new(0.),
+        }
+    }
+
+    fn level_at(&self, open: bool, elapsed: u32) -> f32 {
+        let from = self.changed_level.get();
+        if !open {
+            if elapsed >= self.release {
+                return 0.;
+            }
+            return from * (1. - elapsed as f32 / self.release as f32);
+        }
+        if elapsed < self.attack {
+            // Retriggering starts the attack from the current level.
+            return from + (1. - from) * elapsed as f32 / self.attack as f32;
+        }
+        let elapsed = elapsed - self.attack;
+        if elapsed < self.decay {
+            return 1. - (1. - self.sustain) * elapsed as f32 / self.decay as f32;
+        }
+        self.sustain
+    }
+}
+
+impl Modulator for Envelope {
+    fn get(&self, now: u32) -> f32 {
+        let open = self.gate.open.get();
+        if open != self.was_open.get() {
+            self.was_open.set(open);
+            self.changed_at.set(now);
+            self.changed_level.set(self.level.get());
+        }
+        let elapsed = now.saturating_sub(self.changed_at.get());
+        let level = self.level_at(open, elapsed);
+        self.level.set(level);
+        level
+    }
+}
+
+/// The shape of a periodic [`Lfo`].
+pub(crate) enum Wave {
+    /// Switches between the low and the high value every half of the period.
+    Square,
+    /// Linearly goes from the low to the high value and then jumps back.
+    Saw,
+    /// Holds a random value between low and high for the whole period.
+    Random(u32),
+}
+
+/// Periodic low-frequency oscillator.
+pub(crate) struct Lfo {
+    wave: Wave,
+    /// The period in samples.
+    period: u32,
+    low: f32,
+    high: f32,
+}
+
+impl Lfo {
+    pub fn new(wave: Wave, freq: f32, low: f32, high: f32) -> Self {
+        let period = (SAMPLE_RATE / freq.max(0.01)) as u32;
+        Self {
+            wave,
+            period: period.max(1),
+            low,
+            high,
+        }
+    }
+}
+
+impl Modulator for Lfo {
+    fn get(&self, now: u32) -> f32 {
+        let phase = (now % self.period) as f32 / self.period as f32;
+        let ratio = match self.wave {
+            Wave::Square => {
+                if phase < 0.5 {
+                    1.
+                } else {
+                    0.
+                }
+            }
+            Wave::Saw => phase,
+            Wave::Random(seed) => {
+                let step = now / self.period;
+                random(seed ^ step) as f32 / u32::MAX as f32
+            }
+        };
+        super::mul_add(self.high - self.low, ratio, self.low)
+    }
+}
+
+/// Piecewise-linear curve defined by (time in samples, value) points.
+///
+/// Holds the first value before the first point and the last value after the last point.
+pub(crate) struct Curve {
+    points: Vec<(u32, f32)>,
+}
+
+impl Curve {
+    pub fn new(mut points: Vec<(u32, f32)>) -> Self {
+        points.sort_by_key(|(t, _)| *t);
+        Self { points }
+    }
+
+    /// Parse points from the guest memory: pairs of little-endian u32 time and f32 value.
+    pub fn from_bytes(raw: &[u8]) -> Self {
+        let points = raw
+            .chunks_exact(8)
+            .map(|c| {
+                let time = u32::from_le_bytes([c[0], c[1], c[2], c[3]]);
+                let val = f32::from_le_bytes([c[4], c[5], c[6], c[7]]);
+                (time, val)
+            })
+            .collect();
+        Self::new(points)
+    }
+}
+
+impl Modulator for Curve {
+    fn get(&self, now: u32) -> f32 {
+        let idx = self.points.partition_point(|(t, _)| *t <= now);
+        let Some((t1, v1)) = self.points.get(idx) else {
+            return self.points.last().map(|(_, v)| *v).unwrap_or_default();
+        };
+        let Some((t0, v0)) = idx.checked_sub(1).and_then(|i| self.points.get(i)) else {
+            return *v1;
+        };
+        let ratio = (now - t0) as f32 / (t1 - t0) as f32;
+        v0 + (v1 - v0) * ratio
+    }
+}
+
+/// A fast integer hash used to produce random values.
+const fn random(x: u32) -> u32 {
+    let mut x = x.wrapping_add(0x9e37_79b9);
+    x = (x ^ (x >> 16)).wrapping_mul(0x85eb_ca6b);
+    x = (x ^ (x >> 13)).wrapping_mul(0xc2b2_ae35);
+    x ^ (x >> 16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope() {
+        let gate = Rc::new(Gate::default());
+        let env = Envelope::new(10, 10, 0.5, 10, gate.clone());
+        assert_eq!(env.get(0), 0.);
+        gate.set(true);
+        assert_eq!(env.get(100), 0.);
+        assert_eq!(env.get(105), 0.5);
+        assert_eq!(env.get(110), 1.);
+        assert_eq!(env.get(115), 0.75);
+        assert_eq!(env.get(120), 0.5);
+        assert_eq!(env.get(500), 0.5);
+        gate.set(false);
+        assert_eq!(env.get(600), 0.5);
+        assert_eq!(env.get(605), 0.25);
+        assert_eq!(env.get(610), 0.);
+    }
+
+    #[test]
+    fn test_lfo() {
+        let lfo = Lfo::new(Wave::Square, 4410., 1., 3.);
+        assert_eq!(lfo.get(0), 3.);
+        assert_eq!(lfo.get(6), 1.);
+        assert_eq!(lfo.get(10), 3.);
+
+        let lfo = Lfo::new(Wave::Saw, 4410., 1., 3.);
+        assert_eq!(lfo.get(5), 2.);
+
+        let lfo = Lfo::new(Wave::Random(13), 4410., 1., 3.);
+        let val = lfo.get(3);
+        assert_eq!(lfo.get(9), val);
+        assert!((1. ..=3.).contains(&val));
+    }
+
+    #[test]
+    fn test_curve() {
+        let curve = Curve::new(alloc::vec![(20, 0.), (10, 1.), (30, 4.)]);
+        assert_eq!(curve.get(0), 1.);
+        assert_eq!(curve.get(10), 1.);
+        assert_eq!(curve.get(15), 0.5);
+        assert_eq!(curve.get(25), 2.);
+        assert_eq!(curve.get(100), 4.);
+        assert_eq!(Curve::new(alloc::vec![]).get(10), 0.);
+    }
+}
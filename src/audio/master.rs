@@ -0,0 +1,198 @@
+use super::CHANNELS;
+use serde::{Deserialize, Serialize};
+
+/// How long it takes to fade the audio in or out, in samples.
+const FADE: f32 = 4410.;
+/// The volume of the audio ducked while the system menu is open.
+const DUCK_GAIN: f32 = 0.2;
+
+/// What to do with the app audio when the system menu or an error is shown.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum MenuAudio {
+    /// Fade out and stop rendering the audio graph.
+    #[default]
+    Pause,
+    /// Keep playing but make the audio quieter.
+    Duck,
+}
+
+/// Runtime audio settings stored in `sys/audio`.
+///
+/// New fields must be added only at the end. Files written by older
+/// runtimes don't have them, and the missing fields get the default values.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub(crate) struct AudioSettings {
+    pub menu: MenuAudio,
+    /// If true, mix both channels into one and play it on both sides.
+    pub mono: bool,
+}
+
+impl AudioSettings {
+    pub fn decode(raw: &[u8]) -> Result<Self, postcard::Error> {
+        let mut settings = Self::default();
+        let (menu, rest) = postcard::take_from_bytes(raw)?;
+        settings.menu = menu;
+        if rest.is_empty() {
+            return Ok(settings);
+        }
+        let (mono, _) = postcard::take_from_bytes(rest)?;
+        settings.mono = mono;
+        Ok(settings)
+    }
+
+    pub fn encode<'b>(&self, buf: &'b mut [u8]) -> Result<&'b mut [u8], postcard::Error> {
+        postcard::to_slice(self, buf)
+    }
+}
+
+/// The runtime-level processing applied on top of the audio graph output.
+///
+/// Applies the volume, the mono downmix, and fading in and out.
+pub(crate) struct Master {
+    /// The current fade gain.
+    gain: f32,
+    /// The fade gain to reach.
+    target: f32,
+    /// The device volume multiplied by the app volume, from 0 to 1.
+    volume: f32,
+    mono: bool,
+}
+
+impl Master {
+    pub const fn new() -> Self {
+        Self {
+            gain: 1.,
+            target: 1.,
+            volume: 1.,
+            mono: false,
+        }
+    }
+
+    /// Set the device volume (0-255), the mono mode, and the app volume (in percents).
+    pub fn configure(&mut self, volume: u8, mono: bool, app_volume: u8) {
+        let master = f32::from(volume) / f32::from(u8::MAX);
+        let app = f32::from(app_volume.min(100)) / 100.;
+        self.volume = master * app;
+        self.mono = mono;
+    }
+
+    /// Start fading out the app audio.
+    pub fn suspend(&mut self, mode: MenuAudio) {
+        self.target = match mode {
+            MenuAudio::Pause => 0.,
+            MenuAudio::Duck => DUCK_GAIN,
+        };
+    }
+
+    /// Start fading the app audio back in.
+    pub fn resume(&mut self) {
+        self.target = 1.;
+    }
+
+    /// True if the audio is fully faded out and the audio graph should not be rendered.
+    ///
+    /// Skipping the rendering keeps all the audio sources at their current position,
+    /// so that they continue where they left off when the audio is resumed.
+    pub fn is_paused(&self) -> bool {
+        self.gain == 0. && self.target == 0.
+    }
+
+    /// Apply the gain and the downmix to the rendered audio.
+    pub fn apply(&mut self, buf: &mut [i16]) {
+        if self.gain == 1. && self.target == 1. && self.volume == 1. && !self.mono {
+            return;
+        }
+        let step = 1. / FADE;
+        for frame in buf.chunks_exact_mut(CHANNELS) {
+            if self.gain < self.target {
+                self.gain = (self.gain + step).min(self.target);
+            } else if self.gain > self.target {
+                self.gain = (self.gain - step).max(self.target);
+            }
+            if self.mono {
+                let sum: i32 = frame.iter().map(|v| i32::from(*v)).sum();
+                let avg = (sum / CHANNELS as i32) as i16;
+                frame.fill(avg);
+            }
+            let gain = self.gain * self.volume;
+            for val in frame {
+                *val = (f32::from(*val) * gain) as i16;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_settings() {
+        // Written before the mono mode was added.
+        let settings = AudioSettings::decode(&[1]).unwrap();
+        assert_eq!(settings.menu, MenuAudio::Duck);
+        assert!(!settings.mono);
+
+        let mut buf = [0; 16];
+        let new = AudioSettings {
+            menu: MenuAudio::Duck,
+            mono: true,
+        };
+        let raw = new.encode(&mut buf).unwrap();
+        let settings = AudioSettings::decode(raw).unwrap();
+        assert_eq!(settings.menu, MenuAudio::Duck);
+        assert!(settings.mono);
+    }
+
+    #[test]
+    fn test_master_pause() {
+        let mut master = Master::new();
+        let mut buf = [100i16; 4];
+        master.apply(&mut buf);
+        assert_eq!(buf, [100; 4]);
+
+        master.suspend(MenuAudio::Pause);
+        assert!(!master.is_paused());
+        let mut buf = [10_000i16; 4];
+        master.apply(&mut buf);
+        assert!(buf[0] < 10_000);
+        assert!(buf[2] < buf[0]);
+        for _ in 0..FADE as usize {
+            master.apply(&mut [0; 2]);
+        }
+        assert!(master.is_paused());
+
+        master.resume();
+        assert!(!master.is_paused());
+        let mut buf = [10_000i16; 2];
+        master.apply(&mut buf);
+        assert!(buf[0] > 0);
+    }
+
+    #[test]
+    fn test_master_volume_mono() {
+        let mut master = Master::new();
+        master.configure(u8::MAX, true, 50);
+        let mut buf = [1000i16, 3000];
+        master.apply(&mut buf);
+        assert_eq!(buf, [1000; 2]);
+
+        master.configure(0, false, 100);
+        let mut buf = [1000i16, 3000];
+        master.apply(&mut buf);
+        assert_eq!(buf, [0; 2]);
+    }
+
+    #[test]
+    fn test_master_duck() {
+        let mut master = Master::new();
+        master.suspend(MenuAudio::Duck);
+        for _ in 0..FADE as usize {
+            master.apply(&mut [0; 2]);
+        }
+        assert!(!master.is_paused());
+        let mut buf = [1000i16; 2];
+        master.apply(&mut buf);
+        assert_eq!(buf, [200; 2]);
+    }
+}
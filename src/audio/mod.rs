@@ -0,0 +1,30 @@
+//! Audio nodes and bookkeeping implemented on the runtime side.
+
+mod adpcm;
+mod capture;
+mod effects;
+mod lfo;
+mod master;
+mod scheduler;
+mod status;
+mod tracker;
+mod tree;
+
+pub(crate) use adpcm::*;
+pub(crate) use capture::*;
+pub(crate) use effects::*;
+pub(crate) use lfo::*;
+pub(crate) use master::*;
+pub(crate) use scheduler::*;
+pub(crate) use status::*;
+pub(crate) use tracker::*;
+pub(crate) use tree::*;
+
+/// Calculate `a * b + c`.
+///
+/// Float math functions from std aren't available in no_std.
+fn mul_add(a: f32, b: f32, c: f32) -> f32 {
+    micromath::F32(a)
+        .mul_add(micromath::F32(b), micromath::F32(c))
+        .0
+}
@@ -0,0 +1,279 @@
+use super::AudioTree;
+use alloc::vec::Vec;
+use firefly_audio::Manager;
+
+/// The number of interleaved channels in the audio output buffer.
+pub(crate) const CHANNELS: usize = 2;
+/// The number of samples in a frame processed by audio nodes.
+pub(crate) const FRAME: usize = 8;
+/// The number of values in a rendered frame of the audio output buffer.
+const FRAME_LEN: usize = FRAME * CHANNELS;
+/// How many events can be waiting in the queue at once.
+const MAX_EVENTS: usize = 256;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum Action {
+    /// Reset the node state.
+    Reset,
+    /// Reset the node and all its children. Used to (re)start a sound.
+    ResetAll,
+    /// Set the node parameter to the given value.
+    ///
+    /// If the node modulator modulates the same param, the modulator is removed
+    /// so that it doesn't override the value. A modulator of another param is kept.
+    Set(u8, f32),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Event {
+    /// The audio sample at which the event should be applied.
+    at: u64,
+    node_id: u32,
+    action: Action,
+}
+
+/// Timed events for the audio graph.
+///
+/// The events are applied in the audio render path when the output
+/// reaches the scheduled sample, so their timing doesn't depend on
+/// the frame rate. Audio nodes process samples in frames, so before rendering
+/// a frame the events due within it are passed to the nodes together with
+/// their offset in the frame, and the nodes apply them at that exact sample.
+///
+/// For that to work, the scheduler must know where each frame starts.
+/// So, the audio manager always renders whole frames, and the part of the last
+/// frame that didn't fit into the output buffer is kept for the next write.
+#[derive(Default)]
+pub(crate) struct Scheduler {
+    /// How many samples were rendered so far. Always a multiple of [`FRAME`].
+    rendered: u64,
+    /// Scheduled events, sorted by time.
+    events: Vec<Event>,
+    /// The last rendered frame.
+    frame: [i16; FRAME_LEN],
+    /// How many values at the end of the last frame weren't written into the output yet.
+    pending: usize,
+}
+
+impl Scheduler {
+    /// The number of samples written into the audio output since the app start.
+    pub const fn now(&self) -> u64 {
+        self.rendered - (self.pending / CHANNELS) as u64
+    }
+
+    /// Add an event to the queue.
+    ///
+    /// Events scheduled for the same sample are applied in the order of adding.
+    /// Returns false if the queue is full.
+    pub fn add(&mut self, at: u64, node_id: u32, action: Action) -> bool {
+        if self.events.len() >= MAX_EVENTS {
+            return false;
+        }
+        let idx = self.events.partition_point(|e| e.at <= at);
+        let event = Event {
+            at,
+            node_id,
+            action,
+        };
+        self.events.insert(idx, event);
+        true
+    }
+
+    /// Cancel all events scheduled for the given node.
+    pub fn cancel(&mut self, node_id: u32) {
+        self.events.retain(|e| e.node_id != node_id);
+    }
+
+    /// Update node IDs after a part of the audio graph was removed.
+    ///
+    /// The new IDs are indexed by the old IDs. Events for removed nodes are dropped.
+    pub fn renumber(&mut self, new_ids: &[Option<u32>]) {
+        self.events
+            .retain_mut(|e| match new_ids.get(e.node_id as usize) {
+                Some(Some(id)) => {
+                    e.node_id = *id;
+                    true
+                }
+                _ => false,
+            });
+    }
+
+    /// Fill the buffer with the audio output, applying the events that get due.
+    pub fn render(&mut self, audio: &mut Manager, tree: &AudioTree, buf: &mut [i16]) {
+        let start = FRAME_LEN - self.pending;
+        let n = self.pending.min(buf.len());
+        buf[..n].copy_from_slice(&self.frame[start..start + n]);
+        self.pending -= n;
+        let mut buf = &mut buf[n..];
+        while !buf.is_empty() {
+            self.arm_due(tree);
+            let frames = self.frames_before_next().min(buf.len() / FRAME_LEN);
+            if frames == 0 {
+                // Less than a frame is left in the output buffer.
+                audio.write(&mut self.frame);
+                self.rendered += FRAME as u64;
+                let n = buf.len();
+                buf.copy_from_slice(&self.frame[..n]);
+                self.pending = FRAME_LEN - n;
+                return;
+            }
+            let (head, tail) = core::mem::take(&mut buf).split_at_mut(frames * FRAME_LEN);
+            audio.write(head);
+            self.rendered += (frames * FRAME) as u64;
+            buf = tail;
+        }
+    }
+
+    /// Pass to the nodes all events that are due within the next frame.
+    ///
+    /// Events for nodes that were removed are silently dropped.
+    fn arm_due(&mut self, tree: &AudioTree) {
+        let end = self.rendered + FRAME as u64;
+        let due = self.events.partition_point(|e| e.at < end);
+        for event in self.events.drain(..due) {
+            // Events scheduled in the past are applied at the frame start.
+            let offset = event.at.saturating_sub(self.rendered) as usize;
+            _ = tree.arm(event.node_id, offset, event.action);
+        }
+    }
+
+    /// How many frames can be rendered before the frame with the next event.
+    fn frames_before_next(&self) -> usize {
+        let Some(event) = self.events.first() else {
+            return usize::MAX;
+        };
+        let left = event.at.saturating_sub(self.rendered) / FRAME as u64;
+        usize::try_from(left).unwrap_or(usize::MAX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{NodeKind, ROOT_ID};
+    use alloc::boxed::Box;
+    use firefly_audio::{modulators, Frame, Nodes, Processor, Sample};
+
+    /// Emits the number of the sample since the last reset, scaled down.
+    #[derive(Default)]
+    struct Counter(u16);
+
+    impl Processor for Counter {
+        fn reset(&mut self) {
+            self.0 = 0;
+        }
+
+        fn process_children(&mut self, _cn: &mut Nodes) -> Option<Frame> {
+            let mut s = [0.; FRAME];
+            for val in &mut s {
+                *val = f32::from(self.0) / 1000.;
+                self.0 += 1;
+            }
+            Some(Frame::mono(Sample::new(s)))
+        }
+    }
+
+    /// Emits the param 0 multiplied by the param 1.
+    struct Const(f32, f32);
+
+    impl Processor for Const {
+        fn set(&mut self, param: u8, val: f32) {
+            match param {
+                0 => self.0 = val,
+                _ => self.1 = val,
+            }
+        }
+
+        fn process_children(&mut self, _cn: &mut Nodes) -> Option<Frame> {
+            Some(Frame::mono(Sample::new([self.0 * self.1; FRAME])))
+        }
+    }
+
+    /// Render the given number of samples in uneven chunks, return the left channel.
+    fn play(s: &mut Scheduler, audio: &mut Manager, tree: &AudioTree, samples: usize) -> Vec<i16> {
+        let mut buf = alloc::vec![0i16; samples * CHANNELS];
+        let mut start = 0;
+        for size in [7, 33, 2, 16, 50].into_iter().cycle() {
+            let end = (start + size).min(buf.len());
+            s.render(audio, tree, &mut buf[start..end]);
+            start = end;
+            if start == buf.len() {
+                break;
+            }
+        }
+        buf.into_iter().step_by(CHANNELS).collect()
+    }
+
+    fn to_i16(val: f32) -> i16 {
+        (val * f32::from(i16::MAX)) as i16
+    }
+
+    #[test]
+    fn test_scheduler() {
+        let mut scheduler = Scheduler::default();
+        assert_eq!(scheduler.frames_before_next(), usize::MAX);
+        assert!(scheduler.add(40, 2, Action::Reset));
+        assert!(scheduler.add(10, 1, Action::ResetAll));
+        assert!(scheduler.add(10, 3, Action::Set(1, 0.5)));
+        let order: Vec<_> = scheduler.events.iter().map(|e| e.node_id).collect();
+        assert_eq!(order, [1, 3, 2]);
+        assert_eq!(scheduler.frames_before_next(), 1);
+
+        scheduler.rendered = 8;
+        scheduler.arm_due(&AudioTree::new());
+        assert_eq!(scheduler.events.len(), 1);
+        assert_eq!(scheduler.frames_before_next(), 4);
+
+        scheduler.cancel(2);
+        assert_eq!(scheduler.frames_before_next(), usize::MAX);
+        scheduler.pending = 6;
+        assert_eq!(scheduler.now(), 5);
+    }
+
+    #[test]
+    fn test_reset_exact() {
+        let mut audio = Manager::new();
+        let mut tree = AudioTree::new();
+        let proc = Box::new(Counter::default());
+        let id = tree.add(&mut audio, ROOT_ID, NodeKind::Sine, proc);
+        let id = id.ok().unwrap();
+        let mut scheduler = Scheduler::default();
+        assert!(scheduler.add(5, id, Action::Reset));
+        assert!(scheduler.add(19, id, Action::Reset));
+        let out = play(&mut scheduler, &mut audio, &tree, 40);
+        for (i, s) in out.into_iter().enumerate() {
+            let n = match i {
+                ..5 => i,
+                5..19 => i - 5,
+                _ => i - 19,
+            };
+            assert_eq!(s, to_i16(n as f32 / 1000.), "sample {i}");
+        }
+        assert_eq!(scheduler.now(), 40);
+    }
+
+    #[test]
+    fn test_set_exact() {
+        let mut audio = Manager::new();
+        let mut tree = AudioTree::new();
+        let proc = Box::new(Const(0.25, 1.));
+        let id = tree.add(&mut audio, ROOT_ID, NodeKind::Gain, proc);
+        let id = id.ok().unwrap();
+        let lfo = Box::new(modulators::Hold::new(2., 2., 0));
+        tree.modulate(id, 1, lfo).ok().unwrap();
+        let mut scheduler = Scheduler::default();
+        // The modulator of another param is kept.
+        assert!(scheduler.add(13, id, Action::Set(0, 0.125)));
+        // The modulator of the same param is removed.
+        assert!(scheduler.add(21, id, Action::Set(1, 4.)));
+        let out = play(&mut scheduler, &mut audio, &tree, 800);
+        for (i, s) in out.into_iter().enumerate() {
+            let val = match i {
+                ..13 => 0.5,
+                13..21 => 0.25,
+                _ => 0.5,
+            };
+            assert_eq!(s, to_i16(val), "sample {i}");
+        }
+    }
+}
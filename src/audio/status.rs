@@ -0,0 +1,113 @@
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use core::cell::Cell;
+use firefly_audio::{Frame, Nodes, Processor};
+
+/// The playback status of an audio node, shared between the node and the runtime.
+#[derive(Default)]
+pub(crate) struct NodeStatus {
+    /// How many samples the node produced since the last reset.
+    pub played: Cell<u32>,
+    /// True if the node has nothing more to play.
+    pub exhausted: Cell<bool>,
+    /// True if the app should be notified when the node is exhausted.
+    pub notify: Cell<bool>,
+    /// True if the app was already notified about the node being exhausted.
+    pub notified: Cell<bool>,
+}
+
+impl NodeStatus {
+    /// Check if the app should be notified about the node being exhausted.
+    ///
+    /// Returns true only once until the node is reset.
+    pub fn take_event(&self) -> bool {
+        if self.notify.get() && self.exhausted.get() && !self.notified.get() {
+            self.notified.set(true);
+            return true;
+        }
+        false
+    }
+}
+
+/// A wrapper for audio processors tracking their playback status.
+///
+/// Only sources that play a file are wrapped, see [`NodeKind::is_tracked`].
+///
+/// [`NodeKind::is_tracked`]: super::NodeKind::is_tracked
+pub(crate) struct Tracked {
+    inner: Box<dyn Processor>,
+    status: Rc<NodeStatus>,
+}
+
+impl Tracked {
+    pub fn new(inner: Box<dyn Processor>, status: Rc<NodeStatus>) -> Self {
+        Self { inner, status }
+    }
+}
+
+impl Processor for Tracked {
+    fn set(&mut self, param: u8, val: f32) {
+        self.inner.set(param, val);
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset();
+        self.status.played.set(0);
+        self.status.exhausted.set(false);
+        self.status.notified.set(false);
+    }
+
+    fn process_children(&mut self, cn: &mut Nodes) -> Option<Frame> {
+        let frame = self.inner.process_children(cn);
+        if frame.is_some() {
+            let played = self.status.played.get();
+            self.status.played.set(played.saturating_add(8));
+        } else {
+            self.status.exhausted.set(true);
+        }
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use firefly_audio::Sample;
+
+    /// Plays two frames of silence.
+    struct Short {
+        left: u8,
+    }
+
+    impl Processor for Short {
+        fn reset(&mut self) {
+            self.left = 2;
+        }
+
+        fn process_children(&mut self, _cn: &mut Nodes) -> Option<Frame> {
+            self.left = self.left.checked_sub(1)?;
+            Some(Frame::mono(Sample::new([0.; 8])))
+        }
+    }
+
+    #[test]
+    fn test_tracked() {
+        let mut cn = Nodes::new();
+        let status = Rc::new(NodeStatus::default());
+        status.notify.set(true);
+        let mut node = Tracked::new(Box::new(Short { left: 2 }), status.clone());
+        assert!(node.process_children(&mut cn).is_some());
+        assert!(node.process_children(&mut cn).is_some());
+        assert_eq!(status.played.get(), 16);
+        assert!(!status.take_event());
+        assert!(node.process_children(&mut cn).is_none());
+        assert!(status.exhausted.get());
+        assert!(status.take_event());
+        assert!(!status.take_event());
+
+        node.reset();
+        assert_eq!(status.played.get(), 0);
+        assert!(!status.exhausted.get());
+        assert!(node.process_children(&mut cn).is_some());
+    }
+}
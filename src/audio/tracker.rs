@@ -0,0 +1,501 @@
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::Cell;
+use core::fmt;
+use firefly_audio::{Frame, Nodes, Processor, Sample};
+
+const SAMPLE_RATE: u32 = 44_100;
+/// Half of the PAL Amiga clock. Divided by a note period, gives the sample rate of the note.
+const PAL_CLOCK: f32 = 3_546_894.6;
+const ROWS: usize = 64;
+const MAX_SAMPLES: usize = 31;
+/// The offset of the song length, followed by the restart position and the order table.
+const ORDERS_OFFSET: usize = 950;
+const SIGNATURE_OFFSET: usize = 1080;
+const PATTERNS_OFFSET: usize = 1084;
+const DEFAULT_SPEED: u8 = 6;
+const DEFAULT_TEMPO: u8 = 125;
+
+/// Frequency ratios for 0-15 semitones, used by the arpeggio effect.
+const SEMITONES: [f32; 16] = [
+    1.0,
+    1.059_463,
+    1.122_462,
+    1.189_207,
+    1.259_921,
+    1.334_84,
+    core::f32::consts::SQRT_2,
+    1.498_307,
+    1.587_401,
+    1.681_793,
+    1.781_797,
+    1.887_749,
+    2.0,
+    2.118_926,
+    2.244_924,
+    2.378_414,
+];
+
+pub(crate) enum TrackerError {
+    TooSmall,
+    Signature,
+}
+
+impl fmt::Display for TrackerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooSmall => write!(f, "the module file is truncated"),
+            Self::Signature => write!(
+                f,
+                "unsupported module format, only 31-sample MOD is supported"
+            ),
+        }
+    }
+}
+
+/// Commands sent from host functions to a playing [`Tracker`].
+///
+/// The tracker itself is owned by the audio manager,
+/// so the runtime keeps a shared handle to control it.
+#[derive(Default)]
+pub(crate) struct TrackerControl {
+    /// The position in the order table to jump to on the next tick.
+    pub jump: Cell<Option<u8>>,
+    /// The new tempo (in BPM) to apply on the next tick.
+    pub tempo: Cell<Option<u8>>,
+    /// Bit mask of muted channels.
+    pub muted: Cell<u32>,
+    /// The currently played position in the order table.
+    pub order: Cell<u8>,
+    /// The currently played row of the pattern.
+    pub row: Cell<u8>,
+}
+
+struct Instrument {
+    data: Vec<i8>,
+    volume: u8,
+    /// Finetune in 1/8 of a semitone, from -8 to 7.
+    finetune: i8,
+    loop_start: usize,
+    loop_len: usize,
+}
+
+#[derive(Clone, Copy)]
+struct Note {
+    instrument: u8,
+    period: u16,
+    effect: u8,
+    param: u8,
+}
+
+#[derive(Default)]
+struct Channel {
+    instrument: Option<usize>,
+    /// The position in the instrument sample data.
+    pos: f32,
+    period: u16,
+    /// The target period for tone portamento.
+    porta_target: u16,
+    porta_speed: u8,
+    volume: u8,
+    effect: u8,
+    param: u8,
+    /// The frequency multiplier set by arpeggio.
+    arpeggio: f32,
+    playing: bool,
+}
+
+/// ProTracker MOD module player.
+///
+/// Supports the most common effects: arpeggio, portamento, tone portamento,
+/// sample offset, volume slide, position jump, set volume, pattern break,
+/// and set speed/tempo.
+pub(crate) struct Tracker {
+    instruments: Vec<Instrument>,
+    orders: Vec<u8>,
+    restart: usize,
+    patterns: Vec<Note>,
+    n_channels: usize,
+    channels: Vec<Channel>,
+    order: usize,
+    row: usize,
+    tick: u8,
+    speed: u8,
+    /// How many samples are left until the next tick.
+    tick_left: u32,
+    samples_per_tick: u32,
+    /// The row to start the next pattern from, set by pattern break or position jump.
+    next_row: Option<usize>,
+    /// The order to play next, set by position jump.
+    next_order: Option<usize>,
+    control: Rc<TrackerControl>,
+}
+
+impl Tracker {
+    pub fn from_bytes(raw: &[u8], control: Rc<TrackerControl>) -> Result<Self, TrackerError> {
+        if raw.len() < PATTERNS_OFFSET {
+            return Err(TrackerError::TooSmall);
+        }
+        let n_channels = parse_channels(&raw[SIGNATURE_OFFSET..PATTERNS_OFFSET])?;
+        let song_len = usize::from(raw[ORDERS_OFFSET]).clamp(1, 128);
+        let restart = usize::from(raw[ORDERS_OFFSET + 1]);
+        let all_orders = &raw[(ORDERS_OFFSET + 2)..(ORDERS_OFFSET + 2 + 128)];
+        let n_patterns = usize::from(*all_orders.iter().max().unwrap_or(&0)) + 1;
+        let orders = all_orders[..song_len].to_vec();
+
+        let patterns_size = n_patterns * ROWS * n_channels * 4;
+        let Some(raw_patterns) = raw.get(PATTERNS_OFFSET..(PATTERNS_OFFSET + patterns_size)) else {
+            return Err(TrackerError::TooSmall);
+        };
+        let patterns = raw_patterns
+            .chunks_exact(4)
+            .map(|b| Note {
+                instrument: (b[0] & 0xf0) | (b[2] >> 4),
+                period: (u16::from(b[0] & 0x0f) << 8) | u16::from(b[1]),
+                effect: b[2] & 0x0f,
+                param: b[3],
+            })
+            .collect();
+
+        let mut instruments = Vec::with_capacity(MAX_SAMPLES);
+        let mut data_offset = PATTERNS_OFFSET + patterns_size;
+        for i in 0..MAX_SAMPLES {
+            let header = &raw[(20 + i * 30)..(20 + (i + 1) * 30)];
+            let len = usize::from(u16::from_be_bytes([header[22], header[23]])) * 2;
+            let finetune = ((header[24] & 0x0f) << 4) as i8 >> 4;
+            let volume = header[25].min(64);
+            let loop_start = usize::from(u16::from_be_bytes([header[26], header[27]])) * 2;
+            let loop_len = usize::from(u16::from_be_bytes([header[28], header[29]])) * 2;
+            let end = (data_offset + len).min(raw.len());
+            let data = raw
+                .get(data_offset..end)
+                .unwrap_or_default()
+                .iter()
+                .map(|b| *b as i8)
+                .collect();
+            data_offset += len;
+            instruments.push(Instrument {
+                data,
+                volume,
+                finetune,
+                loop_start,
+                loop_len: if loop_len > 2 { loop_len } else { 0 },
+            });
+        }
+
+        let mut channels = Vec::with_capacity(n_channels);
+        channels.resize_with(n_channels, Channel::default);
+        let mut tracker = Self {
+            instruments,
+            orders,
+            restart,
+            patterns,
+            n_channels,
+            channels,
+            order: 0,
+            row: 0,
+            tick: 0,
+            speed: DEFAULT_SPEED,
+            tick_left: 0,
+            samples_per_tick: 0,
+            next_row: None,
+            next_order: None,
+            control,
+        };
+        tracker.set_tempo(DEFAULT_TEMPO);
+        Ok(tracker)
+    }
+
+    fn set_tempo(&mut self, tempo: u8) {
+        // A tick lasts 2.5/tempo seconds.
+        let tempo = u32::from(tempo.max(32));
+        self.samples_per_tick = SAMPLE_RATE * 5 / (tempo * 2);
+    }
+
+    fn note(&self, order: usize, row: usize, channel: usize) -> Note {
+        let pattern = usize::from(self.orders[order]);
+        let idx = (pattern * ROWS + row) * self.n_channels + channel;
+        self.patterns[idx]
+    }
+
+    /// Apply commands from the host.
+    fn apply_control(&mut self) {
+        if let Some(order) = self.control.jump.take() {
+            self.order = usize::from(order) % self.orders.len();
+            self.row = 0;
+            self.tick = 0;
+        }
+        if let Some(tempo) = self.control.tempo.take() {
+            self.set_tempo(tempo);
+        }
+    }
+
+    /// Process a single tick: read a new row or apply running effects.
+    fn process_tick(&mut self) {
+        self.apply_control();
+        if self.tick == 0 {
+            self.control.order.set(self.order as u8);
+            self.control.row.set(self.row as u8);
+            for i in 0..self.n_channels {
+                let note = self.note(self.order, self.row, i);
+                self.start_note(i, note);
+            }
+        } else {
+            for i in 0..self.n_channels {
+                self.update_effect(i);
+            }
+        }
+        self.tick += 1;
+        if self.tick >= self.speed {
+            self.tick = 0;
+            self.next_position();
+        }
+    }
+
+    fn start_note(&mut self, i: usize, note: Note) {
+        let ch = &mut self.channels[i];
+        ch.effect = note.effect;
+        ch.param = note.param;
+        ch.arpeggio = 1.;
+        if note.instrument != 0 {
+            let idx = usize::from(note.instrument - 1);
+            if let Some(instrument) = self.instruments.get(idx) {
+                ch.instrument = Some(idx);
+                ch.volume = instrument.volume;
+            }
+        }
+        if note.period != 0 {
+            if note.effect == 0x3 {
+                ch.porta_target = note.period;
+            } else {
+                ch.period = note.period;
+                ch.pos = 0.;
+                ch.playing = true;
+            }
+        }
+        match note.effect {
+            0x3 if note.param != 0 => ch.porta_speed = note.param,
+            0x9 => ch.pos = f32::from(note.param) * 256.,
+            0xB => {
+                self.next_order = Some(usize::from(note.param));
+                self.next_row.get_or_insert(0);
+            }
+            0xC => ch.volume = note.param.min(64),
+            0xD => {
+                let row = usize::from(note.param >> 4) * 10 + usize::from(note.param & 0x0f);
+                self.next_row = Some(row.min(ROWS - 1));
+            }
+            0xF if note.param != 0 => {
+                if note.param < 32 {
+                    self.speed = note.param;
+                } else {
+                    self.set_tempo(note.param);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn update_effect(&mut self, i: usize) {
+        let tick = self.tick;
+        let ch = &mut self.channels[i];
+        let param = ch.param;
+        match ch.effect {
+            0x0 if param != 0 => {
+                let semitones = match tick % 3 {
+                    0 => 0,
+                    1 => param >> 4,
+                    _ => param & 0x0f,
+                };
+                ch.arpeggio = SEMITONES[usize::from(semitones)];
+            }
+            0x1 => ch.period = ch.period.saturating_sub(u16::from(param)).max(113),
+            0x2 => ch.period = ch.period.saturating_add(u16::from(param)).min(856),
+            0x3 => {
+                let speed = u16::from(ch.porta_speed);
+                if ch.period < ch.porta_target {
+                    ch.period = (ch.period + speed).min(ch.porta_target);
+                } else if ch.period > ch.porta_target {
+                    ch.period = ch.period.saturating_sub(speed).max(ch.porta_target);
+                }
+            }
+            0xA => {
+                let up = param >> 4;
+                let down = param & 0x0f;
+                ch.volume = if up != 0 {
+                    (ch.volume + up).min(64)
+                } else {
+                    ch.volume.saturating_sub(down)
+                };
+            }
+            _ => {}
+        }
+    }
+
+    /// Move to the next row, respecting pattern breaks and position jumps.
+    fn next_position(&mut self) {
+        let next_row = self.next_row.take();
+        let next_order = self.next_order.take();
+        if next_row.is_none() && self.row + 1 < ROWS {
+            self.row += 1;
+            return;
+        }
+        self.row = next_row.unwrap_or(0);
+        self.order = match next_order {
+            Some(order) => order,
+            None => self.order + 1,
+        };
+        if self.order >= self.orders.len() {
+            self.order = self.restart;
+        }
+        if self.order >= self.orders.len() {
+            self.order = 0;
+        }
+    }
+
+    /// Mix a single sample from all channels.
+    fn next_sample(&mut self) -> (f32, f32) {
+        let muted = self.control.muted.get();
+        let mut left = 0.;
+        let mut right = 0.;
+        for (ch, i) in self.channels.iter_mut().zip(0..) {
+            if !ch.playing || ch.period == 0 {
+                continue;
+            }
+            let Some(instrument) = ch.instrument.and_then(|idx| self.instruments.get(idx)) else {
+                continue;
+            };
+            let pos = ch.pos as usize;
+            if instrument.loop_len != 0 && pos >= instrument.loop_start + instrument.loop_len {
+                ch.pos -= instrument.loop_len as f32;
+            } else if pos >= instrument.data.len() {
+                ch.playing = false;
+                continue;
+            }
+            let Some(raw) = instrument.data.get(ch.pos as usize) else {
+                ch.playing = false;
+                continue;
+            };
+            let freq = PAL_CLOCK / f32::from(ch.period) * ch.arpeggio;
+            let freq = freq * finetune_ratio(instrument.finetune);
+            ch.pos += freq / SAMPLE_RATE as f32;
+            if muted & (1 << i) != 0 {
+                continue;
+            }
+            let val = f32::from(*raw) / 128. * f32::from(ch.volume) / 64.;
+            // Amiga panning: channels go left, right, right, left.
+            if matches!(i % 4, 0 | 3) {
+                left += val * 0.75;
+                right += val * 0.25;
+            } else {
+                left += val * 0.25;
+                right += val * 0.75;
+            }
+        }
+        let scale = 2. / self.n_channels as f32;
+        (left * scale, right * scale)
+    }
+}
+
+impl Processor for Tracker {
+    fn reset(&mut self) {
+        self.order = 0;
+        self.row = 0;
+        self.tick = 0;
+        self.tick_left = 0;
+        self.speed = DEFAULT_SPEED;
+        self.set_tempo(DEFAULT_TEMPO);
+        self.next_row = None;
+        self.next_order = None;
+        for ch in &mut self.channels {
+            *ch = Channel::default();
+        }
+    }
+
+    fn process_children(&mut self, _cn: &mut Nodes) -> Option<Frame> {
+        let mut left = [0f32; 8];
+        let mut right = [0f32; 8];
+        for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+            if self.tick_left == 0 {
+                self.process_tick();
+                self.tick_left = self.samples_per_tick;
+            }
+            self.tick_left -= 1;
+            (*l, *r) = self.next_sample();
+        }
+        Some(Frame::stereo(Sample::new(left), Sample::new(right)))
+    }
+}
+
+/// Detect the number of channels from the module signature.
+fn parse_channels(sig: &[u8]) -> Result<usize, TrackerError> {
+    match sig {
+        b"M.K." | b"M!K!" | b"FLT4" | b"4CHN" => Ok(4),
+        b"6CHN" => Ok(6),
+        b"8CHN" | b"OCTA" | b"FLT8" => Ok(8),
+        [a, b, b'C', b'H'] if a.is_ascii_digit() && b.is_ascii_digit() => {
+            let n = usize::from(a - b'0') * 10 + usize::from(b - b'0');
+            if n == 0 || n > 32 {
+                return Err(TrackerError::Signature);
+            }
+            Ok(n)
+        }
+        _ => Err(TrackerError::Signature),
+    }
+}
+
+/// The frequency ratio for the instrument finetune (in 1/8 of a semitone).
+fn finetune_ratio(finetune: i8) -> f32 {
+    if finetune == 0 {
+        return 1.;
+    }
+    let power = f32::from(finetune) / 96. * core::f32::consts::LN_2;
+    micromath::F32::from(power).exp().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_channels() {
+        assert_eq!(parse_channels(b"M.K.").ok(), Some(4));
+        assert_eq!(parse_channels(b"8CHN").ok(), Some(8));
+        assert_eq!(parse_channels(b"12CH").ok(), Some(12));
+        assert!(parse_channels(b"00CH").is_err());
+        assert!(parse_channels(b"\0\0\0\0").is_err());
+    }
+
+    #[test]
+    fn test_tracker() {
+        // One pattern, one sample, a single note in the first channel.
+        let mut raw = alloc::vec![0u8; PATTERNS_OFFSET + ROWS * 4 * 4 + 4];
+        raw[20 + 23] = 2; // sample length: 2 words
+        raw[20 + 25] = 64; // volume
+        raw[ORDERS_OFFSET] = 1;
+        raw[SIGNATURE_OFFSET..PATTERNS_OFFSET].copy_from_slice(b"M.K.");
+        // instrument 1, period 428 (C-2), effect F: speed 3
+        raw[PATTERNS_OFFSET..PATTERNS_OFFSET + 4].copy_from_slice(&[0x01, 0xac, 0x1f, 0x03]);
+        let data_offset = PATTERNS_OFFSET + ROWS * 4 * 4;
+        raw[data_offset..].copy_from_slice(&[64, 64, 64, 64]);
+
+        let control = Rc::new(TrackerControl::default());
+        let mut tracker = Tracker::from_bytes(&raw, control.clone()).ok().unwrap();
+        let (left, right) = {
+            tracker.process_tick();
+            tracker.next_sample()
+        };
+        assert_eq!(tracker.speed, 3);
+        assert!(left > right);
+        assert!(left > 0.);
+
+        control.muted.set(0b1);
+        let (left, right) = tracker.next_sample();
+        assert_eq!((left, right), (0., 0.));
+
+        control.jump.set(Some(0));
+        tracker.process_tick();
+        assert_eq!(tracker.row, 0);
+        assert_eq!(control.order.get(), 0);
+    }
+}
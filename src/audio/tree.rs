@@ -0,0 +1,614 @@
+use super::{Action, FRAME};
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use firefly_audio::modulators::Modulator;
+use firefly_audio::{Frame, Manager, NodeError, Nodes, Processor, Sample, SAMPLE_RATE};
+
+/// The ID of the root node of the audio graph.
+pub(crate) const ROOT_ID: u32 = 0;
+
+/// How often (in samples) the modulator updates the node param.
+const MODULATE_EVERY: u32 = SAMPLE_RATE / 60;
+
+/// The type of an audio node as exposed to the apps.
+///
+/// The numeric values are part of the public API, don't change them.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum NodeKind {
+    Root = 0,
+    Empty = 1,
+    Zero = 2,
+    File = 3,
+    Sine = 4,
+    Square = 5,
+    Sawtooth = 6,
+    Triangle = 7,
+    Noise = 8,
+    Mix = 9,
+    AllForOne = 10,
+    Gain = 11,
+    Loop = 12,
+    Concat = 13,
+    Pan = 14,
+    Mute = 15,
+    Pause = 16,
+    TrackPosition = 17,
+    LowPass = 18,
+    HighPass = 19,
+    TakeLeft = 20,
+    TakeRight = 21,
+    Swap = 22,
+    Clip = 23,
+    Adpcm = 24,
+    Tracker = 25,
+    Delay = 26,
+    Reverb = 27,
+    Bitcrusher = 28,
+    Compressor = 29,
+}
+
+impl NodeKind {
+    /// Check if the playback status of the node is tracked.
+    ///
+    /// Only sources playing a file can be exhausted or have a meaningful position,
+    /// so only they pay the cost of counting produced samples.
+    pub fn is_tracked(self) -> bool {
+        matches!(self, Self::File | Self::Adpcm | Self::Tracker)
+    }
+}
+
+struct NodeInfo {
+    parent: u32,
+    kind: NodeKind,
+    /// The position of the node among its siblings. Bigger is later.
+    seq: u32,
+    /// The ID of the node in the audio manager.
+    manager_id: u32,
+    /// The node state shared with the audio manager. None for the root.
+    shared: Option<Rc<RefCell<Shared>>>,
+}
+
+/// The state of an audio node.
+///
+/// The manager owns the processors it renders and cannot give them back.
+/// So, the manager gets only a [`Proxy`] and the state itself is shared
+/// with the tree. This way, the graph can be rebuilt without losing
+/// the state of the nodes.
+struct Shared {
+    proc: Box<dyn Processor>,
+    modulator: Option<WiredModulator>,
+    /// Scheduled events to apply within the next frame, sorted by the offset.
+    events: Vec<Armed>,
+    /// Samples produced by the processor but not emitted yet.
+    ///
+    /// When an event is applied in the middle of a frame, the rest of the frame
+    /// is taken from the start of the next processor frame. The processor output
+    /// is then shifted relative to the node output, and the leftover samples
+    /// of each processor frame are emitted at the start of the next node frame.
+    lag: Chunk,
+}
+
+impl Shared {
+    /// Render the next frame of the processor, updating the modulated param.
+    fn render(&mut self, cn: &mut Nodes) -> Option<Frame> {
+        if let Some(wired) = &mut self.modulator {
+            // Each frame is 8 samples, so exactly one frame in each period
+            // starts within the first 8 samples of the period.
+            if wired.time % MODULATE_EVERY < 8 {
+                let val = wired.modulator.get(wired.time);
+                self.proc.set(wired.param, val);
+            }
+            wired.time = wired.time.wrapping_add(8);
+        }
+        self.proc.process_children(cn)
+    }
+
+    /// Render the next frame, applying the scheduled events at their exact samples.
+    ///
+    /// The processor can render only whole frames. So, the samples before
+    /// an event are taken from a frame rendered with the old state,
+    /// and the rest of that frame is dropped.
+    fn render_split(&mut self, cn: &mut Nodes) -> Option<Frame> {
+        let mut out = core::mem::take(&mut self.lag);
+        for event in core::mem::take(&mut self.events) {
+            if event.offset > out.len {
+                let len = event.offset - out.len;
+                match self.render(cn) {
+                    Some(frame) => out.push(&frame, 0, len),
+                    None => out.pad(len),
+                }
+            } else {
+                out.len = event.offset;
+            }
+            self.apply(event);
+        }
+        let Some(frame) = self.render(cn) else {
+            if out.len == 0 {
+                return None;
+            }
+            out.pad(FRAME - out.len);
+            return Some(out.frame());
+        };
+        let split = FRAME - out.len;
+        out.push(&frame, 0, split);
+        self.lag.push(&frame, split, FRAME);
+        Some(out.frame())
+    }
+
+    fn apply(&mut self, event: Armed) {
+        match event.action {
+            Action::Reset => self.reset(),
+            Action::ResetAll => {
+                self.reset();
+                for shared in &event.descendants {
+                    shared.borrow_mut().reset();
+                }
+            }
+            Action::Set(param, val) => self.set(param, val),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.proc.reset();
+        self.lag = Chunk::default();
+    }
+
+    /// Set the param, removing the modulator if it modulates the same param.
+    fn set(&mut self, param: u8, val: f32) {
+        self.proc.set(param, val);
+        if self.modulator.as_ref().is_some_and(|m| m.param == param) {
+            self.modulator = None;
+        }
+    }
+}
+
+/// A scheduled event passed to the node shortly before it's due.
+struct Armed {
+    /// The sample in the next frame at which the event should be applied.
+    offset: usize,
+    action: Action,
+    /// For [`Action::ResetAll`], the state of all descendants of the node.
+    descendants: Vec<Rc<RefCell<Shared>>>,
+}
+
+/// Up to one frame worth of samples.
+#[derive(Default)]
+struct Chunk {
+    left: [f32; FRAME],
+    right: [f32; FRAME],
+    stereo: bool,
+    len: usize,
+}
+
+impl Chunk {
+    /// Append the samples of the frame from the given range.
+    fn push(&mut self, frame: &Frame, start: usize, end: usize) {
+        let left = frame.left.to_array();
+        let right = frame.right.map_or(left, |r| r.to_array());
+        for i in start..end {
+            self.left[self.len] = left[i];
+            self.right[self.len] = right[i];
+            self.len += 1;
+        }
+        self.stereo |= frame.right.is_some();
+    }
+
+    /// Append the given number of silent samples.
+    fn pad(&mut self, len: usize) {
+        let end = self.len + len;
+        self.left[self.len..end].fill(0.);
+        self.right[self.len..end].fill(0.);
+        self.len = end;
+    }
+
+    fn frame(&self) -> Frame {
+        debug_assert_eq!(self.len, FRAME);
+        let left = Sample::new(self.left);
+        if self.stereo {
+            Frame::stereo(left, Sample::new(self.right))
+        } else {
+            Frame::mono(left)
+        }
+    }
+}
+
+/// A modulator connected to a parameter of a node.
+struct WiredModulator {
+    param: u8,
+    modulator: Box<dyn Modulator>,
+    /// How many samples the node produced since the modulator was connected.
+    time: u32,
+}
+
+/// The processor added into the audio manager in place of the real one.
+struct Proxy(Rc<RefCell<Shared>>);
+
+impl Processor for Proxy {
+    fn set(&mut self, param: u8, val: f32) {
+        self.0.borrow_mut().proc.set(param, val);
+    }
+
+    fn reset(&mut self) {
+        self.0.borrow_mut().reset();
+    }
+
+    fn process_children(&mut self, cn: &mut Nodes) -> Option<Frame> {
+        let mut shared = self.0.borrow_mut();
+        if shared.events.is_empty() && shared.lag.len == 0 {
+            return shared.render(cn);
+        }
+        shared.render_split(cn)
+    }
+}
+
+/// The structure of the audio graph.
+///
+/// The audio manager knows how to render the graph but not how it looks,
+/// and it can only add nodes or remove all children of a node. So, we track
+/// the parent, the type, and the state of every node on our side and rebuild
+/// the graph in the manager when a node is removed or moved.
+///
+/// The ID of a node is its position in the list of all nodes. When nodes
+/// are removed, the nodes added after them get shifted down and so get new IDs.
+pub(crate) struct AudioTree {
+    /// All nodes indexed by their ID, including the root.
+    nodes: Vec<NodeInfo>,
+    /// The position for the next node added to a parent.
+    next_seq: u32,
+}
+
+impl AudioTree {
+    pub fn new() -> Self {
+        let root = NodeInfo {
+            parent: ROOT_ID,
+            kind: NodeKind::Root,
+            seq: 0,
+            manager_id: ROOT_ID,
+            shared: None,
+        };
+        Self {
+            nodes: vec![root],
+            next_seq: 1,
+        }
+    }
+
+    /// Add a new node as the last child of the given parent. Returns the new node ID.
+    pub fn add(
+        &mut self,
+        audio: &mut Manager,
+        parent: u32,
+        kind: NodeKind,
+        proc: Box<dyn Processor>,
+    ) -> Result<u32, NodeError> {
+        let parent_node = self.node(parent)?;
+        let shared = Rc::new(RefCell::new(Shared {
+            proc,
+            modulator: None,
+            events: Vec::new(),
+            lag: Chunk::default(),
+        }));
+        let proxy = Box::new(Proxy(shared.clone()));
+        let manager_id = audio.add_node(parent_node.manager_id, proxy)?;
+        let id = self.nodes.len() as u32;
+        self.nodes.push(NodeInfo {
+            parent,
+            kind,
+            seq: self.next_seq,
+            manager_id,
+            shared: Some(shared),
+        });
+        self.next_seq += 1;
+        Ok(id)
+    }
+
+    /// Get the type of the node. None if there is no such node.
+    pub fn kind(&self, id: u32) -> Option<NodeKind> {
+        let node = self.nodes.get(id as usize)?;
+        Some(node.kind)
+    }
+
+    /// Get IDs of direct children of the node, in the order they are played.
+    pub fn children(&self, id: u32) -> Vec<u32> {
+        let mut children: Vec<_> = self
+            .nodes
+            .iter()
+            .zip(0..)
+            .skip(1)
+            .filter(|(n, _)| n.parent == id)
+            .collect();
+        children.sort_by_key(|(n, _)| n.seq);
+        children.into_iter().map(|(_, id)| id).collect()
+    }
+
+    /// Reset the state of the node.
+    pub fn reset(&self, id: u32) -> Result<(), NodeError> {
+        if let Some(shared) = &self.node(id)?.shared {
+            shared.borrow_mut().reset();
+        }
+        Ok(())
+    }
+
+    /// Reset the state of the node and all its descendants.
+    pub fn reset_all(&self, id: u32) -> Result<(), NodeError> {
+        self.node(id)?;
+        for (node, node_id) in self.nodes.iter().zip(0..) {
+            if let Some(shared) = &node.shared {
+                if self.is_within(node_id, id) {
+                    shared.borrow_mut().reset();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the modulator for the node param, replacing the current one.
+    ///
+    /// The root node mixes its children and has no params, so it's not modulated.
+    pub fn modulate(
+        &self,
+        id: u32,
+        param: u8,
+        modulator: Box<dyn Modulator>,
+    ) -> Result<(), NodeError> {
+        if let Some(shared) = &self.node(id)?.shared {
+            shared.borrow_mut().modulator = Some(WiredModulator {
+                param,
+                modulator,
+                time: 0,
+            });
+        }
+        Ok(())
+    }
+
+    /// Apply the action at the given sample of the next rendered frame.
+    ///
+    /// The root node has no state, so for it only [`Action::ResetAll`]
+    /// does anything, resetting all nodes.
+    pub fn arm(&self, id: u32, offset: usize, action: Action) -> Result<(), NodeError> {
+        let Some(shared) = &self.node(id)?.shared else {
+            if action == Action::ResetAll {
+                for child in self.children(id) {
+                    self.arm(child, offset, action)?;
+                }
+            }
+            return Ok(());
+        };
+        let mut descendants = Vec::new();
+        if action == Action::ResetAll {
+            for (node, node_id) in self.nodes.iter().zip(0..) {
+                if let Some(shared) = &node.shared {
+                    if node_id != id && self.is_within(node_id, id) {
+                        descendants.push(shared.clone());
+                    }
+                }
+            }
+        }
+        shared.borrow_mut().events.push(Armed {
+            offset,
+            action,
+            descendants,
+        });
+        Ok(())
+    }
+
+    /// Remove all descendants of the node.
+    ///
+    /// Returns the new ID for every old ID, None for the removed nodes.
+    pub fn clear(&mut self, audio: &mut Manager, id: u32) -> Result<Vec<Option<u32>>, NodeError> {
+        self.node(id)?;
+        let new_ids = self.retain(|tree, node_id| node_id == id || !tree.is_within(node_id, id));
+        self.rebuild(audio)?;
+        Ok(new_ids)
+    }
+
+    /// Remove the node and all its descendants.
+    ///
+    /// Returns the new ID for every old ID, None for the removed nodes.
+    pub fn remove(&mut self, audio: &mut Manager, id: u32) -> Result<Vec<Option<u32>>, NodeError> {
+        self.node(id)?;
+        let new_ids = self.retain(|tree, node_id| !tree.is_within(node_id, id));
+        self.rebuild(audio)?;
+        Ok(new_ids)
+    }
+
+    /// Detach the node from its parent and attach it as the last child of the new parent.
+    ///
+    /// The node keeps its ID, state, modulator, and children.
+    /// The caller must make sure that the new parent is not within the node.
+    pub fn move_node(
+        &mut self,
+        audio: &mut Manager,
+        id: u32,
+        parent: u32,
+    ) -> Result<(), NodeError> {
+        self.node(id)?;
+        self.node(parent)?;
+        debug_assert!(!self.is_within(parent, id));
+        // Check it here because the graph can't be restored if rebuilding fails.
+        let siblings = self.children(parent);
+        if !siblings.contains(&id) && siblings.len() >= 4 {
+            return Err(NodeError::TooManyChildren);
+        }
+        let node = &mut self.nodes[id as usize];
+        node.parent = parent;
+        node.seq = self.next_seq;
+        self.next_seq += 1;
+        self.rebuild(audio)
+    }
+
+    /// Check if the node is the given ancestor or one of its descendants.
+    pub fn is_within(&self, id: u32, ancestor: u32) -> bool {
+        let mut current = id;
+        loop {
+            if current == ancestor {
+                return true;
+            }
+            if current == ROOT_ID {
+                return false;
+            }
+            let Some(node) = self.nodes.get(current as usize) else {
+                return false;
+            };
+            current = node.parent;
+        }
+    }
+
+    fn node(&self, id: u32) -> Result<&NodeInfo, NodeError> {
+        match self.nodes.get(id as usize) {
+            Some(node) => Ok(node),
+            None => Err(NodeError::UnknownID(id)),
+        }
+    }
+
+    /// Keep only the nodes matching the predicate, shifting IDs of the nodes after.
+    ///
+    /// Returns the new ID for every old ID, None for the removed nodes.
+    /// The predicate must keep parents of all kept nodes.
+    fn retain(&mut self, keep: impl Fn(&Self, u32) -> bool) -> Vec<Option<u32>> {
+        let mut new_ids = Vec::with_capacity(self.nodes.len());
+        let mut next_id = 0;
+        for old_id in 0..self.nodes.len() as u32 {
+            if keep(self, old_id) {
+                new_ids.push(Some(next_id));
+                next_id += 1;
+            } else {
+                new_ids.push(None);
+            }
+        }
+        let mut old_id = 0;
+        self.nodes.retain(|_| {
+            let keep = new_ids[old_id].is_some();
+            old_id += 1;
+            keep
+        });
+        for node in &mut self.nodes {
+            node.parent = new_ids[node.parent as usize].unwrap_or(ROOT_ID);
+        }
+        new_ids
+    }
+
+    /// Add all nodes into the manager again, from scratch.
+    ///
+    /// The state of nodes is shared, so the playback continues where it was.
+    fn rebuild(&mut self, audio: &mut Manager) -> Result<(), NodeError> {
+        audio.clear(ROOT_ID)?;
+        self.attach_children(audio, ROOT_ID)
+    }
+
+    fn attach_children(&mut self, audio: &mut Manager, id: u32) -> Result<(), NodeError> {
+        let parent_id = self.nodes[id as usize].manager_id;
+        for child_id in self.children(id) {
+            let node = &mut self.nodes[child_id as usize];
+            if let Some(shared) = &node.shared {
+                let proxy = Box::new(Proxy(shared.clone()));
+                node.manager_id = audio.add_node(parent_id, proxy)?;
+            }
+            self.attach_children(audio, child_id)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use firefly_audio::{Gain, Mix, Mute, Sine};
+
+    fn add(tree: &mut AudioTree, audio: &mut Manager, parent: u32, kind: NodeKind) -> u32 {
+        let proc: Box<dyn Processor> = match kind {
+            NodeKind::Sine => Box::new(Sine::new(440., 0.)),
+            NodeKind::Gain => Box::new(Gain::new(1.)),
+            NodeKind::Mute => Box::new(Mute::new()),
+            _ => Box::new(Mix::new()),
+        };
+        tree.add(audio, parent, kind, proc).ok().unwrap()
+    }
+
+    /// Render a few frames and check if there is any sound.
+    fn is_silent(audio: &mut Manager) -> bool {
+        let mut buf = [0i16; 64];
+        audio.write(&mut buf);
+        buf.iter().all(|s| *s == 0)
+    }
+
+    #[test]
+    fn test_audio_tree() {
+        let mut audio = Manager::new();
+        let mut tree = AudioTree::new();
+        let a = &mut audio;
+        assert_eq!(add(&mut tree, a, ROOT_ID, NodeKind::Mix), 1);
+        assert_eq!(add(&mut tree, a, 1, NodeKind::Gain), 2);
+        assert_eq!(add(&mut tree, a, 2, NodeKind::Sine), 3);
+        assert_eq!(add(&mut tree, a, ROOT_ID, NodeKind::Gain), 4);
+        assert_eq!(add(&mut tree, a, 4, NodeKind::Sine), 5);
+        assert_eq!(tree.kind(ROOT_ID), Some(NodeKind::Root));
+        assert_eq!(tree.kind(3), Some(NodeKind::Sine));
+        assert_eq!(tree.kind(6), None);
+        assert_eq!(tree.children(ROOT_ID), [1, 4]);
+
+        // The nodes after the cleared ones are shifted.
+        let new_ids = tree.clear(a, 1).ok().unwrap();
+        assert_eq!(new_ids, [Some(0), Some(1), None, None, Some(2), Some(3)]);
+        assert_eq!(tree.kind(1), Some(NodeKind::Mix));
+        assert_eq!(tree.kind(2), Some(NodeKind::Gain));
+        assert_eq!(tree.kind(3), Some(NodeKind::Sine));
+        assert_eq!(tree.kind(4), None);
+        assert_eq!(tree.children(1), []);
+        assert_eq!(tree.children(2), [3]);
+
+        // New IDs continue after the last node.
+        assert_eq!(add(&mut tree, a, 1, NodeKind::Sine), 4);
+        assert_eq!(tree.children(1), [4]);
+
+        let new_ids = tree.remove(a, 2).ok().unwrap();
+        assert_eq!(new_ids, [Some(0), Some(1), None, None, Some(2)]);
+        assert_eq!(tree.children(ROOT_ID), [1]);
+        assert_eq!(tree.children(1), [2]);
+
+        tree.clear(a, ROOT_ID).ok().unwrap();
+        assert_eq!(tree.children(ROOT_ID), []);
+        assert_eq!(tree.kind(1), None);
+    }
+
+    #[test]
+    fn test_move_node() {
+        let mut audio = Manager::new();
+        let mut tree = AudioTree::new();
+        let a = &mut audio;
+        let mix = add(&mut tree, a, ROOT_ID, NodeKind::Mix);
+        let mute = add(&mut tree, a, ROOT_ID, NodeKind::Mute);
+        let sine = add(&mut tree, a, mix, NodeKind::Sine);
+        let gain = add(&mut tree, a, mute, NodeKind::Gain);
+        tree.modulate(
+            mute,
+            0,
+            Box::new(firefly_audio::modulators::Hold::new(0., 0., 0)),
+        )
+        .ok()
+        .unwrap();
+        assert!(!is_silent(a));
+
+        // The moved node keeps its ID and goes after the other children.
+        tree.move_node(a, sine, mute).ok().unwrap();
+        assert_eq!(tree.children(mix), []);
+        assert_eq!(tree.children(mute), [gain, sine]);
+        assert!(tree.is_within(sine, mute));
+        // The Mute node keeps its modulator and so stays muted.
+        assert!(is_silent(a));
+
+        tree.move_node(a, sine, mix).ok().unwrap();
+        assert_eq!(tree.children(mix), [sine]);
+        assert!(!is_silent(a));
+
+        // A node can have at most 4 children.
+        for _ in 0..3 {
+            add(&mut tree, a, mute, NodeKind::Sine);
+        }
+        let res = tree.move_node(a, sine, mute);
+        assert!(matches!(res, Err(NodeError::TooManyChildren)));
+        assert_eq!(tree.children(mix), [sine]);
+    }
+}
@@ -0,0 +1,104 @@
+/// How many button transitions can be buffered for a single frame.
+const MAX_EVENTS: usize = 32;
+/// The number of buttons tracked: A, B, X, Y, and menu.
+const BUTTONS: u8 = 5;
+
+/// A button pressed or released.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) struct ButtonEvent {
+    /// The button index, in the same order as the bits of `input.read_buttons`.
+    pub button: u8,
+    pub pressed: bool,
+    /// When the transition happened, in milliseconds since the previous frame.
+    ///
+    /// Always 0 for the input synchronized over the network.
+    pub offset: u16,
+}
+
+impl ButtonEvent {
+    /// Encode the event for the guest: button, pressed flag, and little-endian offset.
+    pub fn encode(&self) -> [u8; 4] {
+        let offset = self.offset.to_le_bytes();
+        [self.button, u8::from(self.pressed), offset[0], offset[1]]
+    }
+}
+
+/// Press and release transitions of buttons.
+///
+/// Transitions are recorded as soon as the input is read, possibly
+/// several times between frames, and are shown to the app on the next frame.
+/// That way, the app doesn't miss short taps happening between two updates.
+#[derive(Default)]
+pub(crate) struct ButtonEvents {
+    /// The buttons pressed when the input was last read.
+    prev: u8,
+    /// Transitions recorded since the last frame.
+    pending: heapless::Vec<ButtonEvent, MAX_EVENTS>,
+    /// Transitions visible to the app during the current frame.
+    current: heapless::Vec<ButtonEvent, MAX_EVENTS>,
+}
+
+impl ButtonEvents {
+    /// Record transitions between the previous and the given state of buttons.
+    pub fn record(&mut self, buttons: u8, offset: u16) {
+        let changed = self.prev ^ buttons;
+        for button in 0..BUTTONS {
+            if changed & (1 << button) == 0 {
+                continue;
+            }
+            let event = ButtonEvent {
+                button,
+                pressed: buttons & (1 << button) != 0,
+                offset,
+            };
+            // If the buffer is full, drop the event but still track the state.
+            _ = self.pending.push(event);
+        }
+        self.prev = buttons;
+    }
+
+    /// Make the recorded transitions visible to the app.
+    pub fn next_frame(&mut self) {
+        self.current = core::mem::take(&mut self.pending);
+    }
+
+    /// Transitions that happened since the previous frame.
+    pub fn events(&self) -> &[ButtonEvent] {
+        &self.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_button_events() {
+        let mut events = ButtonEvents::default();
+        events.record(0b00001, 0);
+        events.next_frame();
+        let expected = ButtonEvent {
+            button: 0,
+            pressed: true,
+            offset: 0,
+        };
+        assert_eq!(events.events(), [expected]);
+
+        // A short tap of B between frames.
+        events.record(0b00011, 4);
+        events.record(0b00001, 9);
+        events.record(0b00001, 16);
+        assert_eq!(events.events().len(), 1);
+        events.next_frame();
+        let events: heapless::Vec<_, 4> = events.events().iter().map(ButtonEvent::encode).collect();
+        assert_eq!(events, [[1, 1, 4, 0], [1, 0, 9, 0]]);
+    }
+
+    #[test]
+    fn test_no_events() {
+        let mut events = ButtonEvents::default();
+        events.record(0, 0);
+        events.next_frame();
+        assert!(events.events().is_empty());
+    }
+}
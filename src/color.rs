@@ -1,3 +1,4 @@
+use crate::frame_buffer::DEFAULT_PALETTE;
 use embedded_graphics::pixelcolor::raw::RawU16;
 use embedded_graphics::pixelcolor::*;
 
@@ -135,6 +136,81 @@ impl FromRGB for Rgb888 {
     }
 }
 
+/// Colors of the system UI: the menu, the keyboard, the error scene,
+/// and the battery indicator.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Theme {
+    pub bg: Rgb16,
+    pub primary: Rgb16,
+    pub accent: Rgb16,
+    pub danger: Rgb16,
+    pub muted: Rgb16,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            bg: DEFAULT_PALETTE[12],
+            primary: DEFAULT_PALETTE[0],
+            accent: DEFAULT_PALETTE[6],
+            danger: DEFAULT_PALETTE[2],
+            muted: DEFAULT_PALETTE[13],
+        }
+    }
+}
+
+impl Theme {
+    /// Build the theme from the user's theme and contrast settings.
+    ///
+    /// The theme is packed into u32 the same way as in firefly-types:
+    /// one nibble per color, from the highest used bits: primary, secondary,
+    /// accent, and background color. Each color is an index in the default palette.
+    /// The lowest byte is the theme index and isn't used here.
+    /// In high contrast mode, all text is drawn in the primary color.
+    pub fn new(settings: &firefly_types::Settings) -> Self {
+        let color = |shift: u32| {
+            let index = (settings.theme >> shift) & 0xF;
+            DEFAULT_PALETTE[index as usize]
+        };
+        let mut theme = Self {
+            primary: color(20),
+            muted: color(16),
+            accent: color(12),
+            bg: color(8),
+            ..Self::default()
+        };
+        // Don't let a broken theme make the system UI unreadable.
+        if theme.primary == theme.bg {
+            theme = Self::default();
+        }
+        if settings.contrast {
+            theme.accent = theme.primary;
+            theme.muted = theme.primary;
+        }
+        theme
+    }
+
+    pub fn bg<C: FromRGB>(&self) -> C {
+        C::from_rgb(self.bg)
+    }
+
+    pub fn primary<C: FromRGB>(&self) -> C {
+        C::from_rgb(self.primary)
+    }
+
+    pub fn accent<C: FromRGB>(&self) -> C {
+        C::from_rgb(self.accent)
+    }
+
+    pub fn danger<C: FromRGB>(&self) -> C {
+        C::from_rgb(self.danger)
+    }
+
+    pub fn muted<C: FromRGB>(&self) -> C {
+        C::from_rgb(self.muted)
+    }
+}
+
 const fn new_rgb565(r: u8, g: u8, b: u8) -> Rgb565 {
     let r = r as u32 * Rgb565::MAX_R as u32 / Rgb888::MAX_R as u32;
     let g = g as u32 * Rgb565::MAX_G as u32 / Rgb888::MAX_G as u32;
@@ -144,3 +220,32 @@ const fn new_rgb565(r: u8, g: u8, b: u8) -> Rgb565 {
     debug_assert!(b < 256);
     Rgb565::new(r as u8, g as u8, b as u8)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_theme() {
+        let mut settings = firefly_types::Settings::default();
+        let theme = Theme::new(&settings);
+        assert!(theme == Theme::default());
+
+        settings.theme = 0x_C0_6D_00;
+        let theme = Theme::new(&settings);
+        assert!(theme.primary == DEFAULT_PALETTE[0xC]);
+        assert!(theme.muted == DEFAULT_PALETTE[0x0]);
+        assert!(theme.accent == DEFAULT_PALETTE[0x6]);
+        assert!(theme.bg == DEFAULT_PALETTE[0xD]);
+
+        // Unreadable themes fall back to the default one.
+        settings.theme = 0;
+        assert!(Theme::new(&settings) == Theme::default());
+
+        settings.theme = 0x_0D_6C_00;
+        settings.contrast = true;
+        let theme = Theme::new(&settings);
+        assert!(theme.accent == theme.primary);
+        assert!(theme.muted == theme.primary);
+    }
+}
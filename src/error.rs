@@ -8,6 +8,7 @@ pub enum Error {
     OpenDir(alloc::string::String, firefly_hal::FSError),
     OpenFile(&'static str, firefly_hal::FSError),
     ReadFile(&'static str, firefly_hal::FSError),
+    WriteFile(&'static str, firefly_hal::FSError),
     NoLauncher,
     InvalidAuthorID(firefly_types::ValidationError),
     InvalidAppID(firefly_types::ValidationError),
@@ -16,9 +17,12 @@ pub enum Error {
     AppIDMismatch,
 
     Linking(LinkingError),
+    Instrument(wasmparser::BinaryReaderError),
 
     DecodeMeta(postcard::Error),
     DecodeStats(postcard::Error),
+    EncodeSnapshot(postcard::Error),
+    DecodeSnapshot(postcard::Error),
     SerialEncode(postcard::Error),
     SerialDecode(postcard::Error),
 
@@ -28,6 +32,15 @@ pub enum Error {
 
     CheatUndefined,
     CheatInNet,
+
+    MemoryNotFound,
+    MemoryOutOfBounds,
+    GlobalNotFound,
+    GlobalWrite,
+
+    SuspendInNet,
+    SuspendUnsupported,
+    InvalidSnapshot,
 }
 
 impl fmt::Display for Error {
@@ -65,11 +78,15 @@ impl fmt::Display for Error {
             Self::InvalidAppID(err) => write!(f, "invalid app ID: {err}"),
             Self::CannotDisplay => write!(f, "failed to draw on the display"),
             Self::ReadFile(name, err) => write!(f, "cannot read {name}: {err}"),
+            Self::WriteFile(name, err) => write!(f, "cannot write {name}: {err}"),
             Self::AuthorIDMismatch => write!(f, "author ID in meta and in path don't match"),
             Self::AppIDMismatch => write!(f, "app ID in meta and in path don't match"),
             Self::Linking(err) => write!(f, "linking: {err}"),
+            Self::Instrument(err) => write!(f, "cannot instrument _bin for profiling: {err}"),
             Self::DecodeMeta(err) => write!(f, "cannot decode _meta: {err}"),
             Self::DecodeStats(err) => write!(f, "cannot decode stats: {err}"),
+            Self::EncodeSnapshot(err) => write!(f, "cannot encode app snapshot: {err}"),
+            Self::DecodeSnapshot(err) => write!(f, "cannot decode app snapshot: {err}"),
             Self::SerialEncode(err) => write!(f, "cannot encode response for serial: {err}"),
             Self::SerialDecode(err) => write!(f, "cannot decode request from serial: {err}"),
             Self::SerialStart(err) => write!(f, "cannot connect to serial port: {err}"),
@@ -77,6 +94,13 @@ impl fmt::Display for Error {
             Self::SerialRecv(err) => write!(f, "cannot read from serial port: {err}"),
             Self::CheatUndefined => write!(f, "the app doesn't have cheat callback"),
             Self::CheatInNet => write!(f, "cheats are disabled in multiplayer"),
+            Self::MemoryNotFound => write!(f, "the app doesn't export memory"),
+            Self::MemoryOutOfBounds => write!(f, "the memory range is out of bounds"),
+            Self::GlobalNotFound => write!(f, "the app doesn't export the global"),
+            Self::GlobalWrite => write!(f, "the global is immutable or has a different type"),
+            Self::SuspendInNet => write!(f, "cannot suspend the app in multiplayer"),
+            Self::SuspendUnsupported => write!(f, "the app doesn't export resume callback"),
+            Self::InvalidSnapshot => write!(f, "the app snapshot is corrupted"),
         }
     }
 }
@@ -130,6 +154,8 @@ pub(crate) enum HostError {
     NoneColor,
     UnknownPeer(u32),
     AudioNode(firefly_audio::NodeError),
+    UnknownNode(u32),
+    UnknownMenuItem(u32),
     NoStats,
     NoBadges,
     NoBadge(u32),
@@ -160,6 +186,8 @@ impl fmt::Display for HostError {
             Self::NoneColor => write!(f, "color is None (0)"),
             Self::UnknownPeer(p) => write!(f, "peer {p} is not connected"),
             Self::AudioNode(err) => write!(f, "audio node error: {err}"),
+            Self::UnknownNode(id) => write!(f, "audio node {id} does not exist"),
+            Self::UnknownMenuItem(id) => write!(f, "menu item {id} does not exist"),
             Self::NoStats => write!(f, "the app doesn't have stats file"),
             Self::NoBadges => write!(f, "the app doesn't have any badges"),
             Self::NoBadge(id) => write!(f, "the app doesn't have a badge with ID {id}"),
@@ -1,37 +1,72 @@
-use crate::color::FromRGB;
-use embedded_graphics::mono_font::ascii::FONT_6X9;
+use crate::color::{FromRGB, Theme};
+use crate::config::FullID;
+use crate::error::RuntimeStats;
+use crate::i18n::{Lang, Message};
+use alloc::string::String;
 use embedded_graphics::mono_font::MonoTextStyle;
 use embedded_graphics::prelude::*;
 use embedded_graphics::primitives::{
     CornerRadii, PrimitiveStyle, Rectangle, RoundedRectangle, StyledDrawable,
 };
 use embedded_graphics::text::Text;
-use firefly_hal::{Device, DeviceImpl, Duration, Instant};
+use firefly_hal::{Device, DeviceImpl, Duration, InputState, Instant};
 
 const FONT_HEIGHT: i32 = 10;
 const FONT_WIDTH: i32 = 6;
 const CENTER: Point = Point::new(240 / 2, 160 / 2);
 const BTN_DELAY: Duration = Duration::from_ms(500);
+/// How many lines of the error report fit on one page.
+const PAGE_LINES: usize = 9;
+/// The left edge of the area on the right side of the screen reserved for the QR code.
+const QR_LEFT: i32 = 128;
+/// The version of the runtime included in the error report.
+const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 /// An alert popup window showing an error message.
+///
+/// Besides the message itself, shows the app ID, the runtime version,
+/// and runtime stats. The full report is also encoded as a QR code
+/// so that it can be scanned with a phone and attached to a bug report.
 pub(crate) struct ErrorScene {
-    msg: alloc::string::String,
+    /// The full error report, wrapped to fit the screen.
+    msg: String,
+    /// The error report rendered as an ASCII QR code.
+    ///
+    /// None if the report is too big to fit into a QR code.
+    qr: Option<String>,
+    /// The index of the currently shown page of the report.
+    page: usize,
     start: Option<Instant>,
     showed_msg: bool,
     showed_btn: bool,
     enabled_btn: bool,
     buttons: u8,
+
+    /// True if the touch pad is currently pressed up or down.
+    pad_pressed: bool,
+
+    /// The language of the confirmation button.
+    lang: Lang,
 }
 
 impl ErrorScene {
-    pub fn new(msg: alloc::string::String) -> Self {
+    pub fn new(msg: String, id: &FullID, stats: &RuntimeStats, lang: Lang) -> Self {
+        // The QR code goes into bug reports, so it's always in English.
+        let report = make_report(&msg, id, stats, Lang::English);
+        let qr = render_qr(&report);
+        let mut msg = make_report(&msg, id, stats, lang);
+        wrap_text(&mut msg);
         Self {
             msg,
+            qr,
+            page: 0,
             start: None,
             showed_msg: false,
             showed_btn: false,
             enabled_btn: false,
             buttons: 0,
+            pad_pressed: false,
+            lang,
         }
     }
 
@@ -52,9 +87,16 @@ impl ErrorScene {
             }
         }
 
+        let input = device.read_input();
+        if let Some(input) = &input {
+            self.handle_pad(input);
+        } else {
+            self.pad_pressed = false;
+        }
+
         // If the button is active, check if the user pressed and released it.
         if self.enabled_btn {
-            let buttons = match device.read_input() {
+            let buttons = match input {
                 Some(input) => input.buttons,
                 None => 0u8,
             };
@@ -67,61 +109,165 @@ impl ErrorScene {
         false
     }
 
-    pub fn render<D, C, E>(&mut self, display: &mut D) -> Result<(), E>
+    /// Switch between pages of the report when the touch pad is pressed up or down.
+    fn handle_pad(&mut self, input: &InputState) {
+        let Some(pad) = &input.pad else {
+            self.pad_pressed = false;
+            return;
+        };
+        let pressed = pad.y < -50 || pad.y > 50;
+        if pressed && !self.pad_pressed {
+            let page = if pad.y < -50 {
+                (self.page + 1).min(self.pages() - 1)
+            } else {
+                self.page.saturating_sub(1)
+            };
+            if page != self.page {
+                self.page = page;
+                self.showed_msg = false;
+            }
+        }
+        self.pad_pressed = pressed;
+    }
+
+    /// The number of pages in the report.
+    fn pages(&self) -> usize {
+        let lines = self.msg.lines().count();
+        lines.div_ceil(PAGE_LINES).max(1)
+    }
+
+    pub fn render<D, C, E>(&mut self, display: &mut D, theme: &Theme) -> Result<(), E>
     where
         D: DrawTarget<Color = C, Error = E> + OriginDimensions,
         C: RgbColor + FromRGB,
     {
         if !self.showed_msg {
-            display.clear(C::BG)?;
-            let mut text_style = MonoTextStyle::new(&FONT_6X9, C::PRIMARY);
-            text_style.background_color = Some(C::BG);
-            wrap_text(&mut self.msg);
-            let line_width = self
-                .msg
-                .lines()
-                .map(|line| line.len())
-                .max()
-                .unwrap_or_default();
-            let x_shift = FONT_WIDTH * line_width as i32 / 2;
-            let point = Point::new(CENTER.x - x_shift, 71 - FONT_HEIGHT);
-            let text = Text::new(&self.msg, point, text_style);
-            text.draw(display)?;
+            display.clear(theme.bg())?;
+            let mut text_style = MonoTextStyle::new(self.lang.font(), theme.primary());
+            text_style.background_color = Some(theme.bg());
+            let start = self.page * PAGE_LINES;
+            let lines = self.msg.lines().skip(start).take(PAGE_LINES);
+            for (line, i) in lines.zip(0..) {
+                let point = Point::new(4, FONT_HEIGHT + i * FONT_HEIGHT);
+                let text = Text::new(line, point, text_style);
+                text.draw(display)?;
+            }
+            self.draw_page_number(display, theme)?;
+            self.draw_qr(display, theme)?;
             self.showed_msg = true;
+            // The screen was cleared, the button must be drawn again.
+            self.showed_btn = false;
         }
 
         if !self.showed_btn {
-            let color = if self.enabled_btn {
-                C::ACCENT
+            let color: C = if self.enabled_btn {
+                theme.accent()
             } else {
-                C::MUTED
+                theme.muted()
             };
 
-            let text = "oh no!";
-            let x_shift = (FONT_WIDTH / 2) * text.len() as i32;
-            let point = Point::new(CENTER.x - x_shift, 120 - FONT_HEIGHT);
+            let text = Message::OhNo.translate(self.lang);
+            let text_width = text.chars().count() as i32 * FONT_WIDTH;
+            let x_shift = text_width / 2;
+            let point = Point::new(QR_LEFT / 2 - x_shift, 140 - FONT_HEIGHT);
 
             {
                 let point = Point::new(point.x - 2, point.y - 8);
                 let mut box_style = PrimitiveStyle::with_stroke(color, 1);
-                box_style.fill_color = Some(C::BG);
+                box_style.fill_color = Some(theme.bg());
                 let corners = CornerRadii::new(Size::new_equal(4));
                 let size = Size {
-                    width: (text.len() as i32 * FONT_WIDTH) as u32 + 4,
+                    width: text_width as u32 + 4,
                     height: FONT_HEIGHT as u32 + 4,
                 };
                 let rect = RoundedRectangle::new(Rectangle::new(point, size), corners);
                 rect.draw_styled(&box_style, display)?;
             }
 
-            let mut text_style = MonoTextStyle::new(&FONT_6X9, color);
-            text_style.background_color = Some(C::BG);
+            let mut text_style = MonoTextStyle::new(self.lang.font(), color);
+            text_style.background_color = Some(theme.bg());
             let text = Text::new(text, point, text_style);
             text.draw(display)?;
             self.showed_btn = true;
         }
         Ok(())
     }
+
+    /// Show the current page number if the report doesn't fit on one page.
+    fn draw_page_number<D, C, E>(&self, display: &mut D, theme: &Theme) -> Result<(), E>
+    where
+        D: DrawTarget<Color = C, Error = E> + OriginDimensions,
+        C: RgbColor + FromRGB,
+    {
+        let pages = self.pages();
+        if pages <= 1 {
+            return Ok(());
+        }
+        let mut text_style = MonoTextStyle::new(self.lang.font(), theme.muted());
+        text_style.background_color = Some(theme.bg());
+        let label = Message::Page.translate(self.lang);
+        let text = alloc::format!("{label} {}/{pages}", self.page + 1);
+        let x_shift = (FONT_WIDTH / 2) * text.chars().count() as i32;
+        let point = Point::new(QR_LEFT / 2 - x_shift, 120 - FONT_HEIGHT);
+        Text::new(&text, point, text_style).draw(display)?;
+        Ok(())
+    }
+
+    /// Draw the QR code with the full report on the right side of the screen.
+    fn draw_qr<D, C, E>(&self, display: &mut D, theme: &Theme) -> Result<(), E>
+    where
+        D: DrawTarget<Color = C, Error = E> + OriginDimensions,
+        C: RgbColor + FromRGB,
+    {
+        let Some(qr) = &self.qr else {
+            return Ok(());
+        };
+        let width = qr.find('\n').unwrap_or_default() as i32;
+        if width == 0 {
+            return Ok(());
+        }
+        let available = 240 - QR_LEFT - 4;
+        let scale = (available / width).max(1);
+        let side = width * scale;
+        let left = QR_LEFT + (available - side) / 2;
+        let top = CENTER.y - side / 2;
+        let size = Size::new_equal(scale as u32);
+        for (line, y) in qr.lines().zip(0..) {
+            for (ch, x) in line.chars().zip(0..) {
+                let color: C = if ch == '#' {
+                    theme.primary()
+                } else {
+                    theme.bg()
+                };
+                let point = Point::new(left + x * scale, top + y * scale);
+                display.fill_solid(&Rectangle::new(point, size), color)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The full error report: the message, the app ID, the runtime version, and stats.
+fn make_report(msg: &str, id: &FullID, stats: &RuntimeStats, lang: Lang) -> String {
+    let author = id.author();
+    let app = id.app();
+    let app_label = Message::App.translate(lang);
+    let runtime_label = Message::Runtime.translate(lang);
+    alloc::format!("{msg}\n\n{app_label}: {author}.{app}\n{runtime_label}: v{VERSION}\n{stats}")
+}
+
+/// Encode the error report as an ASCII QR code.
+///
+/// Uses the same rendering path as `graphics.draw_qr`.
+fn render_qr(report: &str) -> Option<String> {
+    let code = tinyqr::QrCode::new(report.as_bytes()).ok()?;
+    let ascii_img = code
+        .render::<char>()
+        .dark_color('#')
+        .light_color(' ')
+        .module_dimensions(1, 1)
+        .build();
+    Some(ascii_img)
 }
 
 /// Split long lines of text into several lines.
@@ -138,3 +284,194 @@ fn wrap_text(text: &mut str) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::convert::Infallible;
+    use embedded_graphics::pixelcolor::Rgb565;
+    use firefly_hal::Pad;
+
+    /// A display keeping all pixels in memory.
+    struct Screen(Vec<Rgb565>);
+
+    impl Screen {
+        fn new() -> Self {
+            Self(vec![Rgb565::BLACK; 240 * 160])
+        }
+
+        /// Count pixels of the given color in the given area.
+        fn count(&self, area: Rectangle, color: Rgb565) -> usize {
+            area.points()
+                .filter(|p| self.0[(p.y * 240 + p.x) as usize] == color)
+                .count()
+        }
+    }
+
+    impl OriginDimensions for Screen {
+        fn size(&self) -> Size {
+            Size::new(240, 160)
+        }
+    }
+
+    impl DrawTarget for Screen {
+        type Color = Rgb565;
+        type Error = Infallible;
+
+        fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+        where
+            I: IntoIterator<Item = Pixel<Self::Color>>,
+        {
+            for Pixel(point, color) in pixels {
+                if (0..240).contains(&point.x) && (0..160).contains(&point.y) {
+                    self.0[(point.y * 240 + point.x) as usize] = color;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn make_id() -> FullID {
+        FullID::new("joearms".try_into().unwrap(), "hello".try_into().unwrap())
+    }
+
+    fn make_scene(msg: &str) -> ErrorScene {
+        let id = make_id();
+        let stats = RuntimeStats {
+            last_called: "graphics.draw_line",
+        };
+        ErrorScene::new(msg.into(), &id, &stats, Lang::English)
+    }
+
+    fn press_pad(scene: &mut ErrorScene, y: i16) {
+        let input = InputState {
+            pad: Some(Pad { x: 0, y }),
+            buttons: 0,
+        };
+        scene.handle_pad(&input);
+        let input = InputState::default();
+        scene.handle_pad(&input);
+    }
+
+    #[test]
+    fn test_wrap_text() {
+        let mut text = String::from("the quick brown fox jumps over the lazy dog");
+        wrap_text(&mut text);
+        assert_eq!(text, "the quick brown fox jumps\nover the lazy dog");
+        let mut text = String::from("short\nlines stay as is");
+        wrap_text(&mut text);
+        assert_eq!(text, "short\nlines stay as is");
+    }
+
+    #[test]
+    fn test_pages() {
+        // The message, an empty line, app, runtime, and stats wrapped on two lines.
+        let scene = make_scene("oops");
+        assert_eq!(scene.msg.lines().count(), 6);
+        assert_eq!(scene.pages(), 1);
+
+        let msg = "line\n".repeat(20);
+        let scene = make_scene(&msg);
+        let lines = scene.msg.lines().count();
+        assert_eq!(scene.pages(), lines.div_ceil(PAGE_LINES));
+        assert_eq!(scene.pages(), 3);
+    }
+
+    #[test]
+    fn test_switch_pages() {
+        let msg = "line\n".repeat(20);
+        let mut scene = make_scene(&msg);
+        scene.showed_msg = true;
+        // Can't go before the first page.
+        press_pad(&mut scene, 100);
+        assert_eq!(scene.page, 0);
+        assert!(scene.showed_msg);
+        press_pad(&mut scene, -100);
+        assert_eq!(scene.page, 1);
+        assert!(!scene.showed_msg);
+        // Holding the pad doesn't flip more pages.
+        let input = InputState {
+            pad: Some(Pad { x: 0, y: -100 }),
+            buttons: 0,
+        };
+        scene.handle_pad(&input);
+        scene.handle_pad(&input);
+        assert_eq!(scene.page, 2);
+        // Can't go past the last page.
+        press_pad(&mut scene, -100);
+        assert_eq!(scene.page, 2);
+        press_pad(&mut scene, 100);
+        assert_eq!(scene.page, 1);
+    }
+
+    #[test]
+    fn test_render_app_info() {
+        let mut scene = make_scene("oops");
+        let mut lines = scene.msg.lines();
+        assert_eq!(lines.next(), Some("oops"));
+        assert_eq!(lines.next(), Some(""));
+        assert_eq!(lines.next(), Some("app: joearms.hello"));
+        let runtime = alloc::format!("runtime: v{VERSION}");
+        assert_eq!(lines.next(), Some(runtime.as_str()));
+
+        let theme = Theme::default();
+        let mut screen = Screen::new();
+        scene.render(&mut screen, &theme).unwrap();
+        assert!(scene.showed_msg);
+        assert!(scene.showed_btn);
+        let primary: Rgb565 = theme.primary();
+        // The app info is on the third line of the text on the left.
+        let top = 2 * FONT_HEIGHT + 2;
+        let app_line = Rectangle::new(Point::new(4, top), Size::new(18 * 6, 9));
+        assert!(screen.count(app_line, primary) > 0);
+        // The QR code is on the right.
+        let qr = Rectangle::new(Point::new(QR_LEFT, 0), Size::new(240 - QR_LEFT as u32, 160));
+        assert!(screen.count(qr, primary) > 0);
+    }
+
+    #[test]
+    fn test_localized_report() {
+        let id = make_id();
+        let stats = RuntimeStats {
+            last_called: "graphics.draw_line",
+        };
+        let scene = ErrorScene::new("oops".into(), &id, &stats, Lang::German);
+        let mut lines = scene.msg.lines().skip(2);
+        assert_eq!(lines.next(), Some("App: joearms.hello"));
+        let runtime = alloc::format!("Laufzeit: v{VERSION}");
+        assert_eq!(lines.next(), Some(runtime.as_str()));
+        // The QR code is the same for all languages.
+        assert_eq!(scene.qr, make_scene("oops").qr);
+    }
+
+    #[test]
+    fn test_qr_payload() {
+        let id = make_id();
+        let stats = RuntimeStats {
+            last_called: "graphics.draw_line",
+        };
+        let report = make_report("oops", &id, &stats, Lang::English);
+
+        // The payload is the full report, not the text wrapped for the screen.
+        let scene = make_scene("oops");
+        assert_eq!(scene.qr, render_qr(&report));
+        let qr = scene.qr.unwrap();
+        let width = qr.find('\n').unwrap();
+        assert!(qr.lines().all(|line| line.chars().count() == width));
+        assert_eq!(qr.lines().count(), width);
+
+        // The app ID and the stats can be parsed back from the payload.
+        let app = report
+            .lines()
+            .find_map(|l| l.strip_prefix("app: "))
+            .unwrap();
+        assert!(FullID::try_from(app).ok() == Some(id));
+        assert!(report.ends_with(&alloc::format!("\n{stats}")));
+
+        // Reports that don't fit into a QR code are shown without it.
+        let huge = "x".repeat(8000);
+        assert_eq!(render_qr(&huge), None);
+    }
+}
@@ -17,7 +17,7 @@ const BUFFER_SIZE: usize = WIDTH * HEIGHT / PPB;
 
 // https://lospec.com/palette-list/sweetie-16
 // https://github.com/nesbox/TIC-80/wiki/Palette
-const DEFAULT_PALETTE: [Rgb16; 16] = [
+pub(crate) const DEFAULT_PALETTE: [Rgb16; 16] = [
     Rgb16::from_rgb(0x1a, 0x1c, 0x2c), // #1a1c2c, black
     Rgb16::from_rgb(0x5d, 0x27, 0x5d), // #5d275d, purple
     Rgb16::from_rgb(0xb1, 0x3e, 0x53), // #b13e53, red
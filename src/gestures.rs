@@ -0,0 +1,219 @@
+use firefly_hal::Pad;
+
+/// The default radius of the touch pad area around the center ignored by the d-pad.
+///
+/// Also used by the system menu and the virtual keyboard.
+pub(crate) const DEFAULT_DEAD_ZONE: u16 = 50;
+/// How far (in pad units) the finger can move for the touch to still count as a tap.
+const TAP_DISTANCE: i64 = 100;
+/// How long (in frames) the finger can touch the pad for the touch to count as a tap.
+const TAP_FRAMES: u32 = 15;
+/// How soon (in frames) after a tap the next tap counts as a double tap.
+const DOUBLE_TAP_FRAMES: u32 = 20;
+/// How far (in pad units) the finger must move for the touch to count as a swipe.
+const SWIPE_DISTANCE: i64 = 400;
+/// How long (in frames) a swipe can take.
+const SWIPE_FRAMES: u32 = 30;
+const FPS: i64 = 60;
+
+/// One of 8 directions, counter-clockwise starting from the right.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub(crate) enum Direction {
+    #[default]
+    None = 0,
+    Right = 1,
+    UpRight = 2,
+    Up = 3,
+    UpLeft = 4,
+    Left = 5,
+    DownLeft = 6,
+    Down = 7,
+    DownRight = 8,
+}
+
+impl Direction {
+    /// Get the direction of the vector, ignoring vectors shorter than the dead zone.
+    pub fn from_vector(x: i32, y: i32, dead_zone: u16) -> Self {
+        let dead_zone = i64::from(dead_zone);
+        let (x, y) = (i64::from(x), i64::from(y));
+        if x * x + y * y < dead_zone * dead_zone {
+            return Self::None;
+        }
+        // tan(22.5°) ≈ 0.4142 ≈ 53/128
+        let ax = x.abs();
+        let ay = y.abs();
+        if ay * 128 < ax * 53 {
+            return if x > 0 { Self::Right } else { Self::Left };
+        }
+        if ax * 128 < ay * 53 {
+            return if y > 0 { Self::Up } else { Self::Down };
+        }
+        match (x > 0, y > 0) {
+            (true, true) => Self::UpRight,
+            (false, true) => Self::UpLeft,
+            (false, false) => Self::DownLeft,
+            (true, false) => Self::DownRight,
+        }
+    }
+
+    /// Get the d-pad direction of the finger position on the touch pad.
+    pub fn from_pad(pad: &Pad, dead_zone: u16) -> Self {
+        Self::from_vector(i32::from(pad.x), i32::from(pad.y), dead_zone)
+    }
+}
+
+/// A recognized touch pad gesture.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Gesture {
+    Tap,
+    DoubleTap,
+    /// A quick finger movement in the given direction with the given speed
+    /// (in pad units per second).
+    Swipe(Direction, u16),
+}
+
+impl Gesture {
+    /// Encode the gesture for the guest.
+    ///
+    /// The lowest byte is the gesture type (1 for tap, 2 for double tap,
+    /// 3 for swipe), the second byte is the swipe direction,
+    /// and the highest 2 bytes are the swipe speed.
+    pub fn encode(self) -> u32 {
+        match self {
+            Self::Tap => 1,
+            Self::DoubleTap => 2,
+            Self::Swipe(dir, speed) => 3 | ((dir as u32) << 8) | (u32::from(speed) << 16),
+        }
+    }
+}
+
+/// The start of the current touch.
+struct Touch {
+    x: i32,
+    y: i32,
+    frame: u32,
+}
+
+/// Recognizes gestures from the touch pad position reported each frame.
+#[derive(Default)]
+pub(crate) struct Gestures {
+    frame: u32,
+    touch: Option<Touch>,
+    /// The last known finger position of the current touch.
+    last: (i32, i32),
+    /// The frame on which the last tap ended, if it can be followed by a double tap.
+    last_tap: Option<u32>,
+    /// The gesture recognized on the current frame.
+    current: Option<Gesture>,
+}
+
+impl Gestures {
+    /// Update the recognizer with the pad state for the next frame.
+    pub fn update(&mut self, pad: Option<&Pad>) {
+        self.frame = self.frame.wrapping_add(1);
+        self.current = None;
+        if let Some(pad) = pad {
+            let pos = (i32::from(pad.x), i32::from(pad.y));
+            if self.touch.is_none() {
+                self.touch = Some(Touch {
+                    x: pos.0,
+                    y: pos.1,
+                    frame: self.frame,
+                });
+            }
+            self.last = pos;
+            return;
+        }
+        let Some(touch) = self.touch.take() else {
+            return;
+        };
+        self.current = self.recognize(&touch);
+    }
+
+    fn recognize(&mut self, touch: &Touch) -> Option<Gesture> {
+        let frames = self.frame.wrapping_sub(touch.frame);
+        let dx = self.last.0 - touch.x;
+        let dy = self.last.1 - touch.y;
+        let distance_sq = i64::from(dx) * i64::from(dx) + i64::from(dy) * i64::from(dy);
+        if distance_sq < TAP_DISTANCE * TAP_DISTANCE && frames <= TAP_FRAMES {
+            if let Some(last_tap) = self.last_tap.take() {
+                if self.frame.wrapping_sub(last_tap) <= DOUBLE_TAP_FRAMES {
+                    return Some(Gesture::DoubleTap);
+                }
+            }
+            self.last_tap = Some(self.frame);
+            return Some(Gesture::Tap);
+        }
+        self.last_tap = None;
+        if distance_sq >= SWIPE_DISTANCE * SWIPE_DISTANCE && frames <= SWIPE_FRAMES {
+            let dir = Direction::from_vector(dx, dy, 0);
+            // The approximate float sqrt is off by up to 5%, use the exact integer one.
+            let distance = distance_sq.isqrt();
+            let speed = distance * FPS / i64::from(frames.max(1));
+            let speed = u16::try_from(speed).unwrap_or(u16::MAX);
+            return Some(Gesture::Swipe(dir, speed));
+        }
+        None
+    }
+
+    /// The gesture recognized on the current frame.
+    pub fn current(&self) -> Option<Gesture> {
+        self.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pad(x: i16, y: i16) -> Pad {
+        Pad { x, y }
+    }
+
+    #[test]
+    fn test_direction() {
+        assert_eq!(Direction::from_vector(10, 10, 50), Direction::None);
+        assert_eq!(Direction::from_vector(100, 10, 50), Direction::Right);
+        assert_eq!(Direction::from_vector(-100, 10, 50), Direction::Left);
+        assert_eq!(Direction::from_vector(10, 100, 50), Direction::Up);
+        assert_eq!(Direction::from_vector(10, -100, 50), Direction::Down);
+        assert_eq!(Direction::from_vector(100, 100, 50), Direction::UpRight);
+        assert_eq!(Direction::from_vector(-100, -90, 50), Direction::DownLeft);
+        // Must not overflow.
+        assert_eq!(
+            Direction::from_vector(i32::MAX, 0, u16::MAX),
+            Direction::Right
+        );
+    }
+
+    #[test]
+    fn test_tap_and_double_tap() {
+        let mut gestures = Gestures::default();
+        gestures.update(Some(&pad(10, 10)));
+        gestures.update(Some(&pad(20, 10)));
+        assert_eq!(gestures.current(), None);
+        gestures.update(None);
+        assert_eq!(gestures.current(), Some(Gesture::Tap));
+        gestures.update(None);
+        assert_eq!(gestures.current(), None);
+        gestures.update(Some(&pad(10, 10)));
+        gestures.update(None);
+        assert_eq!(gestures.current(), Some(Gesture::DoubleTap));
+    }
+
+    #[test]
+    fn test_swipe() {
+        let mut gestures = Gestures::default();
+        gestures.update(Some(&pad(-300, 0)));
+        gestures.update(Some(&pad(0, 0)));
+        gestures.update(Some(&pad(300, 0)));
+        gestures.update(None);
+        let Some(Gesture::Swipe(dir, speed)) = gestures.current() else {
+            panic!("swipe expected");
+        };
+        assert_eq!(dir, Direction::Right);
+        assert_eq!(speed, 12_000);
+        let gesture = Gesture::Swipe(Direction::Right, 12_000);
+        assert_eq!(gesture.encode(), 3 | (1 << 8) | (12_000 << 16));
+    }
+}
@@ -1,7 +1,13 @@
 use super::fs::get_file_name;
+use crate::audio::{
+    Action, Adpcm, Bitcrusher, Compressor, Curve, Delay, Envelope, Gate, Lfo, NodeKind, NodeStatus,
+    Reverb, Tracked, Tracker, TrackerControl, Wave, ROOT_ID,
+};
 use crate::error::HostError;
 use crate::state::State;
+use crate::utils::read_all;
 use alloc::boxed::Box;
+use alloc::rc::Rc;
 use firefly_audio::*;
 use firefly_hal::Dir;
 
@@ -12,7 +18,7 @@ pub(crate) fn add_sine(mut caller: C, parent_id: u32, freq: f32, phase: f32) ->
     let state = caller.data_mut();
     state.called = "audio.add_sine";
     let proc = Sine::new(freq, phase);
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Sine, Box::new(proc))
 }
 
 /// Add square wave generator as a child for the given node.
@@ -20,7 +26,7 @@ pub(crate) fn add_square(mut caller: C, parent_id: u32, freq: f32, phase: f32) -
     let state = caller.data_mut();
     state.called = "audio.add_square";
     let proc = Square::new(freq, phase);
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Square, Box::new(proc))
 }
 
 /// Add sawtooth wave generator as a child for the given node.
@@ -28,7 +34,7 @@ pub(crate) fn add_sawtooth(mut caller: C, parent_id: u32, freq: f32, phase: f32)
     let state = caller.data_mut();
     state.called = "audio.add_sawtooth";
     let proc = Sawtooth::new(freq, phase);
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Sawtooth, Box::new(proc))
 }
 
 /// Add triangle wave generator as a child for the given node.
@@ -36,7 +42,7 @@ pub(crate) fn add_triangle(mut caller: C, parent_id: u32, freq: f32, phase: f32)
     let state = caller.data_mut();
     state.called = "audio.add_triangle";
     let proc = Triangle::new(freq, phase);
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Triangle, Box::new(proc))
 }
 
 /// Add white noise generator as a child for the given node.
@@ -44,7 +50,7 @@ pub(crate) fn add_noise(mut caller: C, parent_id: u32, seed: i32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_noise";
     let proc = Noise::new(seed);
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Noise, Box::new(proc))
 }
 
 /// Add empty source as a child for the given node.
@@ -52,7 +58,7 @@ pub(crate) fn add_empty(mut caller: C, parent_id: u32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_empty";
     let proc = Empty::new();
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Empty, Box::new(proc))
 }
 
 /// Add zero source as a child for the given node.
@@ -60,7 +66,7 @@ pub(crate) fn add_zero(mut caller: C, parent_id: u32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_zero";
     let proc = Zero::new();
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Zero, Box::new(proc))
 }
 
 /// Add PCM file source as a child for the given node.
@@ -89,7 +95,181 @@ pub(crate) fn add_file(mut caller: C, parent_id: u32, ptr: u32, len: u32) -> u32
             return 0;
         }
     };
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::File, Box::new(proc))
+}
+
+/// Add IMA ADPCM WAV file source as a child for the given node.
+///
+/// The file is decoded on the fly while streaming from the ROM.
+/// A spare reader is kept open so that the node can rewind on reset.
+pub(crate) fn add_adpcm(mut caller: C, parent_id: u32, ptr: u32, len: u32) -> u32 {
+    let state = caller.data_mut();
+    state.called = "audio.add_adpcm";
+    let Some(memory) = state.memory else {
+        state.log_error(HostError::MemoryNotFound);
+        return 0;
+    };
+    let (data, state) = memory.data_and_store_mut(&mut caller);
+    let Some(name) = get_file_name(state, data, ptr, len) else {
+        return 0;
+    };
+    let reader = match state.rom_dir.open_file(name) {
+        Ok(reader) => reader,
+        Err(err) => {
+            state.log_error(err);
+            return 0;
+        }
+    };
+    let proc = match Adpcm::from_file(reader) {
+        Ok(proc) => proc,
+        Err(err) => {
+            state.log_error(err);
+            return 0;
+        }
+    };
+    let rewind = Rc::downgrade(&proc.rewind());
+    let id = add_node(state, parent_id, NodeKind::Adpcm, Box::new(proc));
+    if id != 0 {
+        state.audio_rewinds.push((name.into(), rewind));
+    }
+    id
+}
+
+/// Add a tracker music (MOD) file from the ROM as a child for the given node.
+///
+/// The whole module is loaded into memory.
+pub(crate) fn add_tracker(mut caller: C, parent_id: u32, ptr: u32, len: u32) -> u32 {
+    let state = caller.data_mut();
+    state.called = "audio.add_tracker";
+    let Some(memory) = state.memory else {
+        state.log_error(HostError::MemoryNotFound);
+        return 0;
+    };
+    let (data, state) = memory.data_and_store_mut(&mut caller);
+    let Some(name) = get_file_name(state, data, ptr, len) else {
+        return 0;
+    };
+    let raw = match state.rom_dir.open_file(name) {
+        Ok(reader) => read_all(reader),
+        Err(err) => {
+            state.log_error(err);
+            return 0;
+        }
+    };
+    let Ok(raw) = raw else {
+        state.log_error("cannot read the module file");
+        return 0;
+    };
+    let control = Rc::new(TrackerControl::default());
+    let proc = match Tracker::from_bytes(&raw, control.clone()) {
+        Ok(proc) => proc,
+        Err(err) => {
+            state.log_error(err);
+            return 0;
+        }
+    };
+    let id = add_node(state, parent_id, NodeKind::Tracker, Box::new(proc));
+    if id != 0 {
+        state.trackers.push((id, control));
+    }
+    id
+}
+
+/// Jump to the given position in the order table of the tracker.
+pub(crate) fn tracker_jump(mut caller: C, node_id: u32, position: u32) {
+    let state = caller.data_mut();
+    state.called = "audio.tracker_jump";
+    if let Some(control) = get_tracker(state, node_id) {
+        control.jump.set(Some(position.min(255) as u8));
+    }
+}
+
+/// Mute tracker channels. Each bit of the mask is a channel, 1 means muted.
+pub(crate) fn tracker_mute(mut caller: C, node_id: u32, mask: u32) {
+    let state = caller.data_mut();
+    state.called = "audio.tracker_mute";
+    if let Some(control) = get_tracker(state, node_id) {
+        control.muted.set(mask);
+    }
+}
+
+/// Set the tempo of the tracker, in BPM.
+pub(crate) fn tracker_tempo(mut caller: C, node_id: u32, tempo: u32) {
+    let state = caller.data_mut();
+    state.called = "audio.tracker_tempo";
+    if !(32..=255).contains(&tempo) {
+        state.log_error("tempo must be between 32 and 255");
+        return;
+    }
+    if let Some(control) = get_tracker(state, node_id) {
+        control.tempo.set(Some(tempo as u8));
+    }
+}
+
+/// Get how many samples the node produced since it was added or reset.
+///
+/// Only file sources (PCM, ADPCM, and tracker music) are tracked.
+pub(crate) fn get_played(mut caller: C, node_id: u32) -> u32 {
+    let state = caller.data_mut();
+    state.called = "audio.get_played";
+    match get_status(state, node_id) {
+        Some(status) => status.played.get(),
+        None => 0,
+    }
+}
+
+/// Check if the file source has nothing more to play.
+pub(crate) fn is_exhausted(mut caller: C, node_id: u32) -> u32 {
+    let state = caller.data_mut();
+    state.called = "audio.is_exhausted";
+    match get_status(state, node_id) {
+        Some(status) => u32::from(status.exhausted.get()),
+        None => 0,
+    }
+}
+
+/// Call the `audio_event` callback of the app when the file source is exhausted.
+pub(crate) fn notify_on_end(mut caller: C, node_id: u32, enabled: u32) {
+    let state = caller.data_mut();
+    state.called = "audio.notify_on_end";
+    if let Some(status) = get_status(state, node_id) {
+        status.notify.set(enabled != 0);
+    }
+}
+
+fn get_status(state: &mut State, node_id: u32) -> Option<Rc<NodeStatus>> {
+    let status = state.audio_status.iter().find(|(id, _)| *id == node_id);
+    match status {
+        Some((_, status)) => Some(status.clone()),
+        None => {
+            state.log_error(HostError::UnknownNode(node_id));
+            None
+        }
+    }
+}
+
+/// Get the currently played position of the tracker.
+///
+/// The position in the order table is in the second byte
+/// and the pattern row is in the lowest byte.
+pub(crate) fn tracker_position(mut caller: C, node_id: u32) -> u32 {
+    let state = caller.data_mut();
+    state.called = "audio.tracker_position";
+    match get_tracker(state, node_id) {
+        Some(control) => (u32::from(control.order.get()) << 8) | u32::from(control.row.get()),
+        None => 0,
+    }
+}
+
+fn get_tracker(state: &mut State, node_id: u32) -> Option<Rc<TrackerControl>> {
+    let control = state.trackers.iter().find(|(id, _)| *id == node_id);
+    match control {
+        Some((_, control)) => Some(control.clone()),
+        None => {
+            state.log_error(HostError::UnknownNode(node_id));
+            None
+        }
+    }
 }
 
 /// Add Mix filter as a child for the given node.
@@ -97,7 +277,7 @@ pub(crate) fn add_mix(mut caller: C, parent_id: u32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_mix";
     let proc = Mix::new();
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Mix, Box::new(proc))
 }
 
 /// Add AllForOne filter as a child for the given node.
@@ -105,7 +285,7 @@ pub(crate) fn add_all_for_one(mut caller: C, parent_id: u32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_all_for_one";
     let proc = AllForOne::new();
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::AllForOne, Box::new(proc))
 }
 
 /// Add Gain filter as a child for the given node.
@@ -113,7 +293,7 @@ pub(crate) fn add_gain(mut caller: C, parent_id: u32, lvl: f32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_gain";
     let proc = Gain::new(lvl);
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Gain, Box::new(proc))
 }
 
 /// Add Loop filter as a child for the given node.
@@ -121,7 +301,7 @@ pub(crate) fn add_loop(mut caller: C, parent_id: u32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_loop";
     let proc = Loop::new();
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Loop, Box::new(proc))
 }
 
 /// Add Concat filter as a child for the given node.
@@ -129,7 +309,7 @@ pub(crate) fn add_concat(mut caller: C, parent_id: u32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_concat";
     let proc = Concat::new();
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Concat, Box::new(proc))
 }
 
 /// Add Pan filter as a child for the given node.
@@ -137,7 +317,7 @@ pub(crate) fn add_pan(mut caller: C, parent_id: u32, lvl: f32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_pan";
     let proc = Pan::new(lvl);
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Pan, Box::new(proc))
 }
 
 /// Add Mute filter as a child for the given node.
@@ -145,7 +325,7 @@ pub(crate) fn add_mute(mut caller: C, parent_id: u32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_mute";
     let proc = Mute::new();
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Mute, Box::new(proc))
 }
 
 /// Add Pause filter as a child for the given node.
@@ -153,7 +333,7 @@ pub(crate) fn add_pause(mut caller: C, parent_id: u32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_pause";
     let proc = Pause::new();
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Pause, Box::new(proc))
 }
 
 /// Add TrackPosition filter as a child for the given node.
@@ -161,7 +341,7 @@ pub(crate) fn add_track_position(mut caller: C, parent_id: u32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_track_position";
     let proc = TrackPosition::new();
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::TrackPosition, Box::new(proc))
 }
 
 /// Add LowHighPass filter as a child for the given node.
@@ -169,7 +349,7 @@ pub(crate) fn add_low_pass(mut caller: C, parent_id: u32, freq: f32, q: f32) ->
     let state = caller.data_mut();
     state.called = "audio.add_low_pass";
     let proc = LowHighPass::new(true, freq, q);
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::LowPass, Box::new(proc))
 }
 
 /// Add LowHighPass filter as a child for the given node.
@@ -177,7 +357,7 @@ pub(crate) fn add_high_pass(mut caller: C, parent_id: u32, freq: f32, q: f32) ->
     let state = caller.data_mut();
     state.called = "audio.add_high_pass";
     let proc = LowHighPass::new(false, freq, q);
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::HighPass, Box::new(proc))
 }
 
 /// Add TakeLeft filter as a child for the given node.
@@ -185,7 +365,7 @@ pub(crate) fn add_take_left(mut caller: C, parent_id: u32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_take_left";
     let proc = TakeLeft::new();
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::TakeLeft, Box::new(proc))
 }
 
 /// Add TakeRight filter as a child for the given node.
@@ -193,7 +373,7 @@ pub(crate) fn add_take_right(mut caller: C, parent_id: u32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_take_right";
     let proc = TakeRight::new();
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::TakeRight, Box::new(proc))
 }
 
 /// Add Swap filter as a child for the given node.
@@ -201,7 +381,7 @@ pub(crate) fn add_swap(mut caller: C, parent_id: u32) -> u32 {
     let state = caller.data_mut();
     state.called = "audio.add_swap";
     let proc = Swap::new();
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Swap, Box::new(proc))
 }
 
 /// Add Clip filter as a child for the given node.
@@ -209,12 +389,76 @@ pub(crate) fn add_clip(mut caller: C, parent_id: u32, low: f32, high: f32) -> u3
     let state = caller.data_mut();
     state.called = "audio.add_clip";
     let proc = Clip::new(low, high);
-    add_node(state, parent_id, Box::new(proc))
+    add_node(state, parent_id, NodeKind::Clip, Box::new(proc))
+}
+
+/// Add Delay (echo) filter as a child for the given node.
+///
+/// The delay time is in samples, up to half a second.
+pub(crate) fn add_delay(mut caller: C, parent_id: u32, time: u32, feedback: f32, mix: f32) -> u32 {
+    let state = caller.data_mut();
+    state.called = "audio.add_delay";
+    let proc = Delay::new(time, feedback, mix);
+    add_node(state, parent_id, NodeKind::Delay, Box::new(proc))
+}
+
+/// Add Reverb filter as a child for the given node.
+pub(crate) fn add_reverb(mut caller: C, parent_id: u32, room: f32, damping: f32, mix: f32) -> u32 {
+    let state = caller.data_mut();
+    state.called = "audio.add_reverb";
+    let proc = Reverb::new(room, damping, mix);
+    add_node(state, parent_id, NodeKind::Reverb, Box::new(proc))
+}
+
+/// Add Bitcrusher filter as a child for the given node.
+pub(crate) fn add_bitcrusher(mut caller: C, parent_id: u32, bits: u32, downsample: u32) -> u32 {
+    let state = caller.data_mut();
+    state.called = "audio.add_bitcrusher";
+    let proc = Bitcrusher::new(bits, downsample);
+    add_node(state, parent_id, NodeKind::Bitcrusher, Box::new(proc))
 }
 
-fn add_node(state: &mut State, parent_id: u32, proc: Box<dyn firefly_audio::Processor>) -> u32 {
-    match state.audio.add_node(parent_id, proc) {
-        Ok(id) => id,
+/// Add Compressor filter as a child for the given node.
+///
+/// Attack and release are in samples. Use a high ratio to get a limiter.
+pub(crate) fn add_compressor(
+    mut caller: C,
+    parent_id: u32,
+    threshold: f32,
+    ratio: f32,
+    attack: u32,
+    release: u32,
+) -> u32 {
+    let state = caller.data_mut();
+    state.called = "audio.add_compressor";
+    let proc = Compressor::new(threshold, ratio, attack, release);
+    add_node(state, parent_id, NodeKind::Compressor, Box::new(proc))
+}
+
+fn add_node(
+    state: &mut State,
+    parent_id: u32,
+    kind: NodeKind,
+    proc: Box<dyn firefly_audio::Processor>,
+) -> u32 {
+    let mut status = None;
+    let proc = if kind.is_tracked() {
+        let node_status = Rc::new(NodeStatus::default());
+        status = Some(node_status.clone());
+        Box::new(Tracked::new(proc, node_status))
+    } else {
+        proc
+    };
+    match state
+        .audio_tree
+        .add(&mut state.audio, parent_id, kind, proc)
+    {
+        Ok(id) => {
+            if let Some(status) = status {
+                state.audio_status.push((id, status));
+            }
+            id
+        }
         Err(err) => {
             state.log_error(HostError::AudioNode(err));
             0
@@ -252,47 +496,299 @@ pub(crate) fn mod_sine(mut caller: C, node_id: u32, param: u32, freq: f32, low:
     modulate(state, node_id, param, Box::new(lfo));
 }
 
-fn modulate(state: &mut State, node_id: u32, param: u32, lfo: Box<dyn modulators::Modulator>) {
-    let node = match state.audio.get_node(node_id) {
-        Ok(node) => node,
-        Err(err) => {
-            state.log_error(HostError::AudioNode(err));
-            return;
-        }
+/// Modulate a parameter of the given node using a square wave.
+pub(crate) fn mod_square(mut caller: C, node_id: u32, param: u32, freq: f32, low: f32, high: f32) {
+    let state = caller.data_mut();
+    state.called = "audio.mod_square";
+    let lfo = Lfo::new(Wave::Square, freq, low, high);
+    modulate(state, node_id, param, Box::new(lfo));
+}
+
+/// Modulate a parameter of the given node using a sawtooth wave.
+pub(crate) fn mod_saw(mut caller: C, node_id: u32, param: u32, freq: f32, low: f32, high: f32) {
+    let state = caller.data_mut();
+    state.called = "audio.mod_saw";
+    let lfo = Lfo::new(Wave::Saw, freq, low, high);
+    modulate(state, node_id, param, Box::new(lfo));
+}
+
+/// Modulate a parameter of the given node using random values (sample and hold).
+///
+/// A new random value is picked `freq` times per second.
+pub(crate) fn mod_random(mut caller: C, node_id: u32, param: u32, freq: f32, low: f32, high: f32) {
+    let state = caller.data_mut();
+    state.called = "audio.mod_random";
+    let lfo = Lfo::new(Wave::Random(state.seed), freq, low, high);
+    modulate(state, node_id, param, Box::new(lfo));
+}
+
+/// Modulate a parameter of the given node using a piecewise-linear curve.
+///
+/// The curve points are pairs of u32 time (in samples) and f32 value.
+pub(crate) fn mod_curve(mut caller: C, node_id: u32, param: u32, ptr: u32, len: u32) {
+    let state = caller.data_mut();
+    state.called = "audio.mod_curve";
+    let Some(memory) = state.memory else {
+        state.log_error(HostError::MemoryNotFound);
+        return;
+    };
+    let (data, state) = memory.data_and_store_mut(&mut caller);
+    let ptr = ptr as usize;
+    let len = len as usize;
+    let Some(end) = ptr.checked_add(len) else {
+        state.log_error(HostError::OomPointer);
+        return;
+    };
+    let Some(raw) = data.get(ptr..end) else {
+        state.log_error(HostError::OomPointer);
+        return;
     };
+    state.count_reads(len);
+    let lfo = Curve::from_bytes(raw);
+    modulate(state, node_id, param, Box::new(lfo));
+}
+
+/// Modulate a parameter of the given node using an ADSR envelope.
+///
+/// The envelope starts when its gate is opened using [`set_gate`].
+pub(crate) fn mod_envelope(
+    mut caller: C,
+    node_id: u32,
+    param: u32,
+    attack: u32,
+    decay: u32,
+    sustain: f32,
+    release: u32,
+) {
+    let state = caller.data_mut();
+    state.called = "audio.mod_envelope";
+    let gate = Rc::new(Gate::default());
+    let lfo = Envelope::new(attack, decay, sustain, release, gate.clone());
+    if modulate(state, node_id, param, Box::new(lfo)) {
+        state.gates.push((node_id, gate));
+    }
+}
+
+/// Open (trigger) or close (release) the gate of the envelope modulating the node.
+pub(crate) fn set_gate(mut caller: C, node_id: u32, open: u32) {
+    let state = caller.data_mut();
+    state.called = "audio.set_gate";
+    let gate = state.gates.iter().find(|(id, _)| *id == node_id);
+    match gate {
+        Some((_, gate)) => gate.set(open != 0),
+        None => state.log_error("the node is not modulated by an envelope"),
+    }
+}
+
+/// Set the modulator for the node param. Returns false if failed.
+fn modulate(
+    state: &mut State,
+    node_id: u32,
+    param: u32,
+    lfo: Box<dyn modulators::Modulator>,
+) -> bool {
+    if param > 8 {
+        state.log_error("param value is too high");
+        return false;
+    }
+    if let Err(err) = state.audio_tree.modulate(node_id, param as u8, lfo) {
+        state.log_error(HostError::AudioNode(err));
+        return false;
+    }
+    // The new modulator replaces the old one, and so its gate.
+    state.gates.retain(|(id, _)| *id != node_id);
+    true
+}
+
+/// Get the number of audio samples rendered since the app start.
+///
+/// Used as the time reference for scheduled events.
+pub(crate) fn get_time(mut caller: C) -> u64 {
+    let state = caller.data_mut();
+    state.called = "audio.get_time";
+    state.audio_scheduler.now()
+}
+
+/// Reset the node when the audio output reaches the given sample.
+pub(crate) fn schedule_reset(mut caller: C, node_id: u32, at: u64) {
+    let state = caller.data_mut();
+    state.called = "audio.schedule_reset";
+    schedule(state, node_id, at, Action::Reset);
+}
+
+/// Reset the node and all its children when the audio output reaches the given sample.
+pub(crate) fn schedule_reset_all(mut caller: C, node_id: u32, at: u64) {
+    let state = caller.data_mut();
+    state.called = "audio.schedule_reset_all";
+    schedule(state, node_id, at, Action::ResetAll);
+}
+
+/// Set the node parameter when the audio output reaches the given sample.
+///
+/// If the node modulator modulates the same parameter, the modulator is removed.
+/// A modulator of another parameter keeps running.
+pub(crate) fn schedule_set(mut caller: C, node_id: u32, param: u32, val: f32, at: u64) {
+    let state = caller.data_mut();
+    state.called = "audio.schedule_set";
     if param > 8 {
         state.log_error("param value is too high");
         return;
     }
-    node.modulate(param as u8, lfo);
+    schedule(state, node_id, at, Action::Set(param as u8, val));
+}
+
+/// Cancel all scheduled events for the node.
+pub(crate) fn cancel_scheduled(mut caller: C, node_id: u32) {
+    let state = caller.data_mut();
+    state.called = "audio.cancel_scheduled";
+    state.audio_scheduler.cancel(node_id);
+}
+
+fn schedule(state: &mut State, node_id: u32, at: u64, action: Action) {
+    if state.audio_tree.kind(node_id).is_none() {
+        state.log_error(HostError::UnknownNode(node_id));
+        return;
+    }
+    if !state.audio_scheduler.add(at, node_id, action) {
+        state.log_error("too many scheduled audio events");
+    }
 }
 
 /// Reset the given node.
 pub(crate) fn reset(mut caller: C, node_id: u32) {
     let state = caller.data_mut();
     state.called = "audio.reset";
-    match state.audio.get_node(node_id) {
-        Ok(node) => node.reset(),
-        Err(err) => state.log_error(HostError::AudioNode(err)),
-    };
+    if let Err(err) = state.audio_tree.reset(node_id) {
+        state.log_error(HostError::AudioNode(err));
+    }
 }
 
 /// Reset the given node and all its child nodes.
 pub(crate) fn reset_all(mut caller: C, node_id: u32) {
     let state = caller.data_mut();
     state.called = "audio.reset_all";
-    match state.audio.get_node(node_id) {
-        Ok(node) => node.reset_all(),
-        Err(err) => state.log_error(HostError::AudioNode(err)),
-    };
+    if let Err(err) = state.audio_tree.reset_all(node_id) {
+        state.log_error(HostError::AudioNode(err));
+    }
 }
 
 /// Remove all children from the node.
+///
+/// The nodes added after the removed ones get new IDs (shifted down).
 pub(crate) fn clear(mut caller: C, node_id: u32) {
     let state = caller.data_mut();
     state.called = "audio.clear";
-    let res = state.audio.clear(node_id);
-    if let Err(err) = res {
+    match state.audio_tree.clear(&mut state.audio, node_id) {
+        Ok(new_ids) => renumber(state, &new_ids),
+        Err(err) => state.log_error(HostError::AudioNode(err)),
+    }
+}
+
+/// Remove the node and all its children.
+///
+/// The nodes added after the removed ones get new IDs (shifted down).
+pub(crate) fn remove(mut caller: C, node_id: u32) {
+    let state = caller.data_mut();
+    state.called = "audio.remove";
+    if node_id == ROOT_ID {
+        state.log_error("cannot remove the root node");
+        return;
+    }
+    match state.audio_tree.remove(&mut state.audio, node_id) {
+        Ok(new_ids) => renumber(state, &new_ids),
+        Err(err) => state.log_error(HostError::AudioNode(err)),
+    }
+}
+
+/// Detach the node from its current parent and attach it to the given one.
+///
+/// The node keeps its ID, children, state, and modulator,
+/// and is played after the other children of the new parent.
+pub(crate) fn move_node(mut caller: C, node_id: u32, parent_id: u32) {
+    let state = caller.data_mut();
+    state.called = "audio.move";
+    if node_id == ROOT_ID {
+        state.log_error("cannot move the root node");
+        return;
+    }
+    if state.audio_tree.is_within(parent_id, node_id) {
+        state.log_error("cannot move the node into itself or its children");
+        return;
+    }
+    if let Err(err) = state
+        .audio_tree
+        .move_node(&mut state.audio, node_id, parent_id)
+    {
         state.log_error(HostError::AudioNode(err));
     }
 }
+
+/// Update node IDs in control handles after a part of the audio graph was removed.
+///
+/// Handles of removed nodes are dropped.
+fn renumber(state: &mut State, new_ids: &[Option<u32>]) {
+    let new_id = |id: &mut u32| match new_ids.get(*id as usize) {
+        Some(Some(new_id)) => {
+            *id = *new_id;
+            true
+        }
+        _ => false,
+    };
+    state.audio_status.retain_mut(|(id, _)| new_id(id));
+    state.trackers.retain_mut(|(id, _)| new_id(id));
+    state.gates.retain_mut(|(id, _)| new_id(id));
+    state.audio_scheduler.renumber(new_ids);
+}
+
+/// Write IDs of the direct children of the node into the buffer.
+///
+/// Each ID is a little-endian u32. Returns the number of children,
+/// even if the buffer is too small to fit all of them.
+pub(crate) fn get_children(mut caller: C, node_id: u32, buf_ptr: u32, buf_len: u32) -> u32 {
+    let state = caller.data_mut();
+    state.called = "audio.get_children";
+    let Some(memory) = state.memory else {
+        state.log_error(HostError::MemoryNotFound);
+        return 0;
+    };
+    let (data, state) = memory.data_and_store_mut(&mut caller);
+    if state.audio_tree.kind(node_id).is_none() {
+        state.log_error(HostError::UnknownNode(node_id));
+        return 0;
+    }
+    let buf_ptr = buf_ptr as usize;
+    let buf_len = buf_len as usize;
+    let Some(buf_end) = buf_ptr.checked_add(buf_len) else {
+        state.log_error(HostError::OomPointer);
+        return 0;
+    };
+    let Some(buf) = data.get_mut(buf_ptr..buf_end) else {
+        state.log_error(HostError::OomPointer);
+        return 0;
+    };
+    let mut count = 0;
+    let mut chunks = buf.chunks_exact_mut(4);
+    for child_id in state.audio_tree.children(node_id) {
+        if let Some(chunk) = chunks.next() {
+            chunk.copy_from_slice(&child_id.to_le_bytes());
+        }
+        count += 1;
+    }
+    state.count_writes(buf_len.min(count as usize * 4));
+    count
+}
+
+/// Get the type of the node.
+///
+/// Returns 0 for the root node and for unknown nodes.
+pub(crate) fn get_kind(mut caller: C, node_id: u32) -> u32 {
+    let state = caller.data_mut();
+    state.called = "audio.get_kind";
+    match state.audio_tree.kind(node_id) {
+        Some(kind) => kind as u32,
+        None => {
+            state.log_error(HostError::UnknownNode(node_id));
+            0
+        }
+    }
+}
@@ -127,6 +127,7 @@ pub(crate) fn load_file(
             return 0;
         }
     };
+    state.count_writes(file_size);
     if file_size != buf_len {
         state.log_error(HostError::BufferSize);
         return 0;
@@ -186,6 +187,7 @@ pub(crate) fn dump_file(
         state.log_error(HostError::FileWrite);
         return 0;
     };
+    state.count_reads(file_size);
     if file.flush().is_err() {
         state.log_error(HostError::FileFlush);
         return 0;
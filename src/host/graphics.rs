@@ -334,6 +334,7 @@ pub(crate) fn draw_qr(
         state.log_error(HostError::OomPointer);
         return;
     };
+    state.count_reads(text_bytes.len());
 
     // generate ASCII QR code
     let Ok(code) = tinyqr::QrCode::new(text_bytes) else {
@@ -426,6 +427,7 @@ pub(crate) fn draw_text(
         state.log_error(HostError::OomPointer);
         return;
     };
+    state.count_reads(text_bytes.len() + font_bytes.len());
     let font = match parse_font(font_bytes) {
         Ok(font) => font,
         Err(err) => {
@@ -567,6 +569,7 @@ fn draw_image_inner(mut caller: C, ptr: u32, len: u32, x: i32, y: i32, sub: Opti
         state.log_error(msg);
         return;
     }
+    state.count_reads(image_bytes.len());
     match image_bytes[0] {
         0x21 => draw_image_v1(state, image_bytes, x, y, sub),
         0x22 => draw_image_v2(state, image_bytes, x, y, sub),
@@ -1,4 +1,7 @@
+use crate::button_events::ButtonEvents;
 use crate::error::HostError;
+use crate::gestures::Direction;
+use crate::keyboard::Keyboard;
 use crate::state::{NetHandler, State};
 use alloc::boxed::Box;
 use firefly_hal::*;
@@ -36,6 +39,197 @@ pub(crate) fn read_buttons(mut caller: C, index: u32) -> u32 {
     u32::from(input.buttons)
 }
 
+/// Write button press and release events since the previous frame into the buffer.
+///
+/// Each event is 4 bytes: the button index (in the same order as in `read_buttons`),
+/// 1 if pressed or 0 if released, and the little-endian u16 time of the event
+/// in milliseconds since the previous frame. Returns the number of events,
+/// even if the buffer is too small to fit all of them.
+pub(crate) fn read_button_events(mut caller: C, index: u32, buf_ptr: u32, buf_len: u32) -> u32 {
+    let state = caller.data_mut();
+    state.called = "input.read_button_events";
+    let Some(memory) = state.memory else {
+        state.log_error(HostError::MemoryNotFound);
+        return 0;
+    };
+    let (data, state) = memory.data_and_store_mut(&mut caller);
+    let buf_ptr = buf_ptr as usize;
+    let buf_len = buf_len as usize;
+    let Some(buf_end) = buf_ptr.checked_add(buf_len) else {
+        state.log_error(HostError::OomPointer);
+        return 0;
+    };
+    let Some(buf) = data.get_mut(buf_ptr..buf_end) else {
+        state.log_error(HostError::OomPointer);
+        return 0;
+    };
+    let Some(events) = get_button_events(state, index) else {
+        return 0;
+    };
+    let mut count = 0;
+    let mut chunks = buf.chunks_exact_mut(4);
+    for event in events.events() {
+        if let Some(chunk) = chunks.next() {
+            chunk.copy_from_slice(&event.encode());
+        }
+        count += 1;
+    }
+    state.count_writes(buf_len.min(count as usize * 4));
+    count
+}
+
+/// Write the text typed on a keyboard into the buffer.
+///
+/// The text is UTF-8 encoded and never cut in the middle of a character.
+/// Returns the number of bytes written. The text that didn't fit into the buffer
+/// is kept for the next call. The keyboard is available only in the emulator
+/// (or over serial) and only in single-player, because typed text
+/// is not synchronized between devices.
+pub(crate) fn read_text(mut caller: C, buf_ptr: u32, buf_len: u32) -> u32 {
+    let state = caller.data_mut();
+    state.called = "input.read_text";
+    let Some(memory) = state.memory else {
+        state.log_error(HostError::MemoryNotFound);
+        return 0;
+    };
+    let (data, state) = memory.data_and_store_mut(&mut caller);
+    let buf_ptr = buf_ptr as usize;
+    let buf_len = buf_len as usize;
+    let Some(buf_end) = buf_ptr.checked_add(buf_len) else {
+        state.log_error(HostError::OomPointer);
+        return 0;
+    };
+    let Some(buf) = data.get_mut(buf_ptr..buf_end) else {
+        state.log_error(HostError::OomPointer);
+        return 0;
+    };
+    if let NetHandler::FrameSyncer(_) = state.net_handler.get_mut() {
+        state.text_input.clear();
+        return 0;
+    }
+    let text = state.text_input.take(buf_len);
+    let size = text.len();
+    buf[..size].copy_from_slice(text.as_bytes());
+    state.count_writes(size);
+    size as u32
+}
+
+/// Open the on-screen keyboard.
+///
+/// The charset is a string of characters the user can type. If empty,
+/// uppercase letters, digits, and basic punctuation are used. The app is paused
+/// until the user is done. Then the entered UTF-8 text (no longer than the buffer)
+/// is written into the buffer and the `handle_keyboard` callback is called
+/// with the text length. In multiplayer, the keyboard is not available
+/// and the callback is called right away with an empty text.
+pub(crate) fn open_keyboard(
+    mut caller: C,
+    charset_ptr: u32,
+    charset_len: u32,
+    buf_ptr: u32,
+    buf_len: u32,
+) {
+    let state = caller.data_mut();
+    state.called = "input.open_keyboard";
+    let Some(memory) = state.memory else {
+        state.log_error(HostError::MemoryNotFound);
+        return;
+    };
+    let (data, state) = memory.data_and_store_mut(&mut caller);
+    let charset_ptr = charset_ptr as usize;
+    let charset_len = charset_len as usize;
+    let Some(charset_end) = charset_ptr.checked_add(charset_len) else {
+        state.log_error(HostError::OomPointer);
+        return;
+    };
+    let Some(charset_bytes) = data.get(charset_ptr..charset_end) else {
+        state.log_error(HostError::OomPointer);
+        return;
+    };
+    let Ok(charset) = core::str::from_utf8(charset_bytes) else {
+        state.log_error(HostError::TextUtf8);
+        return;
+    };
+    let buf_end = (buf_ptr as usize).checked_add(buf_len as usize);
+    if buf_end.is_none_or(|end| end > data.len()) {
+        state.log_error(HostError::OomPointer);
+        return;
+    }
+    state.count_reads(charset_len);
+    if let NetHandler::FrameSyncer(_) = state.net_handler.get_mut() {
+        state.keyboard_result = Some((buf_ptr, alloc::string::String::new()));
+        return;
+    }
+    let keyboard = Keyboard::new(charset, buf_ptr, buf_len as usize);
+    state.keyboard = Some(keyboard);
+}
+
+/// Get the button events for the peer with the given ID.
+///
+/// Uses the same peer selection rules as [`get_input`].
+fn get_button_events<'a>(state: &'a mut State, index: u32) -> Option<&'a ButtonEvents> {
+    let NetHandler::FrameSyncer(_) = state.net_handler.get_mut() else {
+        return Some(&state.button_events);
+    };
+    if index > 32 {
+        return Some(&state.combined_button_events);
+    }
+    if state.peer_button_events.get(index as usize).is_none() {
+        state.log_error(HostError::UnknownPeer(index));
+        return None;
+    }
+    state.peer_button_events.get(index as usize)
+}
+
+/// Get the touch pad gesture recognized on the current frame.
+///
+/// Returns 0 if there is no gesture. Otherwise, the lowest byte is the gesture type
+/// (1 for tap, 2 for double tap, 3 for swipe), the second byte is the swipe direction
+/// (see `read_dpad`), and the highest 2 bytes are the swipe speed
+/// in pad units per second.
+pub(crate) fn read_gesture(mut caller: C, index: u32) -> u32 {
+    let state = caller.data_mut();
+    state.called = "input.read_gesture";
+    let gestures = match state.net_handler.get_mut() {
+        NetHandler::FrameSyncer(_) if index > 32 => &state.combined_gestures,
+        NetHandler::FrameSyncer(_) => {
+            let Some(gestures) = state.peer_gestures.get(index as usize) else {
+                state.log_error(HostError::UnknownPeer(index));
+                return 0;
+            };
+            gestures
+        }
+        _ => &state.gestures,
+    };
+    match gestures.current() {
+        Some(gesture) => gesture.encode(),
+        None => 0,
+    }
+}
+
+/// Get the touch pad position as one of 8 directions.
+///
+/// Returns 0 if the pad is not touched or the finger is within the dead zone.
+/// Otherwise, the directions go counter-clockwise from 1 (right) to 8 (down-right).
+pub(crate) fn read_dpad(mut caller: C, index: u32) -> u32 {
+    let state = caller.data_mut();
+    state.called = "input.read_dpad";
+    let Some(input) = get_input(state, index) else {
+        return 0;
+    };
+    let Some(pad) = input.pad else {
+        return 0;
+    };
+    Direction::from_pad(&pad, state.dead_zone) as u32
+}
+
+/// Set the radius of the touch pad center ignored by `read_dpad`.
+pub(crate) fn set_dead_zone(mut caller: C, radius: u32) {
+    let state = caller.data_mut();
+    state.called = "input.set_dead_zone";
+    state.dead_zone = radius.min(u32::from(u16::MAX)) as u16;
+}
+
 /// Get the input for the peer with the given ID.
 ///
 /// Automatically picks between local input, peer input, or combined input.
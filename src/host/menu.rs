@@ -1,4 +1,5 @@
 use crate::error::HostError;
+use crate::menu::ItemKind;
 use crate::state::State;
 use alloc::boxed::Box;
 use alloc::string::ToString;
@@ -8,6 +9,64 @@ type C<'a, 'b> = wasmi::Caller<'a, Box<State<'b>>>;
 pub(crate) fn add_menu_item(mut caller: C, index: u32, name_ptr: u32, name_len: u32) {
     let state = caller.data_mut();
     state.called = "menu.add_menu_item";
+    add_item(caller, index, name_ptr, name_len, ItemKind::Button);
+}
+
+/// Add a menu item that opens a nested menu.
+///
+/// Items are placed into the submenu using [`set_parent`].
+pub(crate) fn add_submenu(mut caller: C, index: u32, name_ptr: u32, name_len: u32) {
+    let state = caller.data_mut();
+    state.called = "menu.add_submenu";
+    add_item(caller, index, name_ptr, name_len, ItemKind::Submenu);
+}
+
+/// Add a menu item that is switched on and off when selected.
+pub(crate) fn add_toggle(mut caller: C, index: u32, name_ptr: u32, name_len: u32, on: u32) {
+    let state = caller.data_mut();
+    state.called = "menu.add_toggle";
+    add_item(caller, index, name_ptr, name_len, ItemKind::Toggle(on != 0));
+}
+
+/// Add a menu item with a number that the user can change in the given range.
+///
+/// Each press left or right changes the value by the given step.
+/// The step of zero is treated as one.
+#[expect(clippy::too_many_arguments)]
+pub(crate) fn add_slider(
+    mut caller: C,
+    index: u32,
+    name_ptr: u32,
+    name_len: u32,
+    value: u32,
+    min: u32,
+    max: u32,
+    step: u32,
+) {
+    let state = caller.data_mut();
+    state.called = "menu.add_slider";
+    let max = max.max(min);
+    let value = value.clamp(min, max);
+    let step = step.max(1);
+    let kind = ItemKind::Slider {
+        value,
+        min,
+        max,
+        step,
+    };
+    add_item(caller, index, name_ptr, name_len, kind);
+}
+
+/// Add a line separating groups of menu items.
+pub(crate) fn add_separator(mut caller: C, index: u32) {
+    let state = caller.data_mut();
+    state.called = "menu.add_separator";
+    let name = alloc::string::String::new();
+    state.menu.add(index as u8, name, ItemKind::Separator);
+}
+
+fn add_item(mut caller: C, index: u32, name_ptr: u32, name_len: u32, kind: ItemKind) {
+    let state = caller.data_mut();
     let Some(memory) = state.memory else {
         state.log_error(HostError::MemoryNotFound);
         return;
@@ -24,7 +83,7 @@ pub(crate) fn add_menu_item(mut caller: C, index: u32, name_ptr: u32, name_len:
         return;
     };
 
-    state.menu.add(index as u8, name.to_string())
+    state.menu.add(index as u8, name.to_string(), kind)
 }
 
 pub(crate) fn remove_menu_item(mut caller: C, index: u32) {
@@ -33,6 +92,49 @@ pub(crate) fn remove_menu_item(mut caller: C, index: u32) {
     state.menu.remove(index as u8);
 }
 
+/// Show the menu item inside of the given submenu.
+pub(crate) fn set_parent(mut caller: C, index: u32, parent: u32) {
+    let state = caller.data_mut();
+    state.called = "menu.set_parent";
+    if state.menu.get_mut(index as u8).is_none() {
+        state.log_error(HostError::UnknownMenuItem(index));
+        return;
+    }
+    // The parent must be a submenu and must not be inside of the item.
+    if !state.menu.set_parent(index as u8, parent as u8) {
+        state.log_error(HostError::UnknownMenuItem(parent));
+    }
+}
+
+/// Disable or enable the menu item.
+pub(crate) fn set_disabled(mut caller: C, index: u32, disabled: u32) {
+    let state = caller.data_mut();
+    state.called = "menu.set_disabled";
+    let Some(item) = state.menu.get_mut(index as u8) else {
+        state.log_error(HostError::UnknownMenuItem(index));
+        return;
+    };
+    item.disabled = disabled != 0;
+}
+
+/// Set the state of a toggle or the value of a slider.
+pub(crate) fn set_value(mut caller: C, index: u32, value: u32) {
+    let state = caller.data_mut();
+    state.called = "menu.set_value";
+    let Some(item) = state.menu.get_mut(index as u8) else {
+        state.log_error(HostError::UnknownMenuItem(index));
+        return;
+    };
+    item.set_value(value);
+}
+
+/// Show the paused app frame behind the menu instead of a blank screen.
+pub(crate) fn set_overlay(mut caller: C, enabled: u32) {
+    let state = caller.data_mut();
+    state.called = "menu.set_overlay";
+    state.menu.set_overlay(enabled != 0);
+}
+
 pub(crate) fn open_menu(mut caller: C) {
     let state = caller.data_mut();
     state.called = "menu.open_menu";
@@ -21,6 +21,7 @@ pub(crate) fn log_debug(mut caller: C, ptr: u32, len: u32) {
         state.log_error(HostError::OomPointer);
         return;
     };
+    state.count_reads(bytes.len());
     let Ok(text) = core::str::from_utf8(bytes) else {
         state.log_error(HostError::TextUtf8);
         return;
@@ -43,6 +44,7 @@ pub(crate) fn log_error(mut caller: C, ptr: u32, len: u32) {
         state.log_error(HostError::OomPointer);
         return;
     };
+    state.count_reads(bytes.len());
     let Ok(text) = core::str::from_utf8(bytes) else {
         state.log_error(HostError::TextUtf8);
         return;
@@ -146,7 +148,9 @@ pub(crate) fn get_name(mut caller: C, index: u32, ptr: u32) -> u32 {
         return 0;
     };
     buf.copy_from_slice(name.as_bytes());
-    name.len() as u32
+    let name_len = name.len() as u32;
+    state.count_writes(len);
+    name_len
 }
 
 /// Get packed (some) system settings of the peer.
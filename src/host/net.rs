@@ -71,6 +71,7 @@ pub(crate) fn save_stash(mut caller: C, peer_id: u32, buf_ptr: u32, buf_len: u32
         state.log_error("stash size cannot exceed 80 bytes");
         return;
     }
+    state.count_reads(buf.len());
 
     let mut handler = state.net_handler.replace(NetHandler::None);
     let peer = get_friend(&mut handler, peer_id);
@@ -116,13 +117,16 @@ pub(crate) fn load_stash(mut caller: C, peer_id: u32, buf_ptr: u32, buf_len: u32
         None => &state.stash,
     };
     let stash_len = stash.len();
-    if stash_len > buf.len() {
+    let written = if stash_len > buf.len() {
         state.log_error("the buffer is not big enough to fit stash");
         buf.copy_from_slice(&stash[..buf.len()]);
+        buf.len()
     } else {
         buf[..stash_len].copy_from_slice(&stash[..]);
+        stash_len
     };
     state.net_handler.replace(handler);
+    state.count_writes(written);
     stash_len as u32
 }
 
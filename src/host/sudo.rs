@@ -170,6 +170,7 @@ fn list_entries(
     if let Err(err) = res {
         state.log_error(err);
     }
+    state.count_writes(pos);
     pos as u32
 }
 
@@ -318,6 +319,7 @@ pub(crate) fn load_file(
             return 0;
         }
     };
+    state.count_writes(file_size);
     if file_size != buf_len as usize {
         state.log_error(HostError::BufferSize);
         return 0;
@@ -384,6 +386,7 @@ pub(crate) fn dump_file(
             return 0;
         }
     };
+    state.count_reads(file_size);
     if file.flush().is_err() {
         state.log_error(HostError::FileFlush);
         return 0;
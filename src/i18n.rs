@@ -0,0 +1,487 @@
+use embedded_graphics::mono_font::{iso_8859_1, iso_8859_2, iso_8859_5, MonoFont};
+
+/// The language of the system UI, based on the device settings.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub(crate) enum Lang {
+    #[default]
+    English,
+    Dutch,
+    French,
+    German,
+    Italian,
+    Polish,
+    Russian,
+    Spanish,
+    Swedish,
+    Ukrainian,
+}
+
+impl Lang {
+    /// Get the language from the ISO 639-1 code stored in the settings.
+    ///
+    /// Falls back to English for unsupported languages.
+    pub fn from_code(code: [u8; 2]) -> Self {
+        match code.map(|b| b.to_ascii_lowercase()) {
+            [b'n', b'l'] => Self::Dutch,
+            [b'f', b'r'] => Self::French,
+            [b'd', b'e'] => Self::German,
+            [b'i', b't'] => Self::Italian,
+            [b'p', b'l'] => Self::Polish,
+            [b'r', b'u'] => Self::Russian,
+            [b'e', b's'] => Self::Spanish,
+            [b's', b'v'] => Self::Swedish,
+            [b'u', b'k'] => Self::Ukrainian,
+            _ => Self::English,
+        }
+    }
+
+    /// The font covering all letters of the language.
+    ///
+    /// All fonts have the same size and include all ASCII characters.
+    pub fn font(self) -> &'static MonoFont<'static> {
+        match self {
+            Self::Polish => &iso_8859_2::FONT_6X9,
+            Self::Russian | Self::Ukrainian => &iso_8859_5::FONT_6X9,
+            _ => &iso_8859_1::FONT_6X9,
+        }
+    }
+}
+
+/// A translatable string of the system UI.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Message {
+    Volume,
+    InputSettings,
+    Back,
+    ScreenShot,
+    Restart,
+    Quit,
+    SwapAB,
+    HoldToToggle,
+    Turbo,
+    PadSensitivity,
+    InvertX,
+    InvertY,
+    UseForAllApps,
+    On,
+    Off,
+    All,
+    /// The button closing the error scene.
+    OhNo,
+    /// What happens with the app audio when the menu is open.
+    MenuAudio,
+    Pause,
+    Duck,
+    /// Mixing both audio channels into one.
+    Mono,
+    /// The label of the app ID in the error report.
+    App,
+    /// The label of the runtime version in the error report.
+    Runtime,
+    /// The label of the page number in the error report.
+    Page,
+    IncompatibleVersions,
+    PeerListFull,
+    Disconnected,
+}
+
+impl Message {
+    pub fn translate(self, lang: Lang) -> &'static str {
+        match lang {
+            Lang::English => self.english(),
+            Lang::Dutch => self.dutch(),
+            Lang::French => self.french(),
+            Lang::German => self.german(),
+            Lang::Italian => self.italian(),
+            Lang::Polish => self.polish(),
+            Lang::Russian => self.russian(),
+            Lang::Spanish => self.spanish(),
+            Lang::Swedish => self.swedish(),
+            Lang::Ukrainian => self.ukrainian(),
+        }
+    }
+
+    const fn english(self) -> &'static str {
+        match self {
+            Self::Volume => "volume",
+            Self::InputSettings => "input settings",
+            Self::Back => "back",
+            Self::ScreenShot => "take screenshot",
+            Self::Restart => "restart app",
+            Self::Quit => "exit app",
+            Self::SwapAB => "swap A/B",
+            Self::HoldToToggle => "hold-to-toggle",
+            Self::Turbo => "turbo",
+            Self::PadSensitivity => "pad sensitivity",
+            Self::InvertX => "invert pad X",
+            Self::InvertY => "invert pad Y",
+            Self::UseForAllApps => "use for all apps",
+            Self::On => "on",
+            Self::Off => "off",
+            Self::All => "all",
+            Self::OhNo => "oh no!",
+            Self::MenuAudio => "audio in menu",
+            Self::Pause => "pause",
+            Self::Duck => "quieter",
+            Self::Mono => "mono audio",
+            Self::App => "app",
+            Self::Runtime => "runtime",
+            Self::Page => "page",
+            Self::IncompatibleVersions => "devices have incompatible OS versions; please, update.",
+            Self::PeerListFull => "cannot connect more devices",
+            Self::Disconnected => "device disconnected",
+        }
+    }
+
+    const fn dutch(self) -> &'static str {
+        match self {
+            Self::Volume => "volume",
+            Self::InputSettings => "invoerinstellingen",
+            Self::Back => "terug",
+            Self::ScreenShot => "schermafbeelding maken",
+            Self::Restart => "app herstarten",
+            Self::Quit => "app afsluiten",
+            Self::SwapAB => "A/B wisselen",
+            Self::HoldToToggle => "vasthouden",
+            Self::Turbo => "turbo",
+            Self::PadSensitivity => "pad-gevoeligheid",
+            Self::InvertX => "pad X omkeren",
+            Self::InvertY => "pad Y omkeren",
+            Self::UseForAllApps => "voor alle apps gebruiken",
+            Self::On => "aan",
+            Self::Off => "uit",
+            Self::All => "alle",
+            Self::OhNo => "oh nee!",
+            Self::MenuAudio => "geluid in menu",
+            Self::Pause => "pauze",
+            Self::Duck => "zachter",
+            Self::Mono => "mono geluid",
+            Self::App => "app",
+            Self::Runtime => "runtime",
+            Self::Page => "pagina",
+            Self::IncompatibleVersions => "apparaten hebben incompatibele OS-versies; werk ze bij.",
+            Self::PeerListFull => "kan geen apparaten meer verbinden",
+            Self::Disconnected => "apparaat losgekoppeld",
+        }
+    }
+
+    const fn french(self) -> &'static str {
+        match self {
+            Self::Volume => "volume",
+            Self::InputSettings => "paramètres des commandes",
+            Self::Back => "retour",
+            Self::ScreenShot => "capture d'écran",
+            Self::Restart => "redémarrer l'app",
+            Self::Quit => "quitter l'app",
+            Self::SwapAB => "inverser A/B",
+            Self::HoldToToggle => "maintien automatique",
+            Self::Turbo => "turbo",
+            Self::PadSensitivity => "sensibilité du pad",
+            Self::InvertX => "inverser le pad X",
+            Self::InvertY => "inverser le pad Y",
+            Self::UseForAllApps => "pour toutes les apps",
+            Self::On => "oui",
+            Self::Off => "non",
+            Self::All => "tous",
+            Self::OhNo => "oh non !",
+            Self::MenuAudio => "son dans le menu",
+            Self::Pause => "pause",
+            Self::Duck => "plus bas",
+            Self::Mono => "son mono",
+            Self::App => "app",
+            Self::Runtime => "système",
+            Self::Page => "page",
+            Self::IncompatibleVersions => {
+                "les appareils ont des versions d'OS incompatibles ; mettez-les à jour."
+            }
+            Self::PeerListFull => "impossible de connecter plus d'appareils",
+            Self::Disconnected => "appareil déconnecté",
+        }
+    }
+
+    const fn german(self) -> &'static str {
+        match self {
+            Self::Volume => "Lautstärke",
+            Self::InputSettings => "Eingabe-Einstellungen",
+            Self::Back => "zurück",
+            Self::ScreenShot => "Screenshot machen",
+            Self::Restart => "App neu starten",
+            Self::Quit => "App beenden",
+            Self::SwapAB => "A/B tauschen",
+            Self::HoldToToggle => "Dauerdruck",
+            Self::Turbo => "Turbo",
+            Self::PadSensitivity => "Pad-Empfindlichkeit",
+            Self::InvertX => "Pad X umkehren",
+            Self::InvertY => "Pad Y umkehren",
+            Self::UseForAllApps => "für alle Apps nutzen",
+            Self::On => "an",
+            Self::Off => "aus",
+            Self::All => "alle",
+            Self::OhNo => "oh nein!",
+            Self::MenuAudio => "Ton im Menü",
+            Self::Pause => "Pause",
+            Self::Duck => "leiser",
+            Self::Mono => "Mono-Ton",
+            Self::App => "App",
+            Self::Runtime => "Laufzeit",
+            Self::Page => "Seite",
+            Self::IncompatibleVersions => {
+                "Geräte haben inkompatible OS-Versionen; bitte aktualisieren."
+            }
+            Self::PeerListFull => "keine weiteren Geräte möglich",
+            Self::Disconnected => "Gerät getrennt",
+        }
+    }
+
+    const fn italian(self) -> &'static str {
+        match self {
+            Self::Volume => "volume",
+            Self::InputSettings => "impostazioni comandi",
+            Self::Back => "indietro",
+            Self::ScreenShot => "cattura schermo",
+            Self::Restart => "riavvia app",
+            Self::Quit => "esci dall'app",
+            Self::SwapAB => "scambia A/B",
+            Self::HoldToToggle => "tieni premuto",
+            Self::Turbo => "turbo",
+            Self::PadSensitivity => "sensibilità pad",
+            Self::InvertX => "inverti pad X",
+            Self::InvertY => "inverti pad Y",
+            Self::UseForAllApps => "usa per tutte le app",
+            Self::On => "sì",
+            Self::Off => "no",
+            Self::All => "tutti",
+            Self::OhNo => "oh no!",
+            Self::MenuAudio => "audio nel menu",
+            Self::Pause => "pausa",
+            Self::Duck => "più basso",
+            Self::Mono => "audio mono",
+            Self::App => "app",
+            Self::Runtime => "runtime",
+            Self::Page => "pagina",
+            Self::IncompatibleVersions => {
+                "i dispositivi hanno versioni del sistema incompatibili; aggiornali."
+            }
+            Self::PeerListFull => "impossibile collegare altri dispositivi",
+            Self::Disconnected => "dispositivo disconnesso",
+        }
+    }
+
+    const fn polish(self) -> &'static str {
+        match self {
+            Self::Volume => "głośność",
+            Self::InputSettings => "ustawienia sterowania",
+            Self::Back => "wstecz",
+            Self::ScreenShot => "zrzut ekranu",
+            Self::Restart => "uruchom ponownie",
+            Self::Quit => "wyjdź z aplikacji",
+            Self::SwapAB => "zamień A/B",
+            Self::HoldToToggle => "przytrzymanie",
+            Self::Turbo => "turbo",
+            Self::PadSensitivity => "czułość pada",
+            Self::InvertX => "odwróć pad X",
+            Self::InvertY => "odwróć pad Y",
+            Self::UseForAllApps => "użyj dla wszystkich",
+            Self::On => "wł.",
+            Self::Off => "wył.",
+            Self::All => "wszystkie",
+            Self::OhNo => "o nie!",
+            Self::MenuAudio => "dźwięk w menu",
+            Self::Pause => "pauza",
+            Self::Duck => "ciszej",
+            Self::Mono => "dźwięk mono",
+            Self::App => "aplikacja",
+            Self::Runtime => "system",
+            Self::Page => "strona",
+            Self::IncompatibleVersions => {
+                "urządzenia mają niezgodne wersje systemu; zaktualizuj je."
+            }
+            Self::PeerListFull => "nie można połączyć więcej urządzeń",
+            Self::Disconnected => "urządzenie rozłączone",
+        }
+    }
+
+    const fn russian(self) -> &'static str {
+        match self {
+            Self::Volume => "громкость",
+            Self::InputSettings => "настройки управления",
+            Self::Back => "назад",
+            Self::ScreenShot => "сделать скриншот",
+            Self::Restart => "перезапустить",
+            Self::Quit => "выйти",
+            Self::SwapAB => "поменять A/B",
+            Self::HoldToToggle => "залипание",
+            Self::Turbo => "турбо",
+            Self::PadSensitivity => "чувствительность",
+            Self::InvertX => "инвертировать X",
+            Self::InvertY => "инвертировать Y",
+            Self::UseForAllApps => "для всех приложений",
+            Self::On => "вкл",
+            Self::Off => "выкл",
+            Self::All => "все",
+            Self::OhNo => "ой-ой!",
+            Self::MenuAudio => "звук в меню",
+            Self::Pause => "пауза",
+            Self::Duck => "тише",
+            Self::Mono => "моно звук",
+            Self::App => "приложение",
+            Self::Runtime => "система",
+            Self::Page => "стр.",
+            Self::IncompatibleVersions => "у устройств несовместимые версии ОС; обновите их.",
+            Self::PeerListFull => "нельзя подключить больше устройств",
+            Self::Disconnected => "устройство отключено",
+        }
+    }
+
+    const fn spanish(self) -> &'static str {
+        match self {
+            Self::Volume => "volumen",
+            Self::InputSettings => "ajustes de control",
+            Self::Back => "atrás",
+            Self::ScreenShot => "captura de pantalla",
+            Self::Restart => "reiniciar app",
+            Self::Quit => "salir de la app",
+            Self::SwapAB => "intercambiar A/B",
+            Self::HoldToToggle => "mantener pulsado",
+            Self::Turbo => "turbo",
+            Self::PadSensitivity => "sensibilidad del pad",
+            Self::InvertX => "invertir pad X",
+            Self::InvertY => "invertir pad Y",
+            Self::UseForAllApps => "usar en todas las apps",
+            Self::On => "sí",
+            Self::Off => "no",
+            Self::All => "todos",
+            Self::OhNo => "¡oh no!",
+            Self::MenuAudio => "sonido en menú",
+            Self::Pause => "pausa",
+            Self::Duck => "más bajo",
+            Self::Mono => "audio mono",
+            Self::App => "app",
+            Self::Runtime => "sistema",
+            Self::Page => "página",
+            Self::IncompatibleVersions => {
+                "los dispositivos tienen versiones de SO incompatibles; actualízalos."
+            }
+            Self::PeerListFull => "no se pueden conectar más dispositivos",
+            Self::Disconnected => "dispositivo desconectado",
+        }
+    }
+
+    const fn swedish(self) -> &'static str {
+        match self {
+            Self::Volume => "volym",
+            Self::InputSettings => "kontrollinställningar",
+            Self::Back => "tillbaka",
+            Self::ScreenShot => "ta skärmbild",
+            Self::Restart => "starta om appen",
+            Self::Quit => "avsluta appen",
+            Self::SwapAB => "byt A/B",
+            Self::HoldToToggle => "låsta knappar",
+            Self::Turbo => "turbo",
+            Self::PadSensitivity => "pad-känslighet",
+            Self::InvertX => "invertera pad X",
+            Self::InvertY => "invertera pad Y",
+            Self::UseForAllApps => "använd för alla appar",
+            Self::On => "på",
+            Self::Off => "av",
+            Self::All => "alla",
+            Self::OhNo => "åh nej!",
+            Self::MenuAudio => "ljud i menyn",
+            Self::Pause => "paus",
+            Self::Duck => "tystare",
+            Self::Mono => "monoljud",
+            Self::App => "app",
+            Self::Runtime => "körmiljö",
+            Self::Page => "sida",
+            Self::IncompatibleVersions => "enheterna har inkompatibla OS-versioner; uppdatera dem.",
+            Self::PeerListFull => "kan inte ansluta fler enheter",
+            Self::Disconnected => "enheten kopplades från",
+        }
+    }
+
+    const fn ukrainian(self) -> &'static str {
+        match self {
+            Self::Volume => "гучність",
+            Self::InputSettings => "налаштування керування",
+            Self::Back => "назад",
+            Self::ScreenShot => "зробити знімок",
+            Self::Restart => "перезапустити",
+            Self::Quit => "вийти",
+            Self::SwapAB => "поміняти A/B",
+            Self::HoldToToggle => "залипання",
+            Self::Turbo => "турбо",
+            Self::PadSensitivity => "чутливість",
+            Self::InvertX => "інвертувати X",
+            Self::InvertY => "інвертувати Y",
+            Self::UseForAllApps => "для всіх застосунків",
+            Self::On => "увімк",
+            Self::Off => "вимк",
+            Self::All => "усі",
+            Self::OhNo => "ой-ой!",
+            Self::MenuAudio => "звук у меню",
+            Self::Pause => "пауза",
+            Self::Duck => "тихіше",
+            Self::Mono => "моно звук",
+            Self::App => "застосунок",
+            Self::Runtime => "система",
+            Self::Page => "стор.",
+            Self::IncompatibleVersions => "пристрої мають несумісні версії ОС; оновіть їх.",
+            Self::PeerListFull => "не можна під'єднати більше пристроїв",
+            Self::Disconnected => "пристрій від'єднано",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_code() {
+        assert_eq!(Lang::from_code(*b"de"), Lang::German);
+        assert_eq!(Lang::from_code(*b"UK"), Lang::Ukrainian);
+        assert_eq!(Lang::from_code(*b"xx"), Lang::English);
+        assert_eq!(Lang::from_code([0, 0]), Lang::English);
+    }
+
+    #[test]
+    fn test_translate() {
+        assert_eq!(Message::Quit.translate(Lang::English), "exit app");
+        assert_eq!(Message::Quit.translate(Lang::German), "App beenden");
+    }
+
+    /// All characters of the translations must be covered by the font of the language.
+    #[test]
+    fn test_font_coverage() {
+        let langs = [
+            Lang::English,
+            Lang::Dutch,
+            Lang::French,
+            Lang::German,
+            Lang::Italian,
+            Lang::Polish,
+            Lang::Russian,
+            Lang::Spanish,
+            Lang::Swedish,
+            Lang::Ukrainian,
+        ];
+        for lang in langs {
+            let mapping = lang.font().glyph_mapping;
+            // Unknown characters are rendered as "?".
+            let unknown = mapping.index('?');
+            for msg in [
+                Message::InputSettings,
+                Message::UseForAllApps,
+                Message::OhNo,
+                Message::Runtime,
+                Message::IncompatibleVersions,
+                Message::PeerListFull,
+                Message::Disconnected,
+            ] {
+                for ch in msg.translate(lang).chars() {
+                    assert_ne!(mapping.index(ch), unknown, "{lang:?}: {ch}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,335 @@
+//! Instrument wasm binaries for the sampling profiler.
+//!
+//! Wasmi doesn't expose the call stack of a paused call. So, when the app
+//! is loaded with sampling enabled, every function body gets a prologue
+//! that stores the index of the function into an exported global.
+//! The same store is repeated after every call so that the global points
+//! to the caller again when the callee returns. When the profiler interrupts
+//! the guest, the global tells which function is currently running.
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::ops::Range;
+use wasmparser::{BinaryReaderError, KnownCustom, Name, Operator, Parser, Payload, TypeRef};
+
+/// The name of the exported global holding the index of the running function.
+pub(crate) const CURRENT_FUNC: &str = "__firefly_func";
+
+const GLOBAL_SECTION: u8 = 6;
+const EXPORT_SECTION: u8 = 7;
+const CODE_SECTION: u8 = 10;
+
+/// The mutable i32 global initialized with -1 (no function is running).
+const NEW_GLOBAL: [u8; 5] = [0x7F, 0x01, 0x41, 0x7F, 0x0B];
+const EXPORT_GLOBAL: u8 = 0x03;
+const I32_CONST: u8 = 0x41;
+const GLOBAL_SET: u8 = 0x24;
+
+pub(crate) struct Instrumented {
+    /// The instrumented wasm binary.
+    pub wasm: Vec<u8>,
+    /// Function names from the "name" custom section.
+    pub names: BTreeMap<u32, String>,
+}
+
+/// Insert tracking of the running function into the given wasm binary.
+///
+/// Indices of all functions and existing globals are preserved.
+pub(crate) fn instrument(wasm: &[u8]) -> Result<Instrumented, BinaryReaderError> {
+    let mut out = Vec::with_capacity(wasm.len() + wasm.len() / 8);
+    let mut names = BTreeMap::new();
+    let mut imported_funcs = 0;
+    let mut imported_globals = 0;
+    let mut global_idx = None;
+    let mut exported = false;
+    for payload in Parser::new(0).parse_all(wasm) {
+        let payload = payload?;
+        match &payload {
+            Payload::Version { range, .. } => out.extend_from_slice(&wasm[range.clone()]),
+            Payload::ImportSection(reader) => {
+                for import in reader.clone() {
+                    match import?.ty {
+                        TypeRef::Func(_) => imported_funcs += 1,
+                        TypeRef::Global(_) => imported_globals += 1,
+                        _ => {}
+                    }
+                }
+            }
+            Payload::CustomSection(reader) => {
+                if let KnownCustom::Name(reader) = reader.as_known() {
+                    for name in reader {
+                        if let Name::Function(map) = name? {
+                            for naming in map {
+                                let naming = naming?;
+                                names.insert(naming.index, naming.name.to_string());
+                            }
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        let Some((id, range)) = payload.as_section() else {
+            continue;
+        };
+        // Insert the missing global and export sections at their place.
+        if id != 0 && global_idx.is_none() && rank(id) > rank(GLOBAL_SECTION) {
+            global_idx = Some(imported_globals);
+            write_section(&mut out, GLOBAL_SECTION, &[&[1], &NEW_GLOBAL]);
+        }
+        if id != 0 && !exported && rank(id) > rank(EXPORT_SECTION) {
+            if let Some(idx) = global_idx {
+                exported = true;
+                write_section(&mut out, EXPORT_SECTION, &[&[1], &export(idx)]);
+            }
+        }
+        match &payload {
+            Payload::GlobalSection(reader) => {
+                let idx = imported_globals + reader.count();
+                global_idx = Some(idx);
+                let items = &wasm[reader.original_position()..range.end];
+                let count = uleb(reader.count() + 1);
+                write_section(&mut out, GLOBAL_SECTION, &[&count, items, &NEW_GLOBAL]);
+            }
+            Payload::ExportSection(reader) => {
+                let Some(idx) = global_idx else {
+                    // Unreachable for valid modules: the global section
+                    // is always inserted before the export section.
+                    write_raw_section(&mut out, id, &wasm[range]);
+                    continue;
+                };
+                exported = true;
+                let items = &wasm[reader.original_position()..range.end];
+                let count = uleb(reader.count() + 1);
+                write_section(&mut out, EXPORT_SECTION, &[&count, items, &export(idx)]);
+            }
+            Payload::CodeSectionStart { count, .. } => {
+                let Some(idx) = global_idx else {
+                    write_raw_section(&mut out, id, &wasm[range]);
+                    continue;
+                };
+                let code = instrument_code(wasm, range, *count, imported_funcs, idx)?;
+                write_section(&mut out, CODE_SECTION, &[&code]);
+            }
+            _ => write_raw_section(&mut out, id, &wasm[range]),
+        }
+    }
+    // The module has no sections after the global or export section.
+    if global_idx.is_none() {
+        global_idx = Some(imported_globals);
+        write_section(&mut out, GLOBAL_SECTION, &[&[1], &NEW_GLOBAL]);
+    }
+    if let (false, Some(idx)) = (exported, global_idx) {
+        write_section(&mut out, EXPORT_SECTION, &[&[1], &export(idx)]);
+    }
+    Ok(Instrumented { wasm: out, names })
+}
+
+/// Instrument all function bodies, returning the new content of the code section.
+fn instrument_code(
+    wasm: &[u8],
+    range: Range<usize>,
+    count: u32,
+    imported_funcs: u32,
+    global_idx: u32,
+) -> Result<Vec<u8>, BinaryReaderError> {
+    let reader = wasmparser::BinaryReader::new(&wasm[range.clone()], range.start);
+    let reader = wasmparser::CodeSectionReader::new(reader)?;
+    let mut code = uleb(count);
+    let mut body_buf = Vec::new();
+    for (i, body) in reader.into_iter().enumerate() {
+        let body = body?;
+        let func_idx = imported_funcs + i as u32;
+        let mut track = Vec::with_capacity(8);
+        track.push(I32_CONST);
+        track.extend(sleb(func_idx as i32));
+        track.push(GLOBAL_SET);
+        track.extend(uleb(global_idx));
+
+        let body_range = body.range();
+        let mut ops = body.get_operators_reader()?;
+        body_buf.clear();
+        // Copy the locals and then set the global at the function start.
+        let mut copied = ops.original_position();
+        body_buf.extend_from_slice(&wasm[body_range.start..copied]);
+        body_buf.extend_from_slice(&track);
+        while !ops.eof() {
+            let op = ops.read()?;
+            let is_call = matches!(
+                op,
+                Operator::Call { .. } | Operator::CallIndirect { .. } | Operator::CallRef { .. }
+            );
+            if is_call {
+                let end = ops.original_position();
+                body_buf.extend_from_slice(&wasm[copied..end]);
+                body_buf.extend_from_slice(&track);
+                copied = end;
+            }
+        }
+        body_buf.extend_from_slice(&wasm[copied..body_range.end]);
+        code.extend(uleb(body_buf.len() as u32));
+        code.extend_from_slice(&body_buf);
+    }
+    Ok(code)
+}
+
+/// The position of the section in a valid module.
+const fn rank(id: u8) -> u8 {
+    match id {
+        // Tag section goes between memory and global sections.
+        13 => 6,
+        1..=5 => id,
+        6..=9 => id + 1,
+        // Data count section goes between element and code sections.
+        12 => 11,
+        10 | 11 => id + 2,
+        _ => u8::MAX,
+    }
+}
+
+/// Encode the export entry for the global tracking the running function.
+fn export(global_idx: u32) -> Vec<u8> {
+    let mut buf = uleb(CURRENT_FUNC.len() as u32);
+    buf.extend_from_slice(CURRENT_FUNC.as_bytes());
+    buf.push(EXPORT_GLOBAL);
+    buf.extend(uleb(global_idx));
+    buf
+}
+
+fn write_raw_section(out: &mut Vec<u8>, id: u8, content: &[u8]) {
+    write_section(out, id, &[content]);
+}
+
+fn write_section(out: &mut Vec<u8>, id: u8, parts: &[&[u8]]) {
+    let size: usize = parts.iter().map(|p| p.len()).sum();
+    out.push(id);
+    out.extend(uleb(size as u32));
+    for part in parts {
+        out.extend_from_slice(part);
+    }
+}
+
+fn uleb(mut val: u32) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(5);
+    loop {
+        let byte = (val & 0x7F) as u8;
+        val >>= 7;
+        if val == 0 {
+            buf.push(byte);
+            return buf;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn sleb(mut val: i32) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(5);
+    loop {
+        let byte = (val & 0x7F) as u8;
+        val >>= 7;
+        let done = (val == 0 && byte & 0x40 == 0) || (val == -1 && byte & 0x40 != 0);
+        if done {
+            buf.push(byte);
+            return buf;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A module with an imported function, two defined functions,
+    /// and a name section for the defined ones:
+    ///
+    /// ```wat
+    /// (module
+    ///   (import "misc" "log" (func))
+    ///   (func $update call $helper call 0)
+    ///   (func $helper)
+    ///   (export "update" (func $update)))
+    /// ```
+    const MODULE: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6D, 0x01, 0x00, 0x00, 0x00, // header
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type section: () -> ()
+        0x02, 0x0C, 0x01, 0x04, b'm', b'i', b's', b'c', 0x03, b'l', b'o', b'g', 0x00,
+        0x00, // import section
+        0x03, 0x03, 0x02, 0x00, 0x00, // function section
+        0x07, 0x0A, 0x01, 0x06, b'u', b'p', b'd', b'a', b't', b'e', 0x00,
+        0x01, // export section
+        0x0A, 0x0B, 0x02, // code section
+        0x06, 0x00, 0x10, 0x02, 0x10, 0x00, 0x0B, // update
+        0x02, 0x00, 0x0B, // helper
+        0x00, 0x18, 0x04, b'n', b'a', b'm', b'e', // name section
+        0x01, 0x11, 0x02, // function names
+        0x01, 0x06, b'u', b'p', b'd', b'a', b't', b'e', //
+        0x02, 0x06, b'h', b'e', b'l', b'p', b'e', b'r',
+    ];
+
+    #[test]
+    fn test_leb() {
+        assert_eq!(uleb(0), [0x00]);
+        assert_eq!(uleb(127), [0x7F]);
+        assert_eq!(uleb(128), [0x80, 0x01]);
+        assert_eq!(uleb(624485), [0xE5, 0x8E, 0x26]);
+        assert_eq!(sleb(0), [0x00]);
+        assert_eq!(sleb(-1), [0x7F]);
+        assert_eq!(sleb(63), [0x3F]);
+        assert_eq!(sleb(64), [0xC0, 0x00]);
+        assert_eq!(sleb(-123456), [0xC0, 0xBB, 0x78]);
+    }
+
+    #[test]
+    fn test_rank() {
+        let order = [1, 2, 3, 4, 5, 13, 6, 7, 8, 9, 12, 10, 11];
+        for pair in order.windows(2) {
+            assert!(rank(pair[0]) < rank(pair[1]));
+        }
+    }
+
+    #[test]
+    fn test_instrument() {
+        let res = instrument(MODULE).unwrap();
+        assert_eq!(res.names.len(), 2);
+        assert_eq!(res.names[&1], "update");
+        assert_eq!(res.names[&2], "helper");
+
+        let mut validator = wasmparser::Validator::new();
+        validator.validate_all(&res.wasm).unwrap();
+
+        let mut found_export = false;
+        let mut bodies = Vec::new();
+        for payload in Parser::new(0).parse_all(&res.wasm) {
+            match payload.unwrap() {
+                Payload::GlobalSection(reader) => {
+                    assert_eq!(reader.count(), 1);
+                }
+                Payload::ExportSection(reader) => {
+                    let exports: Vec<_> = reader.into_iter().map(|e| e.unwrap()).collect();
+                    assert_eq!(exports.len(), 2);
+                    assert_eq!(exports[0].name, "update");
+                    assert_eq!(exports[1].name, CURRENT_FUNC);
+                    assert_eq!(exports[1].index, 0);
+                    found_export = true;
+                }
+                Payload::CodeSectionEntry(body) => {
+                    let ops = body.get_operators_reader().unwrap();
+                    let ops: Vec<_> = ops.into_iter().map(|op| op.unwrap()).collect();
+                    bodies.push(alloc::format!("{ops:?}"));
+                }
+                _ => {}
+            }
+        }
+        assert!(found_export);
+        assert_eq!(bodies.len(), 2);
+        let track =
+            |idx| alloc::format!("I32Const {{ value: {idx} }}, GlobalSet {{ global_index: 0 }}");
+        let update = alloc::format!(
+            "[{t}, Call {{ function_index: 2 }}, {t}, Call {{ function_index: 0 }}, {t}, End]",
+            t = track(1)
+        );
+        assert_eq!(bodies[0], update);
+        assert_eq!(bodies[1], alloc::format!("[{}, End]", track(2)));
+    }
+}
@@ -0,0 +1,262 @@
+use crate::color::{FromRGB, Theme};
+use crate::gestures::{Direction, DEFAULT_DEAD_ZONE};
+use alloc::string::String;
+use alloc::vec::Vec;
+use embedded_graphics::draw_target::DrawTarget;
+use embedded_graphics::geometry::{OriginDimensions, Point, Size};
+use embedded_graphics::mono_font::ascii::FONT_6X9;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::RgbColor;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{PrimitiveStyle, Rectangle, StyledDrawable};
+use embedded_graphics::text::Text;
+use firefly_hal::InputState;
+
+/// The characters available if the app doesn't specify the charset.
+const DEFAULT_CHARSET: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789 .,-_!?";
+/// How many keys fit on the screen, including the backspace and the confirm keys.
+const MAX_KEYS: usize = 60;
+const COLUMNS: usize = 10;
+const KEY_WIDTH: i32 = 24;
+const KEY_HEIGHT: i32 = 16;
+/// The vertical position of the first row of keys.
+const KEYS_TOP: i32 = 28;
+
+/// A key on the on-screen keyboard.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Key {
+    Char(char),
+    Backspace,
+    Confirm,
+}
+
+/// The on-screen keyboard for entering text.
+///
+/// Opened by the app and, like [`Menu`](crate::menu::Menu), rendered
+/// directly on the display. The app is paused while the keyboard is open.
+/// The pad moves the cursor, A types the selected key, B erases
+/// the last character, and the menu button cancels the input.
+pub(crate) struct Keyboard {
+    keys: Vec<Key>,
+    /// The max length of the entered text in bytes.
+    max_len: usize,
+    text: String,
+    selected: usize,
+
+    /// The guest memory address where to write the text when confirmed.
+    pub buf_ptr: u32,
+
+    /// True if the keyboard is currently rendered on the screen.
+    rendered: bool,
+
+    /// The direction in which the pad was pressed on the previous frame.
+    dir: Direction,
+    /// Buttons pressed on the previous frame.
+    buttons: u8,
+}
+
+impl Keyboard {
+    /// Create a keyboard with the given characters.
+    ///
+    /// Only printable ASCII characters are supported. If there are none,
+    /// the default charset (uppercase letters, digits, and punctuation) is used.
+    pub fn new(charset: &str, buf_ptr: u32, max_len: usize) -> Self {
+        let mut keys: Vec<Key> = Vec::new();
+        for ch in charset.chars() {
+            let key = Key::Char(ch);
+            if (' '..='~').contains(&ch) && !keys.contains(&key) && keys.len() < MAX_KEYS - 2 {
+                keys.push(key);
+            }
+        }
+        if keys.is_empty() {
+            keys = DEFAULT_CHARSET.chars().map(Key::Char).collect();
+        }
+        keys.push(Key::Backspace);
+        keys.push(Key::Confirm);
+        Self {
+            keys,
+            max_len,
+            text: String::new(),
+            selected: 0,
+            buf_ptr,
+            rendered: false,
+            dir: Direction::None,
+            // Ignore the buttons held when the keyboard is opened.
+            buttons: 0xff,
+        }
+    }
+
+    /// Handle the input. Returns the entered text when the input is done.
+    ///
+    /// Cancelling the input is the same as confirming an empty text.
+    pub fn handle_input(&mut self, input: &Option<InputState>) -> Option<String> {
+        let input = input.clone().unwrap_or_default();
+        let dir = match &input.pad {
+            Some(pad) => Direction::from_pad(pad, DEFAULT_DEAD_ZONE),
+            None => Direction::None,
+        };
+        if dir != self.dir {
+            self.move_cursor(dir);
+        }
+        self.dir = dir;
+
+        let pressed = input.buttons & !self.buttons;
+        self.buttons = input.buttons;
+        if pressed & 0b10000 != 0 {
+            return Some(String::new());
+        }
+        if pressed & 0b00010 != 0 {
+            self.backspace();
+        }
+        if pressed & 0b00001 != 0 {
+            match self.keys[self.selected] {
+                Key::Char(ch) => self.type_text(ch.encode_utf8(&mut [0; 4])),
+                Key::Backspace => self.backspace(),
+                Key::Confirm => return Some(core::mem::take(&mut self.text)),
+            }
+        }
+        None
+    }
+
+    /// Add the text typed on a physical keyboard.
+    ///
+    /// Backspace erases the last character and newline confirms the input.
+    /// Characters not in the charset are ignored.
+    pub fn handle_text(&mut self, text: &str) -> Option<String> {
+        for ch in text.chars() {
+            match ch {
+                '\n' | '\r' => return Some(core::mem::take(&mut self.text)),
+                '\u{8}' | '\u{7f}' => self.backspace(),
+                _ if self.keys.contains(&Key::Char(ch)) => {
+                    self.type_text(ch.encode_utf8(&mut [0; 4]));
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    fn move_cursor(&mut self, dir: Direction) {
+        let n_keys = self.keys.len();
+        let selected = match dir {
+            Direction::Right => self.selected + 1,
+            Direction::Left => self.selected.wrapping_sub(1),
+            Direction::Down => self.selected + COLUMNS,
+            Direction::Up => self.selected.wrapping_sub(COLUMNS),
+            _ => return,
+        };
+        if selected < n_keys {
+            self.selected = selected;
+            self.rendered = false;
+        }
+    }
+
+    fn type_text(&mut self, ch: &str) {
+        if self.text.len() + ch.len() <= self.max_len {
+            self.text.push_str(ch);
+            self.rendered = false;
+        }
+    }
+
+    fn backspace(&mut self) {
+        if self.text.pop().is_some() {
+            self.rendered = false;
+        }
+    }
+
+    pub fn render<D, C, E>(&mut self, display: &mut D, theme: &Theme) -> Result<(), E>
+    where
+        D: DrawTarget<Color = C, Error = E> + OriginDimensions,
+        C: RgbColor + FromRGB,
+    {
+        if self.rendered {
+            return Ok(());
+        }
+        self.rendered = true;
+
+        let mut text_style = MonoTextStyle::new(&FONT_6X9, theme.primary());
+        text_style.background_color = Some(theme.bg());
+        let mut muted_style = MonoTextStyle::new(&FONT_6X9, theme.muted());
+        muted_style.background_color = Some(theme.bg());
+        let mut selected_style = MonoTextStyle::new(&FONT_6X9, theme.bg());
+        selected_style.background_color = Some(theme.primary());
+
+        display.clear(theme.bg())?;
+
+        // Draw the entered text with a cursor and the remaining length.
+        let text = alloc::format!("{}_", self.text);
+        Text::new(&text, Point::new(6, 12), text_style).draw(display)?;
+        let left = alloc::format!("{}", self.max_len - self.text.len());
+        Text::new(&left, Point::new(210, 12), muted_style).draw(display)?;
+        let line_style = PrimitiveStyle::with_fill(theme.primary());
+        let line = Rectangle::new(Point::new(4, 17), Size::new(232, 1));
+        line.draw_styled(&line_style, display)?;
+
+        for (i, key) in self.keys.iter().enumerate() {
+            let col = (i % COLUMNS) as i32;
+            let row = (i / COLUMNS) as i32;
+            let point = Point::new(col * KEY_WIDTH, KEYS_TOP + row * KEY_HEIGHT);
+            let style = if i == self.selected {
+                let size = Size::new(KEY_WIDTH as u32 - 2, KEY_HEIGHT as u32 - 2);
+                let rect = Rectangle::new(point + Point::new(1, 1), size);
+                rect.draw_styled(&line_style, display)?;
+                selected_style
+            } else {
+                text_style
+            };
+            let mut buf = [0; 4];
+            let label = match key {
+                Key::Char(' ') => "__",
+                Key::Char(ch) => &*ch.encode_utf8(&mut buf),
+                Key::Backspace => "<-",
+                Key::Confirm => "OK",
+            };
+            let x = (KEY_WIDTH - 6 * label.len() as i32) / 2;
+            Text::new(label, point + Point::new(x, 11), style).draw(display)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(buttons: u8) -> Option<InputState> {
+        Some(InputState { pad: None, buttons })
+    }
+
+    #[test]
+    fn test_type_and_confirm() {
+        let mut keyboard = Keyboard::new("ab", 0, 2);
+        assert_eq!(keyboard.handle_input(&press(0b1)), None);
+        assert_eq!(keyboard.handle_input(&None), None);
+        // Type "a" 3 times, only 2 fit.
+        for _ in 0..3 {
+            assert_eq!(keyboard.handle_input(&press(0b1)), None);
+            assert_eq!(keyboard.handle_input(&None), None);
+        }
+        assert_eq!(keyboard.text, "aa");
+        // Erase one character with B.
+        assert_eq!(keyboard.handle_input(&press(0b10)), None);
+        assert_eq!(keyboard.text, "a");
+        keyboard.selected = 3;
+        assert_eq!(keyboard.handle_input(&press(0b1)), Some("a".into()));
+    }
+
+    #[test]
+    fn test_charset() {
+        let keyboard = Keyboard::new("aab\u{e9}", 0, 10);
+        let expected = [Key::Char('a'), Key::Char('b'), Key::Backspace, Key::Confirm];
+        assert_eq!(keyboard.keys, expected);
+        let keyboard = Keyboard::new("", 0, 10);
+        assert_eq!(keyboard.keys.len(), DEFAULT_CHARSET.len() + 2);
+    }
+
+    #[test]
+    fn test_handle_text() {
+        let mut keyboard = Keyboard::new("abcd", 0, 10);
+        assert_eq!(keyboard.handle_text("abc\u{8}"), None);
+        assert_eq!(keyboard.handle_text("dX\n"), Some("abd".into()));
+    }
+}
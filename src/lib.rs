@@ -10,22 +10,32 @@
 
 extern crate alloc;
 
+mod audio;
 mod battery;
+mod button_events;
 mod canvas;
 mod color;
 mod config;
 mod error;
 mod error_scene;
 mod frame_buffer;
+mod gestures;
 mod host;
+mod i18n;
 mod image;
+mod instrument;
+mod keyboard;
 mod linking;
 mod menu;
 mod net;
+mod remap;
 mod runtime;
 mod state;
 mod stats;
+mod suspend;
+mod text_input;
 mod utils;
+mod watch;
 
 pub use color::Rgb16;
 pub use config::{FullID, FullIDError, RuntimeConfig};
@@ -110,8 +110,26 @@ fn select_audio_external<'a>(
         "reset" => Func::wrap(ctx, audio::reset),
         "reset_all" => Func::wrap(ctx, audio::reset_all),
         "clear" => Func::wrap(ctx, audio::clear),
+        "remove" => Func::wrap(ctx, audio::remove),
+        "move" => Func::wrap(ctx, audio::move_node),
+        "get_children" => Func::wrap(ctx, audio::get_children),
+        "get_kind" => Func::wrap(ctx, audio::get_kind),
         "add_empty" => Func::wrap(ctx, audio::add_empty),
         "add_file" => Func::wrap(ctx, audio::add_file),
+        "add_adpcm" => Func::wrap(ctx, audio::add_adpcm),
+        "add_tracker" => Func::wrap(ctx, audio::add_tracker),
+        "tracker_jump" => Func::wrap(ctx, audio::tracker_jump),
+        "tracker_mute" => Func::wrap(ctx, audio::tracker_mute),
+        "tracker_tempo" => Func::wrap(ctx, audio::tracker_tempo),
+        "tracker_position" => Func::wrap(ctx, audio::tracker_position),
+        "get_played" => Func::wrap(ctx, audio::get_played),
+        "is_exhausted" => Func::wrap(ctx, audio::is_exhausted),
+        "notify_on_end" => Func::wrap(ctx, audio::notify_on_end),
+        "get_time" => Func::wrap(ctx, audio::get_time),
+        "schedule_reset" => Func::wrap(ctx, audio::schedule_reset),
+        "schedule_reset_all" => Func::wrap(ctx, audio::schedule_reset_all),
+        "schedule_set" => Func::wrap(ctx, audio::schedule_set),
+        "cancel_scheduled" => Func::wrap(ctx, audio::cancel_scheduled),
         "add_mix" => Func::wrap(ctx, audio::add_mix),
         "add_all_for_one" => Func::wrap(ctx, audio::add_all_for_one),
         "add_gain" => Func::wrap(ctx, audio::add_gain),
@@ -127,6 +145,10 @@ fn select_audio_external<'a>(
         "add_take_right" => Func::wrap(ctx, audio::add_take_right),
         "add_swap" => Func::wrap(ctx, audio::add_swap),
         "add_clip" => Func::wrap(ctx, audio::add_clip),
+        "add_delay" => Func::wrap(ctx, audio::add_delay),
+        "add_reverb" => Func::wrap(ctx, audio::add_reverb),
+        "add_bitcrusher" => Func::wrap(ctx, audio::add_bitcrusher),
+        "add_compressor" => Func::wrap(ctx, audio::add_compressor),
         "add_noise" => Func::wrap(ctx, audio::add_noise),
         "add_sine" => Func::wrap(ctx, audio::add_sine),
         "add_square" => Func::wrap(ctx, audio::add_square),
@@ -136,6 +158,12 @@ fn select_audio_external<'a>(
         "mod_linear" => Func::wrap(ctx, audio::mod_linear),
         "mod_hold" => Func::wrap(ctx, audio::mod_hold),
         "mod_sine" => Func::wrap(ctx, audio::mod_sine),
+        "mod_square" => Func::wrap(ctx, audio::mod_square),
+        "mod_saw" => Func::wrap(ctx, audio::mod_saw),
+        "mod_random" => Func::wrap(ctx, audio::mod_random),
+        "mod_curve" => Func::wrap(ctx, audio::mod_curve),
+        "mod_envelope" => Func::wrap(ctx, audio::mod_envelope),
+        "set_gate" => Func::wrap(ctx, audio::set_gate),
         _ => return None,
     };
     Some(func)
@@ -148,6 +176,12 @@ fn select_input_external<'a>(
     let func = match fn_name {
         "read_pad" => Func::wrap(ctx, input::read_pad),
         "read_buttons" => Func::wrap(ctx, input::read_buttons),
+        "read_button_events" => Func::wrap(ctx, input::read_button_events),
+        "read_text" => Func::wrap(ctx, input::read_text),
+        "open_keyboard" => Func::wrap(ctx, input::open_keyboard),
+        "read_gesture" => Func::wrap(ctx, input::read_gesture),
+        "read_dpad" => Func::wrap(ctx, input::read_dpad),
+        "set_dead_zone" => Func::wrap(ctx, input::set_dead_zone),
         _ => return None,
     };
     Some(func)
@@ -159,7 +193,15 @@ fn select_menu_external<'a>(
 ) -> Option<wasmi::Func> {
     let func = match fn_name {
         "add_menu_item" => Func::wrap(ctx, menu::add_menu_item),
+        "add_submenu" => Func::wrap(ctx, menu::add_submenu),
+        "add_toggle" => Func::wrap(ctx, menu::add_toggle),
+        "add_slider" => Func::wrap(ctx, menu::add_slider),
+        "add_separator" => Func::wrap(ctx, menu::add_separator),
         "remove_menu_item" => Func::wrap(ctx, menu::remove_menu_item),
+        "set_parent" => Func::wrap(ctx, menu::set_parent),
+        "set_disabled" => Func::wrap(ctx, menu::set_disabled),
+        "set_value" => Func::wrap(ctx, menu::set_value),
+        "set_overlay" => Func::wrap(ctx, menu::set_overlay),
         "open_menu" => Func::wrap(ctx, menu::open_menu),
         _ => return None,
     };
@@ -1,8 +1,15 @@
+use crate::audio::MenuAudio;
 use crate::battery::Battery;
-use crate::color::FromRGB;
+use crate::color::{FromRGB, Theme};
+use crate::frame_buffer::FrameBuffer;
+use crate::gestures::{Direction, DEFAULT_DEAD_ZONE};
+use crate::i18n::{Lang, Message};
+use crate::remap::InputProfile;
+use alloc::borrow::Cow;
+use alloc::string::String;
+use alloc::vec::Vec;
 use embedded_graphics::draw_target::DrawTarget;
 use embedded_graphics::geometry::{OriginDimensions, Point, Size};
-use embedded_graphics::mono_font::ascii::FONT_6X9;
 use embedded_graphics::mono_font::MonoTextStyle;
 use embedded_graphics::pixelcolor::RgbColor;
 use embedded_graphics::prelude::*;
@@ -13,41 +20,219 @@ use embedded_graphics::text::Text;
 use firefly_hal::InputState;
 
 const LINE_HEIGHT: i32 = 12;
+/// How many menu items fit on the screen above the battery indicator.
+const VISIBLE_LINES: usize = 11;
+/// How many characters of an item name fit on the screen.
+const MAX_CHARS: usize = 37;
+/// How often (in frames) the name of the selected item is shifted if it doesn't fit.
+const MARQUEE_FRAMES: u32 = 10;
+/// The baseline of the line with the app name and play time.
+const FOOTER_Y: i32 = 153;
+/// How many characters of the footer fit on the left of the battery indicator.
+const FOOTER_CHARS: usize = 33;
+
+/// The item shown at the end of every submenu.
+static BACK: MenuItem = MenuItem::Back;
 
 pub(crate) enum MenuItem {
-    Custom(u8, alloc::string::String),
+    Custom(CustomItem),
+    /// The volume of the running app, in percents.
+    Volume(u8),
+    /// What to do with the app audio while the menu is open.
+    MenuAudio(MenuAudio),
+    /// If the audio is mixed into one channel.
+    Mono(bool),
+    /// Open the input settings screen.
+    InputSettings,
+    /// An option on the input settings screen with its current value.
+    Input(InputOption, String),
+    /// Go back from a submenu to the parent menu.
+    Back,
     ScreenShot,
     Restart,
     Quit,
 }
 
+/// Options on the input settings screen.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum InputOption {
+    SwapAB,
+    Toggle,
+    Turbo,
+    Sensitivity,
+    InvertX,
+    InvertY,
+    /// Use the current app settings as the default for all apps.
+    SaveGlobal,
+}
+
+/// The kind of a menu item added by the app.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum ItemKind {
+    /// Closes the menu when selected.
+    Button,
+    /// Switched on and off when selected, shown with a checkmark.
+    Toggle(bool),
+    /// A number changed by pressing left and right on the pad.
+    Slider {
+        value: u32,
+        min: u32,
+        max: u32,
+        /// How much the value changes on each press.
+        step: u32,
+    },
+    /// Opens a nested menu with the items that have this item as the parent.
+    Submenu,
+    /// A line between groups of items. Cannot be selected.
+    Separator,
+}
+
+/// A menu item added by the app.
+pub(crate) struct CustomItem {
+    /// The ID of the item passed into the `handle_menu` callback.
+    pub index: u8,
+    pub name: String,
+    pub kind: ItemKind,
+    /// The index of the submenu in which the item is shown.
+    pub parent: Option<u8>,
+    /// Disabled items are grayed out and cannot be selected.
+    pub disabled: bool,
+}
+
+impl CustomItem {
+    /// The value passed into the `handle_menu` callback.
+    ///
+    /// 1 or 0 for toggles, the current value for sliders, and 0 for everything else.
+    pub fn value(&self) -> u32 {
+        match self.kind {
+            ItemKind::Toggle(on) => u32::from(on),
+            ItemKind::Slider { value, .. } => value,
+            _ => 0,
+        }
+    }
+
+    /// Set the value of a toggle or a slider.
+    pub fn set_value(&mut self, new_value: u32) {
+        match &mut self.kind {
+            ItemKind::Toggle(on) => *on = new_value != 0,
+            ItemKind::Slider {
+                value, min, max, ..
+            } => *value = new_value.clamp(*min, *max),
+            _ => {}
+        }
+    }
+}
+
 impl MenuItem {
-    fn as_str(&self) -> &str {
+    fn as_str(&self, lang: Lang) -> Cow<'_, str> {
+        let tr = |msg: Message| msg.translate(lang);
+        match self {
+            Self::Custom(item) => match item.kind {
+                ItemKind::Toggle(true) => Cow::Owned(alloc::format!("[x] {}", item.name)),
+                ItemKind::Toggle(false) => Cow::Owned(alloc::format!("[ ] {}", item.name)),
+                ItemKind::Slider { value, .. } => {
+                    Cow::Owned(alloc::format!("{}: < {value} >", item.name))
+                }
+                ItemKind::Submenu => Cow::Owned(alloc::format!("{} >", item.name)),
+                ItemKind::Button | ItemKind::Separator => Cow::Borrowed(&item.name),
+            },
+            Self::Volume(v) => Cow::Owned(alloc::format!("{}: {v}%", tr(Message::Volume))),
+            Self::MenuAudio(mode) => {
+                let mode = match mode {
+                    MenuAudio::Pause => tr(Message::Pause),
+                    MenuAudio::Duck => tr(Message::Duck),
+                };
+                Cow::Owned(alloc::format!("{}: {mode}", tr(Message::MenuAudio)))
+            }
+            Self::Mono(on) => {
+                let on = tr(if *on { Message::On } else { Message::Off });
+                Cow::Owned(alloc::format!("{}: {on}", tr(Message::Mono)))
+            }
+            Self::InputSettings => Cow::Owned(alloc::format!("{} >", tr(Message::InputSettings))),
+            Self::Input(_, t) => Cow::Borrowed(t),
+            Self::Back => Cow::Owned(alloc::format!("< {}", tr(Message::Back))),
+            Self::ScreenShot => Cow::Borrowed(tr(Message::ScreenShot)),
+            Self::Restart => Cow::Borrowed(tr(Message::Restart)),
+            Self::Quit => Cow::Borrowed(tr(Message::Quit)),
+        }
+    }
+
+    /// The submenu in which the item is shown.
+    fn parent(&self) -> Option<u8> {
         match self {
-            Self::Custom(_, t) => t,
-            Self::ScreenShot => "take screenshot",
-            Self::Restart => "restart app",
-            Self::Quit => "exit app",
+            Self::Custom(item) => item.parent,
+            _ => None,
         }
     }
+
+    fn is_separator(&self) -> bool {
+        matches!(self, Self::Custom(item) if item.kind == ItemKind::Separator)
+    }
+}
+
+/// What to do when a menu item is selected.
+enum Selected {
+    /// Close the menu and let the runtime handle the item.
+    Close,
+    /// Keep the menu open and let the runtime handle the item.
+    Keep,
+    /// Switch the toggle with the given index.
+    Toggle(u8),
+    /// Open the submenu with the given index.
+    Submenu(u8),
+    InputScreen,
+    Back,
 }
 
 #[derive(Default)]
 pub(crate) struct Menu {
     /// Custom menu items.
-    app_items: alloc::vec::Vec<MenuItem>,
+    app_items: Vec<MenuItem>,
 
     /// System menu items.
-    sys_items: heapless::Vec<MenuItem, 3>,
+    sys_items: heapless::Vec<MenuItem, 7>,
+
+    /// Items of the input settings screen.
+    input_items: Vec<MenuItem>,
+
+    /// The input profile shown on the input settings screen.
+    input_profile: InputProfile,
+
+    /// The language of the system menu items.
+    lang: Lang,
+
+    /// True if the input settings screen is shown instead of the main menu.
+    input_screen: bool,
+
+    /// If true, the menu is drawn over a dimmed app frame instead of a blank screen.
+    overlay: bool,
+
+    /// The human-readable name of the running app.
+    app_name: String,
+
+    /// For how many seconds the app has been running.
+    play_time: u32,
+
+    /// The stack of opened submenus, the innermost is the last.
+    submenus: Vec<u8>,
 
     selected: i32,
 
+    /// The index of the first item shown on the screen.
+    scroll: i32,
+
+    /// Frames since the menu was rendered, used to animate long item names.
+    marquee: u32,
+
     /// True if the menu should be currently shown.
     active: bool,
 
     /// True if the menu is currently rendered on the screen.
     rendered: bool,
 
+    /// True if the current play time is rendered on the screen.
+    footer_rendered: bool,
+
     /// True if the menu button is currently pressed.
     menu_pressed: bool,
 
@@ -57,34 +242,222 @@ pub(crate) struct Menu {
     /// True if the menu button was released when the menu was open.
     was_released: bool,
 
-    down_pressed: bool,
-    up_pressed: bool,
+    /// The direction in which the pad was pressed on the previous frame.
+    dir: Direction,
+
+    /// The horizontal direction in which the pad is pressed: -1, 0, or 1.
+    slide: i8,
 }
 
 impl Menu {
     pub fn new() -> Self {
-        let mut items = heapless::Vec::<_, 3>::new();
+        let mut items = heapless::Vec::<_, 7>::new();
         unsafe {
+            items.push_unchecked(MenuItem::Volume(100));
+            items.push_unchecked(MenuItem::MenuAudio(MenuAudio::Pause));
+            items.push_unchecked(MenuItem::Mono(false));
+            items.push_unchecked(MenuItem::InputSettings);
             items.push_unchecked(MenuItem::ScreenShot);
             items.push_unchecked(MenuItem::Restart);
             items.push_unchecked(MenuItem::Quit);
         }
         Self {
-            app_items: alloc::vec::Vec::new(),
+            app_items: Vec::new(),
             sys_items: items,
             ..Default::default()
         }
     }
 
     /// Add a custom menu item.
-    pub(crate) fn add(&mut self, index: u8, name: alloc::string::String) {
-        self.app_items.push(MenuItem::Custom(index, name));
+    ///
+    /// If there is already an item with the same index, it is replaced.
+    pub(crate) fn add(&mut self, index: u8, name: String, kind: ItemKind) {
+        let parent = self.get_mut(index).and_then(|item| item.parent);
+        let item = MenuItem::Custom(CustomItem {
+            index,
+            name,
+            kind,
+            parent,
+            disabled: false,
+        });
+        let pos = self
+            .app_items
+            .iter()
+            .position(|i| matches!(i, MenuItem::Custom(i) if i.index == index));
+        match pos {
+            Some(pos) => self.app_items[pos] = item,
+            None => self.app_items.push(item),
+        }
+        self.rendered = false;
+    }
+
+    /// Get a custom menu item by its index.
+    ///
+    /// Since the item might be changed, the menu will be rendered again.
+    pub(crate) fn get_mut(&mut self, index: u8) -> Option<&mut CustomItem> {
+        self.rendered = false;
+        self.app_items.iter_mut().find_map(|item| match item {
+            MenuItem::Custom(item) if item.index == index => Some(item),
+            _ => None,
+        })
+    }
+
+    /// Show the custom menu item inside of the given submenu.
+    ///
+    /// Returns false without changing anything if the parent doesn't exist,
+    /// is not a submenu, or is the item itself or one of its descendants.
+    pub(crate) fn set_parent(&mut self, index: u8, parent: u8) -> bool {
+        let find = |index: u8| {
+            self.app_items.iter().find_map(|item| match item {
+                MenuItem::Custom(item) if item.index == index => Some(item),
+                _ => None,
+            })
+        };
+        match find(parent) {
+            Some(item) if item.kind == ItemKind::Submenu => {}
+            _ => return false,
+        }
+        // Walk up from the new parent to make sure the item is not its ancestor.
+        let mut ancestor = Some(parent);
+        while let Some(current) = ancestor {
+            if current == index {
+                return false;
+            }
+            ancestor = find(current).and_then(|item| item.parent);
+        }
+        let Some(item) = self.get_mut(index) else {
+            return false;
+        };
+        item.parent = Some(parent);
+        true
+    }
+
+    /// Show the given volume (in percents) in the volume menu item.
+    pub(crate) fn set_volume(&mut self, volume: u8) {
+        for item in &mut self.sys_items {
+            if let MenuItem::Volume(v) = item {
+                *v = volume;
+            }
+        }
+        self.rendered = false;
+    }
+
+    /// Show the given mode in the menu audio item.
+    pub(crate) fn set_menu_audio(&mut self, mode: MenuAudio) {
+        for item in &mut self.sys_items {
+            if let MenuItem::MenuAudio(m) = item {
+                *m = mode;
+            }
+        }
+        self.rendered = false;
+    }
+
+    /// Show the given state in the mono menu item.
+    pub(crate) fn set_mono(&mut self, mono: bool) {
+        for item in &mut self.sys_items {
+            if let MenuItem::Mono(m) = item {
+                *m = mono;
+            }
+        }
+        self.rendered = false;
+    }
+
+    /// Draw the menu on top of the last app frame instead of a blank screen.
+    pub(crate) fn set_overlay(&mut self, overlay: bool) {
+        self.overlay = overlay;
+        self.rendered = false;
+    }
+
+    pub(crate) fn set_app_name(&mut self, name: &str) {
+        self.app_name = name.into();
+        self.rendered = false;
+    }
+
+    /// Update the play time shown in the menu footer.
+    pub(crate) fn set_play_time(&mut self, secs: u32) {
+        if secs != self.play_time {
+            self.play_time = secs;
+            self.footer_rendered = false;
+        }
+    }
+
+    /// Set the language of the system menu items.
+    pub(crate) fn set_lang(&mut self, lang: Lang) {
+        self.lang = lang;
+        let profile = self.input_profile.clone();
+        self.set_input_profile(&profile);
+    }
+
+    /// Show the current values of the input profile on the input settings screen.
+    pub(crate) fn set_input_profile(&mut self, profile: &InputProfile) {
+        use alloc::format;
+        let lang = self.lang;
+        let tr = |msg: Message| msg.translate(lang);
+        let on_off = |v: bool| if v { tr(Message::On) } else { tr(Message::Off) };
+        let buttons = |mask: u8| match mask {
+            0 => tr(Message::Off),
+            0b0001 => "A",
+            0b0010 => "B",
+            0b0100 => "X",
+            0b1000 => "Y",
+            _ => tr(Message::All),
+        };
+        let items = [
+            (
+                InputOption::SwapAB,
+                format!("{}: {}", tr(Message::SwapAB), on_off(profile.swap_ab)),
+            ),
+            (
+                InputOption::Toggle,
+                format!("{}: {}", tr(Message::HoldToToggle), buttons(profile.toggle)),
+            ),
+            (
+                InputOption::Turbo,
+                format!("{}: {}", tr(Message::Turbo), buttons(profile.turbo)),
+            ),
+            (
+                InputOption::Sensitivity,
+                format!("{}: {}%", tr(Message::PadSensitivity), profile.sensitivity),
+            ),
+            (
+                InputOption::InvertX,
+                format!("{}: {}", tr(Message::InvertX), on_off(profile.invert_x)),
+            ),
+            (
+                InputOption::InvertY,
+                format!("{}: {}", tr(Message::InvertY), on_off(profile.invert_y)),
+            ),
+            (InputOption::SaveGlobal, tr(Message::UseForAllApps).into()),
+        ];
+        self.input_profile = profile.clone();
+        self.input_items = items
+            .into_iter()
+            .map(|(option, label)| MenuItem::Input(option, label))
+            .collect();
+        self.rendered = false;
     }
 
-    /// Remove a custom menu item.
+    /// Remove a custom menu item together with all items of its submenu.
     pub(crate) fn remove(&mut self, index: u8) {
         self.app_items
-            .retain(|item| !matches!(item, MenuItem::Custom(i, _) if *i == index));
+            .retain(|item| !matches!(item, MenuItem::Custom(i) if i.index == index));
+        let children: Vec<u8> = self
+            .app_items
+            .iter()
+            .filter_map(|item| match item {
+                MenuItem::Custom(item) if item.parent == Some(index) => Some(item.index),
+                _ => None,
+            })
+            .collect();
+        for child in children {
+            self.remove(child);
+        }
+        // If the removed submenu is open, go back to its parent.
+        if let Some(pos) = self.submenus.iter().position(|i| *i == index) {
+            self.submenus.truncate(pos);
+            self.select_first();
+        }
+        self.rendered = false;
     }
 
     pub fn handle_input(&mut self, input: &Option<InputState>) -> Option<&MenuItem> {
@@ -94,7 +467,14 @@ impl Menu {
         if !self.active {
             return None;
         }
-        self.handle_pad(input);
+        let dir = match &input.pad {
+            Some(pad) => Direction::from_pad(pad, DEFAULT_DEAD_ZONE),
+            None => Direction::None,
+        };
+        self.handle_pad(dir);
+        if self.handle_slide(dir) {
+            return self.items().nth(self.selected as usize);
+        }
         self.handle_select(input.s() || input.e())
     }
 
@@ -116,53 +496,183 @@ impl Menu {
                 self.active = true;
                 self.rendered = false;
                 self.was_released = false;
+                // Always open the main menu, not a submenu.
+                if self.input_screen || !self.submenus.is_empty() {
+                    self.input_screen = false;
+                    self.submenus.clear();
+                    self.select_first();
+                }
             }
         }
         self.menu_pressed = pressed;
     }
 
-    fn handle_pad(&mut self, input: &InputState) {
-        let Some(pad) = &input.pad else {
-            self.down_pressed = false;
-            self.up_pressed = false;
+    fn handle_pad(&mut self, dir: Direction) {
+        let prev_dir = self.dir;
+        self.dir = dir;
+        if dir == prev_dir {
             return;
-        };
-        if pad.y < -50 {
-            self.down_pressed = false;
-            let n_items = self.app_items.len() + self.sys_items.len();
-            if !self.up_pressed && self.selected < n_items as i32 - 1 {
-                self.selected += 1;
-                self.rendered = false;
-            }
-            self.up_pressed = true;
         }
-        if pad.y > 50 {
-            self.up_pressed = false;
-            if !self.down_pressed && self.selected > 0 {
-                self.selected -= 1;
-                self.rendered = false;
+        let selected = self.selected as usize;
+        match dir {
+            Direction::Down => {
+                // Skip separators.
+                let next = self
+                    .items()
+                    .enumerate()
+                    .skip(selected + 1)
+                    .find(|(_, item)| !item.is_separator());
+                if let Some((next, _)) = next {
+                    self.selected = next as i32;
+                    self.rendered = false;
+                }
             }
-            self.down_pressed = true;
+            Direction::Up => {
+                let prev = self
+                    .items()
+                    .enumerate()
+                    .take(selected)
+                    .filter(|(_, item)| !item.is_separator())
+                    .last();
+                if let Some((prev, _)) = prev {
+                    self.selected = prev as i32;
+                    self.rendered = false;
+                }
+            }
+            _ => {}
         }
     }
 
+    /// Change the value of the selected slider by pressing left or right on the pad.
+    ///
+    /// Returns true if the value has changed.
+    fn handle_slide(&mut self, dir: Direction) -> bool {
+        let slide = match dir {
+            Direction::Left => -1,
+            Direction::Right => 1,
+            _ => 0,
+        };
+        let prev = self.slide;
+        self.slide = slide;
+        if slide == 0 || slide == prev {
+            return false;
+        }
+        let Some(MenuItem::Custom(item)) = self.items().nth(self.selected as usize) else {
+            return false;
+        };
+        if item.disabled {
+            return false;
+        }
+        let index = item.index;
+        let Some(item) = self.get_mut(index) else {
+            return false;
+        };
+        let ItemKind::Slider { value, step, .. } = item.kind else {
+            return false;
+        };
+        let new_value = if slide > 0 {
+            value.saturating_add(step)
+        } else {
+            value.saturating_sub(step)
+        };
+        item.set_value(new_value);
+        if item.value() == value {
+            return false;
+        }
+        self.rendered = false;
+        true
+    }
+
     fn handle_select(&mut self, pressed: bool) -> Option<&MenuItem> {
-        if self.select_pressed {
-            if !pressed {
-                self.select_pressed = false;
-                let selected = self.selected as usize;
-                // Close menu and return control to the game
-                self.active = false;
-                if let Some(item) = self.app_items.get(selected) {
-                    return Some(item);
+        if !self.select_pressed {
+            self.select_pressed = pressed;
+            return None;
+        }
+        if pressed {
+            return None;
+        }
+        self.select_pressed = false;
+        let selected = self.selected as usize;
+        let action = match self.items().nth(selected)? {
+            MenuItem::Custom(item) if item.disabled => return None,
+            MenuItem::Custom(item) => match item.kind {
+                ItemKind::Button => Selected::Close,
+                ItemKind::Toggle(_) => Selected::Toggle(item.index),
+                ItemKind::Submenu => Selected::Submenu(item.index),
+                ItemKind::Slider { .. } | ItemKind::Separator => return None,
+            },
+            MenuItem::InputSettings => Selected::InputScreen,
+            MenuItem::Back => Selected::Back,
+            // Changing the volume or an input option keeps the menu open
+            // so that the user can press it several times.
+            MenuItem::Volume(_)
+            | MenuItem::MenuAudio(_)
+            | MenuItem::Mono(_)
+            | MenuItem::Input(_, _) => Selected::Keep,
+            MenuItem::ScreenShot | MenuItem::Restart | MenuItem::Quit => Selected::Close,
+        };
+        match action {
+            // Close menu and return control to the game
+            Selected::Close => self.active = false,
+            Selected::Keep => self.rendered = false,
+            Selected::Toggle(index) => {
+                if let Some(item) = self.get_mut(index) {
+                    let on = item.value() == 0;
+                    item.set_value(u32::from(on));
                 }
-                let selected = selected - self.app_items.len();
-                return self.sys_items.get(selected);
+                self.rendered = false;
+            }
+            Selected::Submenu(index) => {
+                self.submenus.push(index);
+                self.select_first();
+                self.rendered = false;
+                return None;
+            }
+            Selected::InputScreen => {
+                self.input_screen = true;
+                self.select_first();
+                self.rendered = false;
+                return None;
+            }
+            Selected::Back => {
+                if self.input_screen {
+                    self.input_screen = false;
+                } else {
+                    self.submenus.pop();
+                }
+                self.select_first();
+                self.rendered = false;
+                return None;
             }
-        } else {
-            self.select_pressed = pressed;
         }
-        None
+        self.items().nth(selected)
+    }
+
+    /// Select the first item on the current screen that can be selected.
+    ///
+    /// Separators and disabled items are skipped.
+    fn select_first(&mut self) {
+        let first = self.items().position(|item| match item {
+            MenuItem::Custom(item) => !item.disabled && item.kind != ItemKind::Separator,
+            _ => true,
+        });
+        self.selected = first.unwrap_or(0) as i32;
+    }
+
+    /// Items shown on the current screen.
+    fn items(&self) -> impl Iterator<Item = &MenuItem> {
+        let parent = self.submenus.last().copied();
+        let (main, sys, back): (&[MenuItem], &[MenuItem], _) = if self.input_screen {
+            (&self.input_items, &[], Some(&BACK))
+        } else if parent.is_some() {
+            (&self.app_items, &[], Some(&BACK))
+        } else {
+            (&self.app_items, &self.sys_items, None)
+        };
+        main.iter()
+            .filter(move |item| item.parent() == parent)
+            .chain(sys)
+            .chain(back)
     }
 
     /// True if the menu should be currently shown.
@@ -182,54 +692,175 @@ impl Menu {
         self.active = false;
     }
 
-    pub fn render<D, C, E>(&mut self, display: &mut D, battery: &Option<Battery>) -> Result<(), E>
+    pub fn render<D, C, E>(
+        &mut self,
+        display: &mut D,
+        frame: &mut FrameBuffer,
+        battery: &Option<Battery>,
+        theme: &Theme,
+    ) -> Result<(), E>
     where
         D: DrawTarget<Color = C, Error = E> + OriginDimensions,
         C: RgbColor + FromRGB,
     {
+        self.marquee = self.marquee.wrapping_add(1);
         if self.rendered {
-            return Ok(());
+            if !self.footer_rendered {
+                self.draw_footer(display, theme)?;
+            }
+            return self.draw_marquee(display, theme);
         }
         self.rendered = true;
+        self.marquee = 0;
+        self.scroll_to_selected();
 
-        let mut black_style = MonoTextStyle::new(&FONT_6X9, C::PRIMARY);
-        black_style.background_color = Some(C::BG);
-        let mut blue_style = MonoTextStyle::new(&FONT_6X9, C::ACCENT);
-        blue_style.background_color = Some(C::BG);
+        let font = self.lang.font();
+        let mut black_style = MonoTextStyle::new(font, theme.primary());
+        black_style.background_color = Some(theme.bg());
+        let mut blue_style = MonoTextStyle::new(font, theme.accent());
+        blue_style.background_color = Some(theme.bg());
+        let mut gray_style = MonoTextStyle::new(font, theme.muted());
+        gray_style.background_color = Some(theme.bg());
 
-        display.clear(C::BG)?;
-        self.draw_cursor(display)?;
-        let items = self.app_items.iter().chain(self.sys_items.iter());
+        if self.overlay {
+            // The frame buffer is preserved while the menu is open,
+            // so we can show the paused game behind the menu.
+            frame.dirty = true;
+            frame.draw(display)?;
+            draw_dither(display, theme)?;
+        } else {
+            display.clear(theme.bg())?;
+        }
+        self.draw_cursor(display, theme)?;
+        let items = self.items().skip(self.scroll as usize).take(VISIBLE_LINES);
         for (item, i) in items.zip(0..) {
+            if item.is_separator() {
+                let point = Point::new(6, 6 + i * LINE_HEIGHT);
+                let line = Rectangle::new(point, Size::new(228, 1));
+                line.draw_styled(&PrimitiveStyle::with_fill(theme.muted()), display)?;
+                continue;
+            }
             let point = Point::new(6, 9 + i * LINE_HEIGHT);
-            let is_custom = matches!(item, MenuItem::Custom(_, _));
-            let text_style = if is_custom { blue_style } else { black_style };
-            let text = Text::new(item.as_str(), point, text_style);
+            let text_style = match item {
+                MenuItem::Custom(item) if item.disabled => gray_style,
+                MenuItem::Custom(_) => blue_style,
+                _ => black_style,
+            };
+            let name = item.as_str(self.lang);
+            let text = truncate(&name);
+            let text = Text::new(&text, point, text_style);
             text.draw(display)?;
         }
-        self.draw_battery(display, battery)
+        self.draw_scrollbar(display, theme)?;
+        self.draw_footer(display, theme)?;
+        self.draw_battery(display, battery, theme)
+    }
+
+    /// Show the app name and for how long it is running.
+    fn draw_footer<D, C, E>(&mut self, display: &mut D, theme: &Theme) -> Result<(), E>
+    where
+        D: DrawTarget<Color = C, Error = E> + OriginDimensions,
+        C: RgbColor + FromRGB,
+    {
+        self.footer_rendered = true;
+        let time = format_play_time(self.play_time);
+        let max_name = FOOTER_CHARS - time.len() - 2;
+        let mut text: String = self.app_name.chars().take(max_name).collect();
+        let pad = FOOTER_CHARS - text.chars().count() - time.len();
+        text.extend(core::iter::repeat_n(' ', pad));
+        text.push_str(&time);
+        let mut style = MonoTextStyle::new(self.lang.font(), theme.muted());
+        style.background_color = Some(theme.bg());
+        Text::new(&text, Point::new(6, FOOTER_Y), style).draw(display)?;
+        Ok(())
+    }
+
+    /// Scroll the menu so that the selected item is visible.
+    fn scroll_to_selected(&mut self) {
+        let visible = VISIBLE_LINES as i32;
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + visible {
+            self.scroll = self.selected - visible + 1;
+        }
+        let n_items = self.items().count() as i32;
+        self.scroll = self.scroll.min(n_items - visible).max(0);
+    }
+
+    /// If the name of the selected item doesn't fit on the screen, slowly scroll it.
+    fn draw_marquee<D, C, E>(&self, display: &mut D, theme: &Theme) -> Result<(), E>
+    where
+        D: DrawTarget<Color = C, Error = E> + OriginDimensions,
+        C: RgbColor + FromRGB,
+    {
+        if !self.marquee.is_multiple_of(MARQUEE_FRAMES) {
+            return Ok(());
+        }
+        let Some(item) = self.items().nth(self.selected as usize) else {
+            return Ok(());
+        };
+        let text = item.as_str(self.lang);
+        let len = text.chars().count();
+        if len <= MAX_CHARS {
+            return Ok(());
+        }
+        // Show the text in a loop with a gap between the end and the start.
+        let shift = (self.marquee / MARQUEE_FRAMES) as usize % (len + 3);
+        let chars = text.chars().chain("   ".chars()).cycle();
+        let text: String = chars.skip(shift).take(MAX_CHARS).collect();
+        let color: C = match item {
+            MenuItem::Custom(_) => theme.accent(),
+            _ => theme.primary(),
+        };
+        let mut style = MonoTextStyle::new(self.lang.font(), color);
+        style.background_color = Some(theme.bg());
+        let i = self.selected - self.scroll;
+        let point = Point::new(6, 9 + i * LINE_HEIGHT);
+        Text::new(&text, point, style).draw(display)?;
+        Ok(())
+    }
+
+    /// Show the scroll position if not all items fit on the screen.
+    fn draw_scrollbar<D, C, E>(&self, display: &mut D, theme: &Theme) -> Result<(), E>
+    where
+        D: DrawTarget<Color = C, Error = E> + OriginDimensions,
+        C: RgbColor + FromRGB,
+    {
+        let n_items = self.items().count() as i32;
+        let visible = VISIBLE_LINES as i32;
+        if n_items <= visible {
+            return Ok(());
+        }
+        let track = visible * LINE_HEIGHT;
+        let track_rect = Rectangle::new(Point::new(237, 2), Size::new(2, track as u32));
+        track_rect.draw_styled(&PrimitiveStyle::with_fill(theme.muted()), display)?;
+        let height = (track * visible / n_items).max(4);
+        let top = 2 + track * self.scroll / n_items;
+        let thumb = Rectangle::new(Point::new(236, top), Size::new(4, height as u32));
+        thumb.draw_styled(&PrimitiveStyle::with_fill(theme.primary()), display)?;
+        Ok(())
     }
 
     /// Indicate which item is currently selected.
-    pub fn draw_cursor<D, C, E>(&self, display: &mut D) -> Result<(), E>
+    pub fn draw_cursor<D, C, E>(&self, display: &mut D, theme: &Theme) -> Result<(), E>
     where
         D: DrawTarget<Color = C, Error = E> + OriginDimensions,
         C: RgbColor + FromRGB,
     {
-        let i = self.selected;
-        let size = Size::new(232, LINE_HEIGHT as u32);
+        let i = self.selected - self.scroll;
+        let size = Size::new(231, LINE_HEIGHT as u32);
         let corners = CornerRadii::new(Size::new_equal(4));
 
         // Render shadow.
-        let box_style = PrimitiveStyle::with_fill(C::PRIMARY);
+        let box_style = PrimitiveStyle::with_fill(theme.primary());
         let point = Point::new(4, 3 + i * LINE_HEIGHT);
         let rect = Rectangle::new(point, size);
         let rect = RoundedRectangle::new(rect, corners);
         rect.draw_styled(&box_style, display)?;
 
         // Render the selection box.
-        let mut box_style = PrimitiveStyle::with_stroke(C::PRIMARY, 1);
-        box_style.fill_color = Some(C::BG);
+        let mut box_style = PrimitiveStyle::with_stroke(theme.primary(), 1);
+        box_style.fill_color = Some(theme.bg());
         let point = Point::new(3, 2 + i * LINE_HEIGHT);
         let rect = Rectangle::new(point, size);
         let rect = RoundedRectangle::new(rect, corners);
@@ -237,8 +868,13 @@ impl Menu {
         Ok(())
     }
 
-    /// Indicate which item is currently selected.
-    pub fn draw_battery<D, C, E>(&self, display: &mut D, battery: &Option<Battery>) -> Result<(), E>
+    /// Show the battery charge.
+    pub fn draw_battery<D, C, E>(
+        &self,
+        display: &mut D,
+        battery: &Option<Battery>,
+        theme: &Theme,
+    ) -> Result<(), E>
     where
         D: DrawTarget<Color = C, Error = E> + OriginDimensions,
         C: RgbColor + FromRGB,
@@ -258,7 +894,11 @@ impl Menu {
             let width = width.clamp(1, max_width);
             let width = if battery.full { max_width } else { width };
             let size = Size::new(width, height);
-            let color = if percent <= 20 { C::DANGER } else { C::ACCENT };
+            let color: C = if percent <= 20 {
+                theme.danger()
+            } else {
+                theme.accent()
+            };
             let box_style = PrimitiveStyle::with_fill(color);
             let rect = Rectangle::new(point, size);
             let rect = RoundedRectangle::new(rect, corners);
@@ -268,7 +908,7 @@ impl Menu {
         // Draw box.
         {
             let size = Size::new(max_width, height);
-            let box_style = PrimitiveStyle::with_stroke(C::PRIMARY, 1);
+            let box_style = PrimitiveStyle::with_stroke(theme.primary(), 1);
             let rect = Rectangle::new(point, size);
             let rect = RoundedRectangle::new(rect, corners);
             rect.draw_styled(&box_style, display)?;
@@ -277,7 +917,7 @@ impl Menu {
         // Draw nibble on the right end.
         {
             let size = Size::new(1, 5);
-            let box_style = PrimitiveStyle::with_fill(C::PRIMARY);
+            let box_style = PrimitiveStyle::with_fill(theme.primary());
             let point = point + Point::new(max_width as _, 3);
             let rect = Rectangle::new(point, size);
             rect.draw_styled(&box_style, display)?;
@@ -286,7 +926,7 @@ impl Menu {
         // Draw indicator of charging (a lighting).
         if battery.connected && !battery.full {
             let center = point + Point::new(max_width as i32 / 2, height as i32 / 2);
-            let style = PrimitiveStyle::with_fill(C::PRIMARY);
+            let style = PrimitiveStyle::with_fill(theme.primary());
 
             let triangle = Triangle::new(
                 Point::new(center.x - 6, center.y),
@@ -306,3 +946,185 @@ impl Menu {
         Ok(())
     }
 }
+
+/// Dim the whole screen by covering every other pixel with the background color.
+fn draw_dither<D, C, E>(display: &mut D, theme: &Theme) -> Result<(), E>
+where
+    D: DrawTarget<Color = C, Error = E> + OriginDimensions,
+    C: RgbColor + FromRGB,
+{
+    let color: C = theme.bg();
+    let area = Rectangle::new(Point::zero(), display.size());
+    let pixels = area.points().filter(|p| (p.x + p.y) % 2 == 0);
+    display.draw_iter(pixels.map(|p| Pixel(p, color)))
+}
+
+/// Format seconds as "m:ss" or, if longer than an hour, as "h:mm:ss".
+fn format_play_time(secs: u32) -> String {
+    let hours = secs / 3600;
+    let minutes = secs / 60 % 60;
+    let secs = secs % 60;
+    if hours == 0 {
+        alloc::format!("{minutes}:{secs:02}")
+    } else {
+        alloc::format!("{hours}:{minutes:02}:{secs:02}")
+    }
+}
+
+/// Cut the text that doesn't fit on the screen.
+fn truncate(text: &str) -> Cow<'_, str> {
+    if text.chars().count() <= MAX_CHARS {
+        return Cow::Borrowed(text);
+    }
+    let mut text: String = text.chars().take(MAX_CHARS - 3).collect();
+    text.push_str("...");
+    Cow::Owned(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use firefly_hal::Pad;
+
+    fn press(menu: &mut Menu, buttons: u8, pad: Option<Pad>) -> Option<(u8, u32)> {
+        let input = Some(InputState { pad, buttons });
+        match menu.handle_input(&input) {
+            Some(MenuItem::Custom(item)) => Some((item.index, item.value())),
+            _ => None,
+        }
+    }
+
+    /// Press and release A.
+    fn select(menu: &mut Menu) -> Option<(u8, u32)> {
+        assert_eq!(press(menu, 0b1, None), None);
+        press(menu, 0, None)
+    }
+
+    #[test]
+    fn test_submenu_and_toggle() {
+        let mut menu = Menu::new();
+        menu.add(1, "options".into(), ItemKind::Submenu);
+        menu.add(2, "sound".into(), ItemKind::Toggle(false));
+        assert!(menu.set_parent(2, 1));
+        menu.activate();
+        // The toggle is in the submenu, so the submenu is the first root item.
+        assert_eq!(select(&mut menu), None);
+        assert_eq!(menu.items().count(), 2);
+        assert_eq!(select(&mut menu), Some((2, 1)));
+        assert!(menu.active());
+        assert_eq!(select(&mut menu), Some((2, 0)));
+
+        // Go down to the "back" item and select it.
+        let down = Some(Pad { x: 0, y: -100 });
+        assert_eq!(press(&mut menu, 0, down), None);
+        assert_eq!(select(&mut menu), None);
+        assert!(matches!(menu.items().next(), Some(MenuItem::Custom(_))));
+        assert_eq!(menu.items().count(), 8);
+    }
+
+    #[test]
+    fn test_set_parent() {
+        let mut menu = Menu::new();
+        menu.add(1, "options".into(), ItemKind::Submenu);
+        menu.add(2, "video".into(), ItemKind::Submenu);
+        menu.add(3, "sound".into(), ItemKind::Toggle(false));
+        assert!(menu.set_parent(2, 1));
+        assert!(menu.set_parent(3, 2));
+        // The parent doesn't exist or is not a submenu.
+        assert!(!menu.set_parent(2, 4));
+        assert!(!menu.set_parent(1, 3));
+        // Cycles.
+        assert!(!menu.set_parent(1, 1));
+        assert!(!menu.set_parent(1, 2));
+        assert_eq!(menu.get_mut(1).unwrap().parent, None);
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("hello"), "hello");
+        let long = "a".repeat(50);
+        let short = truncate(&long);
+        assert_eq!(short.len(), MAX_CHARS);
+        assert!(short.ends_with("..."));
+    }
+
+    #[test]
+    fn test_scroll() {
+        let mut menu = Menu::new();
+        for i in 0..20 {
+            menu.add(i, "item".into(), ItemKind::Button);
+        }
+        menu.selected = 15;
+        menu.scroll_to_selected();
+        assert_eq!(menu.scroll, 5);
+        menu.selected = 2;
+        menu.scroll_to_selected();
+        assert_eq!(menu.scroll, 2);
+    }
+
+    #[test]
+    fn test_slider_and_separator() {
+        let mut menu = Menu::new();
+        menu.add(1, "first".into(), ItemKind::Separator);
+        menu.add(2, "".into(), ItemKind::Separator);
+        let kind = ItemKind::Slider {
+            value: 2,
+            min: 0,
+            max: 3,
+            step: 2,
+        };
+        menu.add(3, "difficulty".into(), kind);
+        menu.activate();
+        menu.selected = 0;
+        // Skip the separator when moving down.
+        assert_eq!(press(&mut menu, 0, Some(Pad { x: 0, y: -100 })), None);
+        assert_eq!(menu.selected, 2);
+        assert_eq!(press(&mut menu, 0, None), None);
+        let right = Some(Pad { x: 100, y: 0 });
+        assert_eq!(press(&mut menu, 0, right.clone()), Some((3, 3)));
+        assert_eq!(press(&mut menu, 0, right.clone()), None);
+        assert_eq!(press(&mut menu, 0, None), None);
+        // Already at max.
+        assert_eq!(press(&mut menu, 0, right), None);
+        assert_eq!(press(&mut menu, 0, None), None);
+        let left = Some(Pad { x: -100, y: 0 });
+        assert_eq!(press(&mut menu, 0, left), Some((3, 1)));
+    }
+
+    #[test]
+    fn test_submenu_first_selected() {
+        let mut menu = Menu::new();
+        menu.add(1, "options".into(), ItemKind::Submenu);
+        menu.add(2, "".into(), ItemKind::Separator);
+        menu.add(3, "locked".into(), ItemKind::Button);
+        menu.add(4, "sound".into(), ItemKind::Toggle(false));
+        for index in 2..=4 {
+            assert!(menu.set_parent(index, 1));
+        }
+        menu.get_mut(3).unwrap().disabled = true;
+        menu.activate();
+        assert_eq!(select(&mut menu), None);
+        assert_eq!(menu.selected, 2);
+        assert_eq!(select(&mut menu), Some((4, 1)));
+    }
+
+    #[test]
+    fn test_lang() {
+        let mut menu = Menu::new();
+        menu.set_input_profile(&InputProfile::default());
+        assert_eq!(BACK.as_str(Lang::English), "< back");
+        menu.set_lang(Lang::German);
+        assert_eq!(BACK.as_str(menu.lang), "< zurück");
+        let MenuItem::Input(_, label) = &menu.input_items[0] else {
+            unreachable!()
+        };
+        assert_eq!(label, "A/B tauschen: aus");
+    }
+
+    #[test]
+    fn test_format_play_time() {
+        assert_eq!(format_play_time(0), "0:00");
+        assert_eq!(format_play_time(75), "1:15");
+        assert_eq!(format_play_time(3600 + 62), "1:01:02");
+    }
+}
@@ -1,4 +1,5 @@
 use super::*;
+use crate::i18n;
 use alloc::boxed::Box;
 use firefly_hal::*;
 
@@ -75,10 +76,10 @@ impl<'a> Connector<'a> {
         Ok(())
     }
 
-    pub fn validate(&self) -> Result<(), &'static str> {
+    pub fn validate(&self) -> Result<(), i18n::Message> {
         for peer in &self.peer_infos {
             if peer.intro.version != self.me.version {
-                return Err("devices have incompatible OS versions; please, update.");
+                return Err(i18n::Message::IncompatibleVersions);
             }
         }
         Ok(())
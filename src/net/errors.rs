@@ -1,3 +1,5 @@
+use crate::i18n::{Lang, Message};
+use alloc::string::String;
 use core::fmt;
 
 pub(crate) enum NetcodeError {
@@ -16,6 +18,23 @@ pub(crate) enum NetcodeError {
     StashFileError(firefly_hal::FSError),
 }
 
+impl NetcodeError {
+    /// The error message in the language of the system UI.
+    ///
+    /// Only the errors that the user can act upon are translated.
+    /// The rest are for bug reports and stay in English.
+    pub fn localize(&self, lang: Lang) -> String {
+        match self {
+            Self::PeerListFull => Message::PeerListFull.translate(lang).into(),
+            Self::Disconnected(name) => {
+                let msg = Message::Disconnected.translate(lang);
+                alloc::format!("{msg}: {name}")
+            }
+            _ => alloc::format!("{self}"),
+        }
+    }
+}
+
 impl From<firefly_hal::NetworkError> for NetcodeError {
     fn from(v: firefly_hal::NetworkError) -> Self {
         Self::Network(v)
@@ -0,0 +1,224 @@
+use firefly_hal::{InputState, Pad};
+use serde::{Deserialize, Serialize};
+
+/// The bit of the menu button. It is never remapped.
+const MENU_BUTTON: u8 = 0b10000;
+/// For how many frames turbo buttons stay pressed and then released.
+const TURBO_FRAMES: u32 = 4;
+/// Sensitivity steps (in percents) the user can switch between in the menu.
+pub(crate) const SENSITIVITY_STEPS: [u8; 5] = [50, 75, 100, 150, 200];
+/// The radius of the touch pad. Coordinates of a touch are never farther from the center.
+const PAD_RADIUS: i64 = 1000;
+/// Button masks the user can switch between in the menu for toggle and turbo.
+pub(crate) const BUTTON_MASKS: [u8; 6] = [0b0000, 0b0001, 0b0010, 0b0100, 0b1000, 0b1111];
+
+/// User-configurable input remapping.
+///
+/// Stored per app in `data/<author>/<app>/input` and globally in `sys/input`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub(crate) struct InputProfile {
+    /// Swap the A and B buttons.
+    pub swap_ab: bool,
+    /// Buttons that are pressed by the first tap and released by the second one.
+    pub toggle: u8,
+    /// Buttons that are repeatedly pressed and released while held.
+    pub turbo: u8,
+    /// The touch pad sensitivity, in percents.
+    pub sensitivity: u8,
+    pub invert_x: bool,
+    pub invert_y: bool,
+}
+
+impl Default for InputProfile {
+    fn default() -> Self {
+        Self {
+            swap_ab: false,
+            toggle: 0,
+            turbo: 0,
+            sensitivity: 100,
+            invert_x: false,
+            invert_y: false,
+        }
+    }
+}
+
+impl InputProfile {
+    pub fn decode(raw: &[u8]) -> Result<Self, postcard::Error> {
+        postcard::from_bytes(raw)
+    }
+
+    pub fn encode(&self) -> Result<alloc::vec::Vec<u8>, postcard::Error> {
+        postcard::to_allocvec(self)
+    }
+}
+
+/// Applies the [`InputProfile`] to the device input.
+#[derive(Default)]
+pub(crate) struct Remapper {
+    pub profile: InputProfile,
+    frame: u32,
+    /// Buttons pressed on the device on the previous frame, after swapping.
+    prev: u8,
+    /// Toggle buttons that are currently on.
+    toggled: u8,
+    /// Buttons reported to the app on the previous frame.
+    output: u8,
+}
+
+impl Remapper {
+    pub fn new(profile: InputProfile) -> Self {
+        Self {
+            profile,
+            ..Self::default()
+        }
+    }
+
+    /// Remap the input for the next frame.
+    pub fn apply(&mut self, input: Option<InputState>) -> Option<InputState> {
+        self.frame = self.frame.wrapping_add(1);
+        let input = input.unwrap_or_default();
+        let raw = self.swap(input.buttons);
+        let toggle = self.profile.toggle & !MENU_BUTTON;
+        let turbo = self.profile.turbo & !MENU_BUTTON & !toggle;
+
+        let just_pressed = raw & !self.prev;
+        self.toggled ^= just_pressed & toggle;
+        self.prev = raw;
+
+        let mut buttons = raw & !toggle & !turbo;
+        buttons |= self.toggled;
+        if (self.frame / TURBO_FRAMES).is_multiple_of(2) {
+            buttons |= raw & turbo;
+        }
+        self.output = buttons;
+
+        let pad = input.pad.map(|pad| self.remap_pad(pad));
+        if pad.is_none() && buttons == 0 {
+            return None;
+        }
+        Some(InputState { pad, buttons })
+    }
+
+    /// Remap buttons read between frames without advancing toggle and turbo state.
+    pub fn peek(&self, buttons: u8) -> u8 {
+        let sticky = (self.profile.toggle | self.profile.turbo) & !MENU_BUTTON;
+        (self.swap(buttons) & !sticky) | (self.output & sticky)
+    }
+
+    fn swap(&self, buttons: u8) -> u8 {
+        if !self.profile.swap_ab {
+            return buttons;
+        }
+        let a = buttons & 0b01;
+        let b = buttons & 0b10;
+        (buttons & !0b11) | (a << 1) | (b >> 1)
+    }
+
+    fn remap_pad(&self, pad: Pad) -> Pad {
+        let scale = |v: i16, invert: bool| {
+            let v = i64::from(v) * i64::from(self.profile.sensitivity) / 100;
+            if invert {
+                -v
+            } else {
+                v
+            }
+        };
+        let mut x = scale(pad.x, self.profile.invert_x);
+        let mut y = scale(pad.y, self.profile.invert_y);
+        // With a higher sensitivity, the edge of the pad is reached sooner.
+        // Keep the direction but don't let the touch go beyond the edge.
+        let distance = (x * x + y * y).isqrt();
+        if distance > PAD_RADIUS {
+            x = x * PAD_RADIUS / distance;
+            y = y * PAD_RADIUS / distance;
+        }
+        Pad {
+            x: x as i16,
+            y: y as i16,
+        }
+    }
+}
+
+/// Switch to the next value in the list of allowed values.
+pub(crate) fn next_step(steps: &[u8], current: u8) -> u8 {
+    let idx = steps.iter().position(|v| *v == current);
+    let next = idx.map_or(0, |idx| (idx + 1) % steps.len());
+    steps[next]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buttons(buttons: u8) -> Option<InputState> {
+        Some(InputState { pad: None, buttons })
+    }
+
+    fn apply(remapper: &mut Remapper, input: u8) -> u8 {
+        remapper.apply(buttons(input)).map_or(0, |i| i.buttons)
+    }
+
+    #[test]
+    fn test_swap_ab() {
+        let mut remapper = Remapper::new(InputProfile {
+            swap_ab: true,
+            ..InputProfile::default()
+        });
+        assert_eq!(apply(&mut remapper, 0b00001), 0b00010);
+        assert_eq!(apply(&mut remapper, 0b10110), 0b10101);
+        assert_eq!(remapper.peek(0b00010), 0b00001);
+    }
+
+    #[test]
+    fn test_toggle() {
+        let mut remapper = Remapper::new(InputProfile {
+            toggle: 0b1,
+            ..InputProfile::default()
+        });
+        assert_eq!(apply(&mut remapper, 0b1), 0b1);
+        assert_eq!(apply(&mut remapper, 0b0), 0b1);
+        assert_eq!(remapper.peek(0b0), 0b1);
+        assert_eq!(apply(&mut remapper, 0b1), 0b0);
+        assert_eq!(apply(&mut remapper, 0b0), 0b0);
+    }
+
+    #[test]
+    fn test_turbo() {
+        let mut remapper = Remapper::new(InputProfile {
+            turbo: 0b1,
+            ..InputProfile::default()
+        });
+        let outputs: alloc::vec::Vec<u8> = (0..8).map(|_| apply(&mut remapper, 0b1)).collect();
+        assert_eq!(outputs, [1, 1, 1, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_pad() {
+        let mut remapper = Remapper::new(InputProfile {
+            sensitivity: 200,
+            invert_y: true,
+            ..InputProfile::default()
+        });
+        let input = InputState {
+            pad: Some(Pad { x: 10, y: 20 }),
+            buttons: 0,
+        };
+        let pad = remapper.apply(Some(input)).unwrap().pad.unwrap();
+        assert_eq!((pad.x, pad.y), (20, -40));
+
+        // The touch doesn't go beyond the pad edge.
+        let input = InputState {
+            pad: Some(Pad { x: 600, y: 800 }),
+            buttons: 0,
+        };
+        let pad = remapper.apply(Some(input)).unwrap().pad.unwrap();
+        assert_eq!((pad.x, pad.y), (600, -800));
+    }
+
+    #[test]
+    fn test_next_step() {
+        assert_eq!(next_step(&SENSITIVITY_STEPS, 100), 150);
+        assert_eq!(next_step(&SENSITIVITY_STEPS, 200), 50);
+        assert_eq!(next_step(&SENSITIVITY_STEPS, 42), 50);
+    }
+}
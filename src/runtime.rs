@@ -1,12 +1,18 @@
-use crate::color::FromRGB;
+use crate::audio::encode_samples;
+use crate::color::{FromRGB, Rgb16};
 use crate::config::{FullID, RuntimeConfig};
 use crate::error::Error;
 use crate::frame_buffer::RenderFB;
+use crate::instrument::{instrument, CURRENT_FUNC};
 use crate::linking::populate_externals;
 use crate::state::{NetHandler, State};
-use crate::stats::StatsTracker;
+use crate::stats::{HostProfiler, Sampler, StatsTracker};
+use crate::suspend::{self, SavedVal, Snapshot, CRITICAL_BATTERY};
 use crate::utils::read_all;
+use crate::watch::{format_memory, Command, Watches};
 use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
 use alloc::vec::Vec;
 use embedded_graphics::draw_target::DrawTarget;
 use embedded_graphics::geometry::OriginDimensions;
@@ -19,6 +25,8 @@ use firefly_types::*;
 const FPS: u8 = 60;
 const KB: u32 = 1024;
 const FUEL_PER_CALL: u64 = 10_000_000;
+/// How often (in fuel units) the sampling profiler interrupts the guest code.
+const FUEL_PER_SAMPLE: u64 = 20_000;
 
 pub struct Runtime<'a, D, C>
 where
@@ -34,6 +42,16 @@ where
     before_exit: Option<wasmi::TypedFunc<(), ()>>,
     cheat: Option<wasmi::TypedFunc<(i32, i32), (i32,)>>,
     handle_menu: Option<wasmi::TypedFunc<(u32,), ()>>,
+    handle_menu_value: Option<wasmi::TypedFunc<(u32, u32), ()>>,
+    audio_event: Option<wasmi::TypedFunc<(u32,), ()>>,
+    handle_keyboard: Option<wasmi::TypedFunc<(u32,), ()>>,
+
+    /// The snapshot of the app to restore on start instead of booting it.
+    resume: Option<Snapshot>,
+    /// The size of the app binary, saved in snapshots.
+    bin_size: u32,
+    /// True if the app has been saved into a snapshot.
+    suspended: bool,
 
     /// Time to render a single frame to match the expected FPS.
     per_frame: Duration,
@@ -47,6 +65,12 @@ where
     render_every: u8,
 
     stats: Option<StatsTracker>,
+    sampler: Option<Sampler>,
+    /// Names of guest functions, known only if the app was instrumented for sampling.
+    func_names: BTreeMap<u32, String>,
+    /// The global holding the index of the running guest function.
+    current_func: Option<wasmi::Global>,
+    watches: Watches,
     serial: SerialImpl,
 }
 
@@ -56,12 +80,37 @@ where
     C: RgbColor + FromRGB,
 {
     /// Create a new runtime with the wasm module loaded and instantiated.
-    pub fn new(mut config: RuntimeConfig<'a, D, C>) -> Result<Self, Error> {
+    pub fn new(config: RuntimeConfig<'a, D, C>) -> Result<Self, Error> {
+        Self::load(config, false)
+    }
+
+    /// Like [`Runtime::new`] but with the sampling profiler enabled.
+    ///
+    /// The app binary is instrumented to track the running guest function,
+    /// so that the samples can be attributed to it. The instrumentation
+    /// slows the app down, so it's done only if requested on start.
+    pub fn new_with_sampling(config: RuntimeConfig<'a, D, C>) -> Result<Self, Error> {
+        let mut runtime = Self::load(config, true)?;
+        runtime.set_sampling(true);
+        Ok(runtime)
+    }
+
+    fn load(mut config: RuntimeConfig<'a, D, C>, sampling: bool) -> Result<Self, Error> {
+        // If no app is requested, resume the suspended app (if any)
+        // instead of starting the launcher.
+        let mut resume = None;
         let id = match config.id {
             Some(id) => id,
-            None => match detect_launcher(&mut config.device) {
-                Some(id) => id,
-                None => return Err(Error::NoLauncher),
+            None => match suspend::load(&mut config.device) {
+                Some(snapshot) => {
+                    let id = snapshot.id.clone();
+                    resume = Some(snapshot);
+                    id
+                }
+                None => match detect_launcher(&mut config.device) {
+                    Some(id) => id,
+                    None => return Err(Error::NoLauncher),
+                },
             },
         };
         id.validate()?;
@@ -104,6 +153,11 @@ where
             Ok(bin_size) => Ok(bin_size),
             Err(err) => Err(Error::OpenFile("_bin", err)),
         }?;
+        // The app was updated since it was suspended, start it fresh.
+        if resume.as_ref().is_some_and(|s| s.bin_size != bin_size) {
+            resume = None;
+            suspend::remove(&mut config.device);
+        }
 
         let engine = {
             let mut wasmi_config = wasmi::Config::default();
@@ -122,6 +176,7 @@ where
             config.net_handler,
             launcher,
         );
+        state.menu.set_app_name(meta.app_name);
         state.load_app_stats()?;
         state.load_stash()?;
 
@@ -150,12 +205,22 @@ where
 
         let mut store = wasmi::Store::new(&engine, state);
         _ = store.set_fuel(FUEL_PER_CALL);
+        store.limiter(|state| &mut state.memory_growth);
+        let mut func_names = BTreeMap::new();
         let instance = {
-            let module = wasmi::Module::new(&engine, wasm_bin)?;
+            let module = if sampling {
+                let instrumented = instrument(&wasm_bin).map_err(Error::Instrument)?;
+                drop(wasm_bin);
+                func_names = instrumented.names;
+                wasmi::Module::new(&engine, instrumented.wasm)?
+            } else {
+                wasmi::Module::new(&engine, wasm_bin)?
+            };
             let mut externals = Vec::new();
             populate_externals(&mut store, &module, sudo, &mut externals)?;
             wasmi::Instance::new(&mut store, &module, &externals)?
         };
+        let current_func = instance.get_global(&store, CURRENT_FUNC);
 
         let runtime = Self {
             display: config.display,
@@ -166,7 +231,17 @@ where
             before_exit: None,
             cheat: None,
             handle_menu: None,
+            handle_menu_value: None,
+            audio_event: None,
+            handle_keyboard: None,
+            resume,
+            bin_size,
+            suspended: false,
             stats: None,
+            sampler: None,
+            func_names,
+            current_func,
+            watches: Watches::default(),
             per_frame: Duration::from_fps(u32::from(FPS)),
             n_frames: 0,
             lagging_frames: 0,
@@ -186,6 +261,125 @@ where
         self.render_every = render_every;
     }
 
+    /// Enable or disable the sampling profiler.
+    ///
+    /// When enabled, the guest callbacks are periodically interrupted
+    /// and the collected flat profile is streamed over serial.
+    /// Can be also toggled over serial by sending `sampling:on` or `sampling:off`.
+    ///
+    /// The running guest function is known only if the runtime was created
+    /// with [`Runtime::new_with_sampling`]. Otherwise, the samples
+    /// are attributed only to the running callback.
+    pub fn set_sampling(&mut self, enabled: bool) {
+        if enabled && self.sampler.is_none() {
+            self.sampler = Some(Sampler::default());
+        }
+        if !enabled {
+            self.sampler = None;
+        }
+    }
+
+    /// Start or stop capturing the audio output.
+    ///
+    /// The audio is written as a WAV file into the app data dir.
+    /// Starting the capture again overwrites the previous recording.
+    pub fn set_audio_capture(&mut self, enabled: bool) {
+        let state = self.store.data_mut();
+        if enabled && state.audio_capture.is_none() {
+            state.start_audio_capture();
+        }
+        if !enabled {
+            state.stop_audio_capture();
+        }
+    }
+
+    /// Send typed text to the app.
+    ///
+    /// Used by the emulator to forward the keyboard input.
+    /// The app reads the text using `input.read_text`.
+    pub fn push_text(&mut self, text: &str) {
+        let state = self.store.data_mut();
+        state.text_input.push(text);
+    }
+
+    /// Read a range of the guest memory.
+    pub fn read_memory(&self, ptr: u32, len: u32) -> Result<Vec<u8>, Error> {
+        let state = self.store.data();
+        let Some(memory) = state.memory else {
+            return Err(Error::MemoryNotFound);
+        };
+        let data = memory.data(&self.store);
+        let ptr = ptr as usize;
+        let Some(end) = ptr.checked_add(len as usize) else {
+            return Err(Error::MemoryOutOfBounds);
+        };
+        let Some(buf) = data.get(ptr..end) else {
+            return Err(Error::MemoryOutOfBounds);
+        };
+        Ok(buf.to_vec())
+    }
+
+    /// Overwrite a range of the guest memory.
+    ///
+    /// Like cheats, disabled in multiplayer.
+    pub fn write_memory(&mut self, ptr: u32, buf: &[u8]) -> Result<(), Error> {
+        let state = self.store.data_mut();
+        if !matches!(state.net_handler.get_mut(), NetHandler::None) {
+            return Err(Error::CheatInNet);
+        }
+        let Some(memory) = state.memory else {
+            return Err(Error::MemoryNotFound);
+        };
+        let data = memory.data_mut(&mut self.store);
+        let ptr = ptr as usize;
+        let Some(end) = ptr.checked_add(buf.len()) else {
+            return Err(Error::MemoryOutOfBounds);
+        };
+        let Some(target) = data.get_mut(ptr..end) else {
+            return Err(Error::MemoryOutOfBounds);
+        };
+        target.copy_from_slice(buf);
+        Ok(())
+    }
+
+    /// Get the value of a global exported by the app.
+    pub fn read_global(&self, name: &str) -> Result<wasmi::Val, Error> {
+        let Some(global) = self.instance.get_global(&self.store, name) else {
+            return Err(Error::GlobalNotFound);
+        };
+        Ok(global.get(&self.store))
+    }
+
+    /// Set the value of a mutable global exported by the app.
+    ///
+    /// Like cheats, disabled in multiplayer.
+    pub fn write_global(&mut self, name: &str, val: wasmi::Val) -> Result<(), Error> {
+        let state = self.store.data_mut();
+        if !matches!(state.net_handler.get_mut(), NetHandler::None) {
+            return Err(Error::CheatInNet);
+        }
+        let Some(global) = self.instance.get_global(&self.store, name) else {
+            return Err(Error::GlobalNotFound);
+        };
+        let res = global.set(&mut self.store, val);
+        if res.is_err() {
+            return Err(Error::GlobalWrite);
+        }
+        Ok(())
+    }
+
+    /// Send the given range of the guest memory over serial every `every` update cycles.
+    ///
+    /// A watch on the same address is replaced.
+    pub fn watch_memory(&mut self, ptr: u32, len: u32, every: u32) {
+        self.watches.add(ptr, len, every);
+    }
+
+    /// Stop sending the memory range starting at the given address.
+    pub fn unwatch_memory(&mut self, ptr: u32) {
+        self.watches.remove(ptr);
+    }
+
     pub fn display_mut(&mut self) -> &mut D {
         &mut self.display
     }
@@ -203,14 +397,33 @@ where
         self.set_memory();
 
         let ins = self.instance;
-        // The `_initialize` and `_start` functions are defined by wasip1.
-        let f = ins.get_typed_func::<(), ()>(&self.store, "_initialize");
-        self.call_callback("_initialize", f.ok())?;
-        let f = ins.get_typed_func::<(), ()>(&self.store, "_start");
-        self.call_callback("_start", f.ok())?;
-        // The `boot` function is defined by our spec.
-        let f = ins.get_typed_func::<(), ()>(&self.store, "boot");
-        self.call_callback("boot", f.ok())?;
+        // The `resume` function is defined by our spec. It's called
+        // instead of `boot` to recreate host-side resources, like audio.
+        // Apps that don't define it can't get these resources back,
+        // so they are started fresh instead.
+        let resume = ins.get_typed_func::<(), ()>(&self.store, "resume").ok();
+        let snapshot = self.resume.take();
+        if snapshot.is_some() && resume.is_none() {
+            let state = self.store.data_mut();
+            suspend::remove(&mut state.device);
+        }
+        if let (Some(snapshot), Some(resume)) = (snapshot, resume) {
+            let res = self.restore(&snapshot);
+            // Resume only once, even if restoring or running the app fails.
+            let state = self.store.data_mut();
+            suspend::remove(&mut state.device);
+            res?;
+            self.call_callback("resume", Some(resume))?;
+        } else {
+            // The `_initialize` and `_start` functions are defined by wasip1.
+            let f = ins.get_typed_func::<(), ()>(&self.store, "_initialize");
+            self.call_callback("_initialize", f.ok())?;
+            let f = ins.get_typed_func::<(), ()>(&self.store, "_start");
+            self.call_callback("_start", f.ok())?;
+            // The `boot` function is defined by our spec.
+            let f = ins.get_typed_func::<(), ()>(&self.store, "boot");
+            self.call_callback("boot", f.ok())?;
+        }
 
         // Other functions defined by our spec.
         self.update = ins.get_typed_func(&self.store, "update").ok();
@@ -218,6 +431,9 @@ where
         self.before_exit = ins.get_typed_func(&self.store, "before_exit").ok();
         self.cheat = ins.get_typed_func(&self.store, "cheat").ok();
         self.handle_menu = ins.get_typed_func(&self.store, "handle_menu").ok();
+        self.handle_menu_value = ins.get_typed_func(&self.store, "handle_menu").ok();
+        self.audio_event = ins.get_typed_func(&self.store, "audio_event").ok();
+        self.handle_keyboard = ins.get_typed_func(&self.store, "handle_keyboard").ok();
         Ok(())
     }
 
@@ -228,74 +444,85 @@ where
     pub fn update(&mut self) -> Result<bool, Error> {
         self.handle_serial()?;
         let state = self.store.data_mut();
-        let menu_was_active = state.menu.active();
+        let overlay_was_active = state.menu.active() || state.keyboard.is_some();
         let menu_index = state.update();
 
         if let Some(scene) = &mut state.error {
-            let res = scene.render(&mut self.display);
+            let res = scene.render(&mut self.display, &state.theme);
             if res.is_err() {
                 return Err(Error::CannotDisplay);
             }
+            self.write_audio();
             return Ok(false);
         }
 
-        // TODO: pause audio when opening menu
+        // If a custom menu item is selected, trigger the handle_menu callback.
+        // Toggles and sliders keep the menu open, so it's done even if the menu is active.
+        if let Some((index, value)) = menu_index {
+            self.call_handle_menu(index, value)?;
+        }
+
+        // The battery status is used both by the menu and for suspending the app.
+        if self.n_frames.is_multiple_of(60) {
+            self.check_battery();
+        }
+
+        let state = self.store.data_mut();
         let menu_is_active = state.menu.active();
         if menu_is_active {
-            if self.n_frames.is_multiple_of(60) {
-                if let Some(battery) = &mut state.battery {
-                    let res = battery.update(&mut state.device);
-                    if let Err(err) = res {
-                        state.device.log_error("battery", err);
-                    }
-                }
-            }
             // We render the system menu directly on the screen,
             // bypassing the frame buffer. That way, we preserve
             // the frame buffer rendered by the app.
             // Performance isn't an issue for a simple text menu.
-            let res = state.menu.render(&mut self.display, &state.battery);
+            state.menu.set_play_time(state.play_time());
+            let frame = &mut state.frame;
+            let theme = &state.theme;
+            let res = state
+                .menu
+                .render(&mut self.display, frame, &state.battery, theme);
+            if res.is_err() {
+                return Err(Error::CannotDisplay);
+            }
+            // Keep counting frames so that the battery is polled once a second.
+            self.n_frames = (self.n_frames + 1) % (FPS * 4);
+            self.write_audio();
+            self.delay();
+            return Ok(false);
+        }
+        if let Some(keyboard) = &mut state.keyboard {
+            // Like the menu, the keyboard is rendered directly on the screen.
+            let res = keyboard.render(&mut self.display, &state.theme);
             if res.is_err() {
                 return Err(Error::CannotDisplay);
             }
+            self.write_audio();
             self.delay();
             return Ok(false);
-        } else if menu_was_active {
+        }
+        if overlay_was_active {
             state.frame.dirty = true;
             if self.render.is_none() {
-                // When menu was open but now closed, if the app doesn't have the `render`
-                // callback defined, the screen flushing will never be called.
+                // When menu (or keyboard) was open but now closed, if the app
+                // doesn't have the `render` callback defined,
+                // the screen flushing will never be called.
                 // As a result, the menu image will stuck on the display.
                 // To avoid that, we fill the screen with a color.
                 //
                 // The color is the same as the menu background color
                 // to avoid flashing that may cause an epilepsy episode.
-                _ = self.display.clear(C::BG);
+                _ = self.display.clear(state.theme.bg());
             }
         }
 
-        // If a custom menu item is selected, trigger the handle_menu callback.
-        if let Some(custom_menu) = menu_index {
-            if let Some(handle_menu) = self.handle_menu {
-                if let Err(err) = handle_menu.call(&mut self.store, (custom_menu as u32,)) {
-                    let stats = self.store.data().runtime_stats();
-                    return Err(Error::FuncCall("handle_menu", err, stats));
-                };
-            }
-        }
+        self.send_keyboard_result()?;
 
         // TODO: continue execution even if an update fails.
         let fuel_update = self.call_callback("update", self.update)?;
         if let Some(stats) = &mut self.stats {
             stats.update_fuel.add(fuel_update);
         }
-        {
-            let state = self.store.data_mut();
-            let audio_buf = state.device.get_audio_buffer();
-            if !audio_buf.is_empty() {
-                state.audio.write(audio_buf);
-            }
-        }
+        self.write_audio();
+        self.send_audio_events()?;
 
         // Check if the app is lagging.
         // Adjust, if needed, how often "render" is called.
@@ -329,9 +556,256 @@ where
         Ok(state.exit)
     }
 
+    /// Render the audio graph into the device audio buffer.
+    ///
+    /// While the system menu or an error is shown, the app audio
+    /// is paused or ducked, depending on the audio settings.
+    fn write_audio(&mut self) {
+        let state = self.store.data_mut();
+        if state.menu.active() || state.error.is_some() {
+            state.audio_master.suspend(state.audio_settings.menu);
+        } else {
+            state.audio_master.resume();
+        }
+        state.refill_rewinds();
+        state.configure_audio();
+        let audio_buf = state.device.get_audio_buffer();
+        if audio_buf.is_empty() {
+            return;
+        }
+        if state.audio_master.is_paused() {
+            audio_buf.fill(0);
+        } else {
+            let scheduler = &mut state.audio_scheduler;
+            scheduler.render(&mut state.audio, &state.audio_tree, audio_buf);
+            state.audio_master.apply(audio_buf);
+        }
+        if state.audio_capture.is_some() {
+            let raw = encode_samples(audio_buf);
+            state.capture_audio(&raw);
+        }
+    }
+
+    /// Call the `handle_menu` callback for the selected custom menu item.
+    ///
+    /// Older apps define the callback accepting only the item index.
+    /// Newer apps also accept the item value (the state of a toggle or a slider).
+    fn call_handle_menu(&mut self, index: u8, value: u32) -> Result<(), Error> {
+        let res = if let Some(handle_menu) = self.handle_menu_value {
+            handle_menu.call(&mut self.store, (u32::from(index), value))
+        } else if let Some(handle_menu) = self.handle_menu {
+            handle_menu.call(&mut self.store, (u32::from(index),))
+        } else {
+            return Ok(());
+        };
+        if let Err(err) = res {
+            let stats = self.store.data().runtime_stats();
+            return Err(Error::FuncCall("handle_menu", err, stats));
+        }
+        Ok(())
+    }
+
+    /// Write the text entered on the on-screen keyboard into the guest memory
+    /// and call the `handle_keyboard` callback.
+    fn send_keyboard_result(&mut self) -> Result<(), Error> {
+        let state = self.store.data_mut();
+        let Some((ptr, text)) = state.keyboard_result.take() else {
+            return Ok(());
+        };
+        let Some(memory) = state.memory else {
+            return Err(Error::MemoryNotFound);
+        };
+        let data = memory.data_mut(&mut self.store);
+        let ptr = ptr as usize;
+        let Some(end) = ptr.checked_add(text.len()) else {
+            return Err(Error::MemoryOutOfBounds);
+        };
+        let Some(target) = data.get_mut(ptr..end) else {
+            return Err(Error::MemoryOutOfBounds);
+        };
+        target.copy_from_slice(text.as_bytes());
+        let len = text.len() as u32;
+        self.call_callback_with("handle_keyboard", self.handle_keyboard, (len,))?;
+        Ok(())
+    }
+
+    /// Call the `audio_event` callback for every flagged audio node that finished playing.
+    fn send_audio_events(&mut self) -> Result<(), Error> {
+        if self.audio_event.is_none() {
+            return Ok(());
+        }
+        let state = self.store.data();
+        let finished: Vec<u32> = state
+            .audio_status
+            .iter()
+            .filter(|(_, status)| status.take_event())
+            .map(|(id, _)| *id)
+            .collect();
+        for node_id in finished {
+            self.call_callback_with("audio_event", self.audio_event, (node_id,))?;
+        }
+        Ok(())
+    }
+
+    /// Save the running app into the `sys` dir so that it can be resumed after power-off.
+    ///
+    /// Should be called when the device is about to sleep. Also called automatically
+    /// when the battery is critically low. On the next start without an explicit app ID,
+    /// the app is resumed instead of starting the launcher. The snapshot is discarded
+    /// if the app exits normally.
+    ///
+    /// Host-side resources (audio graph, canvas, custom menu items) aren't saved,
+    /// and the app recreates them in the `resume` callback. So, apps that don't
+    /// export `resume` can't be suspended.
+    pub fn suspend(&mut self) -> Result<(), Error> {
+        if !self.can_suspend() {
+            return Err(Error::SuspendUnsupported);
+        }
+        let state = self.store.data_mut();
+        if !matches!(state.net_handler.get_mut(), NetHandler::None) {
+            return Err(Error::SuspendInNet);
+        }
+        let Some(memory) = state.memory else {
+            return Err(Error::MemoryNotFound);
+        };
+
+        let mut globals = heapless::Vec::new();
+        for export in self.instance.exports(&self.store) {
+            let Ok(name) = heapless::String::try_from(export.name()) else {
+                continue;
+            };
+            let Some(global) = export.into_global() else {
+                continue;
+            };
+            if !global.ty(&self.store).mutability().is_mut() {
+                continue;
+            }
+            let val = match global.get(&self.store) {
+                wasmi::Val::I32(val) => SavedVal::I32(val),
+                wasmi::Val::I64(val) => SavedVal::I64(val),
+                _ => continue,
+            };
+            if globals.push((name, val)).is_err() {
+                break;
+            }
+        }
+
+        let (data, state) = memory.data_and_store_mut(&mut self.store);
+        let snapshot = Snapshot {
+            id: state.id.clone(),
+            bin_size: self.bin_size,
+            seed: state.seed,
+            lock_seed: state.lock_seed,
+            palette: state.frame.palette.map(|c| [c.0, c.1]),
+            globals,
+            memory_size: data.len() as u32,
+        };
+        let mut buf = [0; suspend::HEADER_SIZE];
+        let header = snapshot.encode(&mut buf)?;
+        let parts: [&[u8]; 3] = [header, &state.frame.data[..], data];
+        suspend::save(&mut state.device, &parts)?;
+        state.save_stash();
+        self.suspended = true;
+        Ok(())
+    }
+
+    /// Check if the app exports the `resume` callback needed to resume it.
+    fn can_suspend(&self) -> bool {
+        let ins = self.instance;
+        ins.get_typed_func::<(), ()>(&self.store, "resume").is_ok()
+    }
+
+    /// Load the guest memory, globals, and the frame buffer from the snapshot file.
+    fn restore(&mut self, snapshot: &Snapshot) -> Result<(), Error> {
+        let state = self.store.data_mut();
+        let Some(memory) = state.memory else {
+            return Err(Error::MemoryNotFound);
+        };
+        let mut dir = match state.device.open_dir(&["sys"]) {
+            Ok(dir) => dir,
+            Err(err) => return Err(Error::OpenDir("sys".into(), err)),
+        };
+        let mut stream = match dir.open_file(suspend::SNAPSHOT_FILE) {
+            Ok(stream) => stream,
+            Err(err) => return Err(Error::OpenFile(suspend::SNAPSHOT_FILE, err)),
+        };
+        // Skip the header, we already have it.
+        Snapshot::read(&mut stream)?;
+        suspend::read_exact(&mut stream, &mut state.frame.data[..])?;
+        for (color, [a, b]) in state.frame.palette.iter_mut().zip(snapshot.palette) {
+            *color = Rgb16(a, b);
+        }
+        state.frame.dirty = true;
+        state.seed = snapshot.seed;
+        state.lock_seed = snapshot.lock_seed;
+
+        // Grow the guest memory to the size it had when suspended.
+        let size = snapshot.memory_size as usize;
+        let current = memory.data(&self.store).len();
+        if size < current {
+            return Err(Error::InvalidSnapshot);
+        }
+        let pages = (size - current) / (64 * KB as usize);
+        if pages > 0 && memory.grow(&mut self.store, pages as _).is_err() {
+            return Err(Error::MemoryOutOfBounds);
+        }
+        let data = memory.data_mut(&mut self.store);
+        let Some(data) = data.get_mut(..size) else {
+            return Err(Error::InvalidSnapshot);
+        };
+        suspend::read_exact(&mut stream, data)?;
+
+        for (name, val) in &snapshot.globals {
+            let val = match *val {
+                SavedVal::I32(val) => wasmi::Val::I32(val),
+                SavedVal::I64(val) => wasmi::Val::I64(val),
+            };
+            let Some(global) = self.instance.get_global(&self.store, name) else {
+                return Err(Error::GlobalNotFound);
+            };
+            if global.set(&mut self.store, val).is_err() {
+                return Err(Error::GlobalWrite);
+            }
+        }
+        Ok(())
+    }
+
+    /// Poll the battery and suspend the app if the battery is about to die.
+    ///
+    /// If the app keeps running because the battery got charged,
+    /// the snapshot is outdated and so it is removed. It will be taken again
+    /// if the battery gets critically low again.
+    fn check_battery(&mut self) {
+        let state = self.store.data_mut();
+        let Some(battery) = &mut state.battery else {
+            return;
+        };
+        if let Err(err) = battery.update(&mut state.device) {
+            state.device.log_error("battery", err);
+            return;
+        }
+        let critical = battery.ok && !battery.connected && battery.percent <= CRITICAL_BATTERY;
+        if critical == self.suspended {
+            return;
+        }
+        if !critical {
+            suspend::remove(&mut state.device);
+            self.suspended = false;
+            return;
+        }
+        // Don't log an error every time for apps that don't support suspending.
+        if !self.can_suspend() {
+            return;
+        }
+        if let Err(err) = self.suspend() {
+            let state = self.store.data_mut();
+            state.device.log_error("suspend", err);
+        }
+    }
+
     // Delay the screen flushing to adjust the frame rate.
     fn delay(&mut self) {
-        let state = self.store.data();
+        let state = self.store.data_mut();
         let now = state.device.now();
         let elapsed = now - self.prev_time;
         if elapsed < self.per_frame {
@@ -343,7 +817,7 @@ where
                     // we shaved off the previous lag, yay!
                     stats.lags -= self.prev_lag;
                 }
-                state.device.delay(delay);
+                state.delay(delay);
             }
             self.fast_frames = (self.fast_frames + 1) % (FPS * 4);
             self.prev_lag = Duration::from_ms(0);
@@ -368,6 +842,11 @@ where
     pub fn finalize(mut self) -> Result<RuntimeConfig<'a, D, C>, Error> {
         self.call_callback("before_exit", self.before_exit)?;
         let mut state = self.store.into_data();
+        state.stop_audio_capture();
+        // The app exited normally, there is nothing to resume.
+        if self.suspended {
+            suspend::remove(&mut state.device);
+        }
         state.save_stash();
         state.update_app_stats();
         state.save_app_stats();
@@ -416,21 +895,65 @@ where
             }
         }
         self.send_stats()?;
+        self.send_samples()?;
+        self.send_watches()?;
         Ok(())
     }
 
+    /// Send the watched ranges of the guest memory to the serial port.
+    fn send_watches(&mut self) -> Result<(), Error> {
+        if self.watches.is_empty() {
+            return Ok(());
+        }
+        let Some(memory) = self.store.data().memory else {
+            return Ok(());
+        };
+        let data = memory.data(&self.store);
+        let mut messages = Vec::new();
+        for (ptr, len) in self.watches.due() {
+            let start = ptr as usize;
+            let buf = start
+                .checked_add(len as usize)
+                .and_then(|end| data.get(start..end));
+            let msg = match buf {
+                Some(buf) => format_memory("watch", ptr, buf),
+                None => alloc::format!("watch {ptr:#x}: out of bounds"),
+            };
+            messages.push(serial::Response::Log(msg));
+        }
+        for resp in messages {
+            self.serial_send(resp)?;
+        }
+        Ok(())
+    }
+
+    /// Send the flat profile collected by the sampling profiler to the serial port.
+    fn send_samples(&mut self) -> Result<(), Error> {
+        let Some(sampler) = &mut self.sampler else {
+            return Ok(());
+        };
+        let Some(resp) = sampler.as_message(&self.func_names) else {
+            return Ok(());
+        };
+        self.serial_send(resp)
+    }
+
     /// Send runtime stats to the serial port.
     fn send_stats(&mut self) -> Result<(), Error> {
         let Some(stats) = &mut self.stats else {
             return Ok(());
         };
-        let state = self.store.data();
-        let now = state.device.now();
-        if let Some(memory) = state.memory {
-            let data = memory.data(&self.store);
-            stats.analyze_memory(data);
+        if let Some(memory) = self.store.data().memory {
+            stats.set_memory_size(memory.data_size(&self.store));
         }
-        let Some(resp) = stats.as_message(now) else {
+        let state = self.store.data_mut();
+        stats.max_memory = u32::try_from(state.memory_growth.max).unwrap_or(u32::MAX);
+        stats.add_memory_access(state.mem_reads, state.mem_writes);
+        state.mem_reads = 0;
+        state.mem_writes = 0;
+        let now = state.device.now();
+        let profiler = state.profiler.as_mut();
+        let Some(resp) = stats.as_message(now, profiler) else {
             return Ok(());
         };
         let encoded = match resp.encode_vec() {
@@ -470,9 +993,18 @@ where
                 let now = state.device.now();
                 if stats && self.stats.is_none() {
                     self.stats = Some(StatsTracker::new(now));
+                    state.profiler = Some(HostProfiler::default());
+                    state.mem_reads = 0;
+                    state.mem_writes = 0;
+                    self.store.call_hook(profile_host_calls);
                 };
                 if !stats && self.stats.is_some() {
                     self.stats = None;
+                    let state = self.store.data_mut();
+                    state.profiler = None;
+                    // Wasmi has no way to remove the call hook,
+                    // so replace it with the cheapest possible one.
+                    self.store.call_hook(|_, _| Ok(()));
                 };
             }
             serial::Request::AppId => {
@@ -505,12 +1037,76 @@ where
                 let resp = serial::Response::Ok;
                 self.serial_send(resp)?;
             }
-            serial::Request::Buttons(_) => todo!(),
-            serial::Request::Data(_) => todo!(),
+            serial::Request::Buttons(_) => {
+                let msg = "ERROR(runtime): sending buttons is not supported";
+                self.serial_send(serial::Response::Log(msg.into()))?;
+            }
+            serial::Request::Data(data) => {
+                let data: &[u8] = data.as_ref();
+                if let Some(cmd) = Command::parse(data) {
+                    let resp = self.run_debug_command(cmd);
+                    return self.serial_send(resp);
+                }
+                match data {
+                    b"audio_capture:on" => self.set_audio_capture(true),
+                    b"audio_capture:off" => self.set_audio_capture(false),
+                    b"sampling:on" => self.set_sampling(true),
+                    b"sampling:off" => self.set_sampling(false),
+                    _ => match data.strip_prefix(b"text:") {
+                        Some(text) => {
+                            let text = alloc::string::String::from_utf8_lossy(text);
+                            self.push_text(&text);
+                        }
+                        None => {
+                            let msg = "ERROR(runtime): unknown data request";
+                            return self.serial_send(serial::Response::Log(msg.into()));
+                        }
+                    },
+                }
+                let resp = serial::Response::Ok;
+                self.serial_send(resp)?;
+            }
         }
         Ok(())
     }
 
+    /// Run a debugger command received over serial and make a response for it.
+    fn run_debug_command(&mut self, cmd: Command) -> serial::Response {
+        let res = match cmd {
+            Command::ReadMemory(ptr, len) => match self.read_memory(ptr, len) {
+                Ok(buf) => return serial::Response::Log(format_memory("memory", ptr, &buf)),
+                Err(err) => Err(err),
+            },
+            Command::WriteMemory(ptr, buf) => self.write_memory(ptr, &buf),
+            Command::Watch(ptr, len, every) => {
+                self.watch_memory(ptr, len, every);
+                Ok(())
+            }
+            Command::Unwatch(ptr) => {
+                self.unwatch_memory(ptr);
+                Ok(())
+            }
+            Command::ReadGlobal(name) => match self.read_global(name) {
+                Ok(val) => {
+                    let msg = alloc::format!("global {name}: {}", format_val(&val));
+                    return serial::Response::Log(msg);
+                }
+                Err(err) => Err(err),
+            },
+            Command::WriteGlobal(name, raw) => match self.read_global(name) {
+                Ok(old) => match parse_val(&old, raw) {
+                    Some(val) => self.write_global(name, val),
+                    None => Err(Error::GlobalWrite),
+                },
+                Err(err) => Err(err),
+            },
+        };
+        match res {
+            Ok(()) => serial::Response::Ok,
+            Err(err) => serial::Response::Log(alloc::format!("ERROR(runtime): {err}")),
+        }
+    }
+
     fn serial_send(&mut self, resp: serial::Response) -> Result<(), Error> {
         let encoded = match resp.encode_vec() {
             Ok(encoded) => encoded,
@@ -528,10 +1124,28 @@ where
         &mut self,
         name: &'static str,
         f: Option<wasmi::TypedFunc<(), ()>>,
+    ) -> Result<u32, Error> {
+        self.call_callback_with(name, f, ())
+    }
+
+    /// Like [`Runtime::call_callback`] but for callbacks accepting arguments.
+    fn call_callback_with<P: CallbackParams>(
+        &mut self,
+        name: &'static str,
+        f: Option<wasmi::TypedFunc<P, ()>>,
+        params: P,
     ) -> Result<u32, Error> {
         _ = self.store.set_fuel(FUEL_PER_CALL);
+        if let Some(profiler) = &mut self.store.data_mut().profiler {
+            profiler.callback = name;
+        }
         if let Some(f) = f {
-            if let Err(err) = f.call(&mut self.store, ()) {
+            let res = if self.sampler.is_some() {
+                self.call_sampled(name, f, params)
+            } else {
+                f.call(&mut self.store, params)
+            };
+            if let Err(err) = res {
                 let stats = self.store.data().runtime_stats();
                 return Err(Error::FuncCall(name, err, stats));
             }
@@ -543,6 +1157,127 @@ where
         let consumed = u32::try_from(consumed).unwrap_or_default();
         Ok(consumed)
     }
+
+    /// Call a guest function, interrupting it every [`FUEL_PER_SAMPLE`] to take a sample.
+    ///
+    /// The total fuel budget is the same as for a regular call.
+    /// When the function returns, the store has the unspent fuel left
+    /// so that the fuel consumption can be calculated as usual.
+    fn call_sampled<P: CallbackParams>(
+        &mut self,
+        name: &'static str,
+        f: wasmi::TypedFunc<P, ()>,
+        params: P,
+    ) -> Result<(), wasmi::Error> {
+        let mut budget = FUEL_PER_CALL;
+        let step = FUEL_PER_SAMPLE.min(budget);
+        budget -= step;
+        _ = self.store.set_fuel(step);
+        // The untyped call is used because only it allows to take
+        // the host error out of the paused invocation.
+        let params = params.to_vals();
+        let mut call = f.func().call_resumable(&mut self.store, &params, &mut [])?;
+        loop {
+            match call {
+                wasmi::ResumableCall::Finished => {
+                    let left = self.store.get_fuel().unwrap_or_default();
+                    _ = self.store.set_fuel(budget + left);
+                    return Ok(());
+                }
+                wasmi::ResumableCall::HostTrap(invocation) => {
+                    return Err(invocation.into_host_error());
+                }
+                wasmi::ResumableCall::OutOfFuel(invocation) => {
+                    let func = self.running_func();
+                    if let Some(sampler) = &mut self.sampler {
+                        sampler.add(name, func);
+                    }
+                    if budget == 0 {
+                        return Err(wasmi::TrapCode::OutOfFuel.into());
+                    }
+                    let step = FUEL_PER_SAMPLE.min(budget);
+                    budget -= step;
+                    _ = self.store.set_fuel(step);
+                    call = invocation.resume(&mut self.store, &mut [])?;
+                }
+            }
+        }
+    }
+
+    /// Get the index of the currently running guest function.
+    ///
+    /// Available only if the app binary was instrumented for sampling.
+    fn running_func(&self) -> Option<u32> {
+        let global = self.current_func?;
+        match global.get(&self.store) {
+            wasmi::Val::I32(idx) => u32::try_from(idx).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Arguments of guest callbacks that can be passed into an untyped call.
+trait CallbackParams: wasmi::WasmParams {
+    fn to_vals(&self) -> heapless::Vec<wasmi::Val, 1>;
+}
+
+impl CallbackParams for () {
+    fn to_vals(&self) -> heapless::Vec<wasmi::Val, 1> {
+        heapless::Vec::new()
+    }
+}
+
+impl CallbackParams for (u32,) {
+    fn to_vals(&self) -> heapless::Vec<wasmi::Val, 1> {
+        let mut vals = heapless::Vec::new();
+        _ = vals.push(wasmi::Val::I32(self.0 as i32));
+        vals
+    }
+}
+
+/// Measure the time spent in each host function.
+///
+/// Used as the store call hook when stats are requested over serial.
+fn profile_host_calls(state: &mut Box<State>, hook: wasmi::CallHook) -> Result<(), wasmi::Error> {
+    let Some(profiler) = &mut state.profiler else {
+        return Ok(());
+    };
+    match hook {
+        wasmi::CallHook::CallingHost => {
+            profiler.started = Some(state.device.now());
+        }
+        wasmi::CallHook::ReturningFromHost => {
+            if let Some(started) = profiler.started.take() {
+                let elapsed = state.device.now() - started;
+                profiler.add(state.called, elapsed);
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Format the value of a global for the debugger.
+fn format_val(val: &wasmi::Val) -> alloc::string::String {
+    match val {
+        wasmi::Val::I32(v) => alloc::format!("{v}"),
+        wasmi::Val::I64(v) => alloc::format!("{v}"),
+        wasmi::Val::F32(v) => alloc::format!("{}", f32::from(*v)),
+        wasmi::Val::F64(v) => alloc::format!("{}", f64::from(*v)),
+        _ => "unsupported type".into(),
+    }
+}
+
+/// Parse the new value for a global, using the same type as the old value has.
+fn parse_val(old: &wasmi::Val, raw: &str) -> Option<wasmi::Val> {
+    let val = match old {
+        wasmi::Val::I32(_) => wasmi::Val::I32(raw.parse().ok()?),
+        wasmi::Val::I64(_) => wasmi::Val::I64(raw.parse().ok()?),
+        wasmi::Val::F32(_) => wasmi::Val::F32(raw.parse::<f32>().ok()?.into()),
+        wasmi::Val::F64(_) => wasmi::Val::F64(raw.parse::<f64>().ok()?.into()),
+        _ => return None,
+    };
+    Some(val)
 }
 
 fn detect_launcher(device: &mut DeviceImpl) -> Option<FullID> {
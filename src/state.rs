@@ -1,24 +1,41 @@
+use crate::audio::{
+    wav_header, Adpcm, AudioCapture, AudioSettings, AudioTree, Gate, Master, MenuAudio, NodeStatus,
+    Scheduler, TrackerControl, CAPTURE_FILE, CAPTURE_TMP,
+};
 use crate::battery::Battery;
+use crate::button_events::ButtonEvents;
 use crate::canvas::Canvas;
-use crate::color::Rgb16;
+use crate::color::{Rgb16, Theme};
 use crate::config::FullID;
 use crate::error::RuntimeStats;
 use crate::error_scene::ErrorScene;
 use crate::frame_buffer::FrameBuffer;
-use crate::menu::{Menu, MenuItem};
+use crate::gestures::{Gestures, DEFAULT_DEAD_ZONE};
+use crate::i18n::Lang;
+use crate::keyboard::Keyboard;
+use crate::menu::{InputOption, Menu, MenuItem};
 use crate::net::*;
-use crate::utils::{read_all, read_all_into};
+use crate::remap::{next_step, InputProfile, Remapper, BUTTON_MASKS, SENSITIVITY_STEPS};
+use crate::stats::{HostProfiler, MemoryGrowth};
+use crate::text_input::TextInput;
+use crate::utils::{read_all, read_all_into, read_into};
 use crate::Error;
-use alloc::borrow::ToOwned;
 use alloc::boxed::Box;
-use core::cell::Cell;
+use alloc::rc::{Rc, Weak};
+use core::cell::{Cell, RefCell};
 use core::fmt::Display;
 use core::str::FromStr;
 use embedded_graphics::pixelcolor::{Rgb888, RgbColor};
-use embedded_io::Write;
+use embedded_io::{Read, Write};
 use firefly_hal::*;
 use firefly_types::Encode;
 
+/// How often the buttons are polled while waiting for the next frame.
+const POLL_INTERVAL: Duration = Duration::from_ms(4);
+
+/// A weak handle to the spare reader of an ADPCM node.
+type RewindSlot = Weak<RefCell<Option<<DirImpl as Dir>::Read>>>;
+
 #[allow(private_interfaces)]
 pub enum NetHandler<'a> {
     None,
@@ -43,6 +60,30 @@ pub(crate) struct State<'a> {
     /// Audio manager.
     pub audio: firefly_audio::Manager,
 
+    /// The structure of the audio graph: parents and types of nodes.
+    pub audio_tree: AudioTree,
+
+    /// Playback status of audio nodes, by node ID.
+    pub audio_status: alloc::vec::Vec<(u32, Rc<NodeStatus>)>,
+
+    /// Handles for controlling tracker nodes, by node ID.
+    pub trackers: alloc::vec::Vec<(u32, Rc<TrackerControl>)>,
+
+    /// Gates of envelope modulators, by node ID.
+    pub gates: alloc::vec::Vec<(u32, Rc<Gate>)>,
+
+    /// Spare readers for rewinding ADPCM nodes, by file name.
+    pub audio_rewinds: alloc::vec::Vec<(alloc::string::String, RewindSlot)>,
+
+    /// Timed events for the audio graph.
+    pub audio_scheduler: Scheduler,
+
+    /// The runtime-level gain used to pause or duck the app audio.
+    pub audio_master: Master,
+
+    /// If present, the audio output is written into a WAV file in the app data dir.
+    pub audio_capture: Option<AudioCapture>,
+
     /// The id of the currently running app.
     pub id: FullID,
 
@@ -71,19 +112,80 @@ pub(crate) struct State<'a> {
     pub next: Option<FullID>,
 
     /// The last read touch pad and buttons input of the current device.
+    ///
+    /// The input is remapped according to the user's input profile.
     pub input: Option<InputState>,
 
+    /// Applies the user's input profile (button swap, turbo, etc) to the input.
+    remapper: Remapper,
+
+    /// Button transitions of the local device.
+    pub button_events: ButtonEvents,
+
+    /// Button transitions of each peer in multiplayer.
+    pub peer_button_events: alloc::vec::Vec<ButtonEvents>,
+
+    /// Button transitions of the combined input of all peers in multiplayer.
+    pub combined_button_events: ButtonEvents,
+
+    /// Touch pad gestures of the local device.
+    pub gestures: Gestures,
+
+    /// Touch pad gestures of each peer in multiplayer.
+    pub peer_gestures: alloc::vec::Vec<Gestures>,
+
+    /// Touch pad gestures of the combined input of all peers in multiplayer.
+    pub combined_gestures: Gestures,
+
+    /// The radius of the touch pad center ignored when reading the pad as a d-pad.
+    pub dead_zone: u16,
+
+    /// Text typed on a keyboard in the emulator or sent over serial.
+    pub text_input: TextInput,
+
+    /// The on-screen keyboard, if opened by the app.
+    pub keyboard: Option<Keyboard>,
+
+    /// The text entered on the on-screen keyboard and the address where to write it.
+    pub keyboard_result: Option<(u32, alloc::string::String)>,
+
+    /// When the input was read for the current frame.
+    frame_start: Instant,
+
     /// The last called host function.
     pub called: &'static str,
 
+    /// Call counts and timings of host functions.
+    ///
+    /// Present only when stats are requested over serial.
+    pub profiler: Option<HostProfiler>,
+
+    /// The biggest memory size requested by the app.
+    pub memory_growth: MemoryGrowth,
+
+    /// How many bytes of the guest memory host functions read since the last report.
+    pub mem_reads: u32,
+
+    /// How many bytes of the guest memory host functions wrote since the last report.
+    pub mem_writes: u32,
+
     /// The device settings.
     pub settings: firefly_types::Settings,
 
+    /// Colors of the system UI, based on the settings.
+    pub theme: Theme,
+
+    /// The runtime audio settings.
+    pub audio_settings: AudioSettings,
+
+    /// The volume of the running app set by the user, in percents.
+    pub app_volume: u8,
+
     /// The battery status (State of Charge, aka SoC).
     pub battery: Option<Battery>,
 
     pub app_stats: Option<firefly_types::Stats>,
-    /// The number of update frames.
+    /// The number of update frames, not counting frames when the menu is open.
     n_frames: u32,
     pub stash: alloc::vec::Vec<u8>,
     pub stash_dirty: bool,
@@ -110,17 +212,35 @@ impl<'a> State<'a> {
         };
         let mut device = device;
         let maybe_battery = Battery::new(&mut device);
+        let now = device.now();
         let settings = load_settings(&mut device).unwrap_or_default();
+        let audio_settings = load_audio_settings(&mut device).unwrap_or_default();
+        let app_volume = load_app_volume(&mut device, &id).unwrap_or(100);
+        let input_profile = load_input_profile(&mut device, &id).unwrap_or_default();
+        let mut menu = Menu::new();
+        menu.set_volume(app_volume);
+        menu.set_menu_audio(audio_settings.menu);
+        menu.set_mono(audio_settings.mono);
+        menu.set_lang(Lang::from_code(settings.lang));
+        menu.set_input_profile(&input_profile);
         Box::new(Self {
             device,
             rom_dir,
             id,
             frame: FrameBuffer::new(),
             canvas: None,
-            menu: Menu::new(),
+            menu,
             launcher,
             error: None,
             audio: firefly_audio::Manager::new(),
+            audio_tree: AudioTree::new(),
+            audio_status: alloc::vec::Vec::new(),
+            trackers: alloc::vec::Vec::new(),
+            gates: alloc::vec::Vec::new(),
+            audio_rewinds: alloc::vec::Vec::new(),
+            audio_scheduler: Scheduler::default(),
+            audio_master: Master::new(),
+            audio_capture: None,
             battery: maybe_battery.ok(),
             seed,
             lock_seed: false,
@@ -128,9 +248,28 @@ impl<'a> State<'a> {
             next: None,
             exit: false,
             input: None,
+            remapper: Remapper::new(input_profile),
+            button_events: ButtonEvents::default(),
+            peer_button_events: alloc::vec::Vec::new(),
+            combined_button_events: ButtonEvents::default(),
+            gestures: Gestures::default(),
+            peer_gestures: alloc::vec::Vec::new(),
+            combined_gestures: Gestures::default(),
+            dead_zone: DEFAULT_DEAD_ZONE,
+            text_input: TextInput::default(),
+            keyboard: None,
+            keyboard_result: None,
+            frame_start: now,
             called: "",
+            profiler: None,
+            memory_growth: MemoryGrowth::default(),
+            mem_reads: 0,
+            mem_writes: 0,
             net_handler: Cell::new(net_handler),
+            theme: Theme::new(&settings),
             settings,
+            audio_settings,
+            app_volume,
             app_stats: None,
             n_frames: 0,
             stash: alloc::vec::Vec::new(),
@@ -253,6 +392,194 @@ impl<'a> State<'a> {
         }
     }
 
+    /// Switch the app volume to the next step and save it in the app data dir.
+    ///
+    /// Goes down by 25% and then wraps back to 100%.
+    fn change_volume(&mut self) {
+        self.app_volume = match self.app_volume {
+            0 => 100,
+            v => v.saturating_sub(25),
+        };
+        self.menu.set_volume(self.app_volume);
+
+        let dir_path = &["data", self.id.author(), self.id.app()];
+        let mut dir = match self.device.open_dir(dir_path) {
+            Ok(dir) => dir,
+            Err(err) => {
+                self.device.log_error("volume", err);
+                return;
+            }
+        };
+        let mut stream = match dir.create_file("volume") {
+            Ok(stream) => stream,
+            Err(err) => {
+                self.device.log_error("volume", err);
+                return;
+            }
+        };
+        let res = stream.write_all(&[self.app_volume]);
+        if let Err(err) = res {
+            let err = FSError::from(err);
+            self.device.log_error("volume", err);
+        }
+    }
+
+    /// Change the selected option of the input profile and save the profile.
+    fn change_input_option(&mut self, option: InputOption) {
+        let profile = &mut self.remapper.profile;
+        match option {
+            InputOption::SwapAB => profile.swap_ab = !profile.swap_ab,
+            InputOption::Toggle => profile.toggle = next_step(&BUTTON_MASKS, profile.toggle),
+            InputOption::Turbo => profile.turbo = next_step(&BUTTON_MASKS, profile.turbo),
+            InputOption::Sensitivity => {
+                profile.sensitivity = next_step(&SENSITIVITY_STEPS, profile.sensitivity);
+            }
+            InputOption::InvertX => profile.invert_x = !profile.invert_x,
+            InputOption::InvertY => profile.invert_y = !profile.invert_y,
+            InputOption::SaveGlobal => {
+                self.save_input_profile(true);
+                return;
+            }
+        }
+        self.menu.set_input_profile(&self.remapper.profile);
+        self.save_input_profile(false);
+    }
+
+    /// Save the current input profile for the app or as the default for all apps.
+    fn save_input_profile(&mut self, global: bool) {
+        let raw = match self.remapper.profile.encode() {
+            Ok(raw) => raw,
+            Err(err) => {
+                self.device.log_error("input profile", err);
+                return;
+            }
+        };
+        let res = if global {
+            self.device.open_dir(&["sys"])
+        } else {
+            self.device
+                .open_dir(&["data", self.id.author(), self.id.app()])
+        };
+        let mut dir = match res {
+            Ok(dir) => dir,
+            Err(err) => {
+                self.device.log_error("input profile", err);
+                return;
+            }
+        };
+        let mut stream = match dir.create_file("input") {
+            Ok(stream) => stream,
+            Err(err) => {
+                self.device.log_error("input profile", err);
+                return;
+            }
+        };
+        let res = stream.write_all(&raw);
+        if let Err(err) = res {
+            let err = FSError::from(err);
+            self.device.log_error("input profile", err);
+        }
+    }
+
+    /// Start writing the audio output into a WAV file in the app data dir.
+    pub(crate) fn start_audio_capture(&mut self) {
+        let dir_path = &["data", self.id.author(), self.id.app()];
+        let mut dir = match self.device.open_dir(dir_path) {
+            Ok(dir) => dir,
+            Err(err) => {
+                self.device.log_error("audio capture", err);
+                return;
+            }
+        };
+        let stream = match dir.create_file(CAPTURE_TMP) {
+            Ok(stream) => stream,
+            Err(err) => {
+                self.device.log_error("audio capture", err);
+                return;
+            }
+        };
+        self.audio_capture = Some(AudioCapture { stream, size: 0 });
+    }
+
+    /// Open spare readers for ADPCM nodes that used theirs to rewind.
+    ///
+    /// Called outside of the audio render path so that opening files
+    /// doesn't happen in the middle of rendering a node.
+    pub(crate) fn refill_rewinds(&mut self) {
+        self.audio_rewinds
+            .retain(|(_, rewind)| rewind.strong_count() > 0);
+        for (name, rewind) in &self.audio_rewinds {
+            let Some(rewind) = rewind.upgrade() else {
+                continue;
+            };
+            if rewind.borrow().is_some() {
+                continue;
+            }
+            let reader = match self.rom_dir.open_file(name) {
+                Ok(reader) => reader,
+                Err(err) => {
+                    self.log_error(err);
+                    continue;
+                }
+            };
+            match Adpcm::open_rewind(reader) {
+                Ok(reader) => *rewind.borrow_mut() = Some(reader),
+                Err(err) => self.log_error(err),
+            }
+        }
+    }
+
+    /// Append the encoded audio samples to the capture.
+    ///
+    /// If writing fails, the capture is stopped.
+    pub(crate) fn capture_audio(&mut self, raw: &[u8]) {
+        let Some(capture) = &mut self.audio_capture else {
+            return;
+        };
+        let res = capture.stream.write_all(raw);
+        if let Err(err) = res {
+            let err = FSError::from(err);
+            self.device.log_error("audio capture", err);
+            self.stop_audio_capture();
+            return;
+        }
+        capture.size = capture.size.saturating_add(raw.len() as u32);
+    }
+
+    /// Stop the audio capture, if any, and assemble the WAV file.
+    pub(crate) fn stop_audio_capture(&mut self) {
+        let Some(mut capture) = self.audio_capture.take() else {
+            return;
+        };
+        let res = capture.stream.flush();
+        if let Err(err) = res {
+            let err = FSError::from(err);
+            self.device.log_error("audio capture", err);
+        }
+        // Close the temporary file before reading it.
+        drop(capture.stream);
+
+        let dir_path = &["data", self.id.author(), self.id.app()];
+        let mut dir = match self.device.open_dir(dir_path) {
+            Ok(dir) => dir,
+            Err(err) => {
+                self.device.log_error("audio capture", err);
+                return;
+            }
+        };
+        if let Err(err) = copy_capture(&mut dir, capture.size) {
+            self.device.log_error("audio capture", err);
+        }
+        if let Err(err) = dir.remove_file(CAPTURE_TMP) {
+            self.device.log_error("audio capture", err);
+        }
+    }
+
+    /// For how many seconds the app has been running, excluding time in the menu.
+    pub(crate) fn play_time(&self) -> u32 {
+        self.n_frames / 60
+    }
+
     /// Save into stats struct the stats from the current play.
     ///
     /// Called just before saving the stats to the disk.
@@ -315,8 +642,13 @@ impl<'a> State<'a> {
     }
 
     /// Update the state: read inputs, handle system commands.
-    pub(crate) fn update(&mut self) -> Option<u8> {
-        self.n_frames += 1;
+    ///
+    /// Returns the index and the value of the custom menu item selected by the user.
+    pub(crate) fn update(&mut self) -> Option<(u8, u32)> {
+        // The app is paused while the menu is open, don't count it as playing.
+        if !self.menu.active() {
+            self.n_frames += 1;
+        }
         if let Some(scene) = self.error.as_mut() {
             let close = scene.update(&mut self.device);
             if close {
@@ -324,10 +656,37 @@ impl<'a> State<'a> {
             }
         }
 
-        if self.error.is_none() {
-            self.input = self.device.read_input();
-        }
+        // The menu uses the input as is, so that a broken profile
+        // can always be fixed from the menu.
+        let raw_input = if self.error.is_none() {
+            self.device.read_input()
+        } else {
+            None
+        };
+        // The input meant for the menu or the on-screen keyboard must not
+        // switch toggle buttons or reach the app.
+        let for_app = self.error.is_none() && !self.menu.active() && self.keyboard.is_none();
+        self.input = if for_app {
+            self.remapper.apply(raw_input.clone())
+        } else {
+            None
+        };
         self.update_net();
+        self.update_input_events();
+
+        // While the on-screen keyboard is open, it takes all the input.
+        if let Some(keyboard) = &mut self.keyboard {
+            let text = self.text_input.take(usize::MAX);
+            let mut result = keyboard.handle_text(&text);
+            if result.is_none() {
+                result = keyboard.handle_input(&raw_input);
+            }
+            if let Some(text) = result {
+                self.keyboard_result = Some((keyboard.buf_ptr, text));
+                self.keyboard = None;
+            }
+            return None;
+        }
 
         // Get combined input for all peers.
         //
@@ -337,21 +696,20 @@ impl<'a> State<'a> {
         // We use it to ensure that all players open the app menu simultaneously.
         let input = match self.net_handler.get_mut() {
             // single-player
-            NetHandler::None => self.input.clone(),
+            NetHandler::None => raw_input,
             // shouldn't be reachable
             NetHandler::Connector(_) => return None,
             // in launcher
-            NetHandler::Connection(_) => self.input.clone(),
+            NetHandler::Connection(_) => raw_input,
             // in game
             NetHandler::FrameSyncer(syncer) => {
                 // TODO: if menu is open, we need to adjust sync timeout
                 // for the frame syncer.
-                match &self.input {
-                    Some(input) => {
+                match raw_input {
+                    Some(mut input) => {
                         // In frame syncer, use shared input for the menu button
                         // (if one player presses it, press it for everyone)
                         // and local input for all other buttons.
-                        let mut input = input.clone();
                         if syncer.get_combined_input().menu() {
                             input.buttons |= 0b10000;
                         } else {
@@ -377,16 +735,164 @@ impl<'a> State<'a> {
             let action = self.menu.handle_input(&input);
             if let Some(action) = action {
                 match action {
-                    MenuItem::Custom(index, _) => return Some(*index),
+                    MenuItem::Custom(item) => return Some((item.index, item.value())),
+                    MenuItem::Volume(_) => self.change_volume(),
+                    MenuItem::MenuAudio(_) => self.change_menu_audio(),
+                    MenuItem::Mono(_) => self.toggle_mono(),
+                    MenuItem::InputSettings | MenuItem::Back => {}
+                    MenuItem::Input(option, _) => {
+                        let option = *option;
+                        self.change_input_option(option);
+                    }
                     MenuItem::ScreenShot => self.take_screenshot(),
                     MenuItem::Restart => self.set_next(Some(self.id.clone())),
                     MenuItem::Quit => self.set_next(None),
                 };
             };
+            // The text typed while the menu is open is not for the app.
+            if self.menu.active() {
+                self.text_input.clear();
+            }
         }
         None
     }
 
+    /// Switch between pausing and ducking the app audio in menu and save the choice.
+    fn change_menu_audio(&mut self) {
+        self.audio_settings.menu = match self.audio_settings.menu {
+            MenuAudio::Pause => MenuAudio::Duck,
+            MenuAudio::Duck => MenuAudio::Pause,
+        };
+        self.menu.set_menu_audio(self.audio_settings.menu);
+        // The menu is open, so the new mode applies right away.
+        self.audio_master.suspend(self.audio_settings.menu);
+        self.save_audio_settings();
+    }
+
+    /// Switch the mono downmix on or off and save the choice.
+    fn toggle_mono(&mut self) {
+        self.audio_settings.mono = !self.audio_settings.mono;
+        self.menu.set_mono(self.audio_settings.mono);
+        self.save_audio_settings();
+    }
+
+    /// Apply the device volume, the mono mode, and the app volume to the audio output.
+    ///
+    /// The device volume depends on whether headphones are connected,
+    /// so it is called before every audio write.
+    pub(crate) fn configure_audio(&mut self) {
+        let volume = if self.device.has_headphones() {
+            self.settings.headphones_volume
+        } else {
+            self.settings.speakers_volume
+        };
+        let mono = self.audio_settings.mono;
+        self.audio_master.configure(volume, mono, self.app_volume);
+    }
+
+    fn save_audio_settings(&mut self) {
+        let mut buf = [0u8; 16];
+        let raw = match self.audio_settings.encode(&mut buf) {
+            Ok(raw) => raw,
+            Err(err) => {
+                self.device.log_error("audio settings", err);
+                return;
+            }
+        };
+        let mut dir = match self.device.open_dir(&["sys"]) {
+            Ok(dir) => dir,
+            Err(err) => {
+                self.device.log_error("audio settings", err);
+                return;
+            }
+        };
+        let mut stream = match dir.create_file("audio") {
+            Ok(stream) => stream,
+            Err(err) => {
+                self.device.log_error("audio settings", err);
+                return;
+            }
+        };
+        let res = stream.write_all(raw);
+        if let Err(err) = res {
+            let err = FSError::from(err);
+            self.device.log_error("audio settings", err);
+        }
+    }
+
+    /// Record button transitions and recognize gestures for the current frame.
+    ///
+    /// In multiplayer, only the input synchronized between peers is used,
+    /// so that all devices see the same events.
+    fn update_input_events(&mut self) {
+        let offset = self.frame_offset();
+        self.frame_start = self.device.now();
+        let NetHandler::FrameSyncer(syncer) = self.net_handler.get_mut() else {
+            let input = self.input.as_ref();
+            let buttons = input.map_or(0, |input| input.buttons);
+            self.button_events.record(buttons, offset);
+            self.button_events.next_frame();
+            self.gestures
+                .update(input.and_then(|input| input.pad.as_ref()));
+            return;
+        };
+        let n_peers = syncer.peers.len();
+        self.peer_button_events
+            .resize_with(n_peers, ButtonEvents::default);
+        self.peer_gestures.resize_with(n_peers, Gestures::default);
+        let peers = syncer.peers.iter();
+        let events = self.peer_button_events.iter_mut();
+        let gestures = self.peer_gestures.iter_mut();
+        for ((peer, events), gestures) in peers.zip(events).zip(gestures) {
+            let frame_state = peer.states.get_current();
+            let buttons = frame_state.map_or(0, |frame_state| frame_state.input.buttons);
+            events.record(buttons, 0);
+            events.next_frame();
+            let pad: Option<Pad> = frame_state.and_then(|s| s.input.pad.map(Into::into));
+            gestures.update(pad.as_ref());
+        }
+        let input = syncer.get_combined_input();
+        self.combined_button_events.record(input.buttons, 0);
+        self.combined_button_events.next_frame();
+        self.combined_gestures.update(input.pad.as_ref());
+    }
+
+    /// Milliseconds passed since the input was read for the current frame.
+    fn frame_offset(&self) -> u16 {
+        let elapsed = self.device.now() - self.frame_start;
+        u16::try_from(elapsed.ns() / 1_000_000).unwrap_or(u16::MAX)
+    }
+
+    /// Wait for the given time, polling the buttons in the meantime.
+    ///
+    /// Catches taps shorter than a frame. The polling is done only in single-player
+    /// because there is no way to sync sub-frame input between devices.
+    pub(crate) fn delay(&mut self, delay: Duration) {
+        let poll = matches!(self.net_handler.get_mut(), NetHandler::None) && self.error.is_none();
+        if !poll {
+            self.device.delay(delay);
+            return;
+        }
+        let start = self.device.now();
+        loop {
+            let buttons = self.device.read_input().map_or(0, |input| input.buttons);
+            let buttons = self.remapper.peek(buttons);
+            let offset = self.frame_offset();
+            self.button_events.record(buttons, offset);
+            let elapsed = self.device.now() - start;
+            if elapsed >= delay {
+                break;
+            }
+            let left = delay - elapsed;
+            let step = if left > POLL_INTERVAL {
+                POLL_INTERVAL
+            } else {
+                left
+            };
+            self.device.delay(step);
+        }
+    }
+
     fn update_net(&mut self) {
         let handler = self.net_handler.replace(NetHandler::None);
         let handler = match handler {
@@ -401,7 +907,7 @@ impl<'a> State<'a> {
     fn update_connector<'b>(&mut self, mut connector: Box<Connector<'b>>) -> NetHandler<'b> {
         let res = connector.update(&self.device);
         if let Err(err) = res {
-            self.error = Some(ErrorScene::new(alloc::format!("{err}")));
+            self.show_error(err.localize(self.lang()));
             self.device.log_error("netcode", err);
             return NetHandler::Connector(connector);
         }
@@ -430,8 +936,8 @@ impl<'a> State<'a> {
                 NetHandler::None
             }
             ConnectStatus::Finished => {
-                if let Err(err) = connector.validate() {
-                    self.error = Some(ErrorScene::new(err.to_owned()))
+                if let Err(msg) = connector.validate() {
+                    self.show_error(msg.translate(self.lang()));
                 }
                 self.set_next(None);
                 let connection = connector.finalize();
@@ -577,12 +1083,33 @@ impl<'a> State<'a> {
             let res = conn.disconnect();
             if let Err(err) = res {
                 self.device.log_error("netcode", &err);
-                let msg = alloc::format!("{err}");
-                self.error = Some(ErrorScene::new(msg));
+                self.show_error(err.localize(self.lang()));
             }
         }
     }
 
+    /// Count bytes of the guest memory read by the current host function.
+    pub(crate) fn count_reads(&mut self, size: usize) {
+        self.mem_reads = self.mem_reads.saturating_add(size as u32);
+    }
+
+    /// Count bytes of the guest memory written by the current host function.
+    pub(crate) fn count_writes(&mut self, size: usize) {
+        self.mem_writes = self.mem_writes.saturating_add(size as u32);
+    }
+
+    /// Show the error screen with the given message, the app ID, and runtime stats.
+    pub(crate) fn show_error<D: Display>(&mut self, msg: D) {
+        let msg = alloc::format!("{msg}");
+        let stats = self.runtime_stats();
+        self.error = Some(ErrorScene::new(msg, &self.id, &stats, self.lang()));
+    }
+
+    /// The language of the system UI.
+    fn lang(&self) -> Lang {
+        Lang::from_code(self.settings.lang)
+    }
+
     /// Log an error/warning occured in the currently executing host function.
     pub(crate) fn log_error<D: Display>(&self, msg: D) {
         self.device.log_error(self.called, msg);
@@ -647,3 +1174,98 @@ fn encode_palette(palette: &[Rgb16; 16]) -> [u8; 16 * 3] {
     }
     encoded
 }
+
+/// Write the WAV header and then the samples from the temporary capture file.
+fn copy_capture(dir: &mut DirImpl, size: u32) -> Result<(), FSError> {
+    let mut stream = dir.create_file(CAPTURE_FILE)?;
+    stream.write_all(&wav_header(size))?;
+    let mut samples = dir.open_file(CAPTURE_TMP)?;
+    let mut buf = [0u8; 512];
+    loop {
+        let n = samples.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        stream.write_all(&buf[..n])?;
+    }
+}
+
+/// Load the runtime audio settings. The file is optional.
+fn load_audio_settings(device: &mut DeviceImpl) -> Option<AudioSettings> {
+    let mut dir = match device.open_dir(&["sys"]) {
+        Ok(dir) => dir,
+        Err(err) => {
+            device.log_error("audio settings", err);
+            return None;
+        }
+    };
+    let file = match dir.open_file("audio") {
+        Ok(file) => file,
+        Err(FSError::NotFound) => return None,
+        Err(err) => {
+            device.log_error("audio settings", err);
+            return None;
+        }
+    };
+    let raw = match read_all(file) {
+        Ok(raw) => raw,
+        Err(err) => {
+            device.log_error("audio settings", FSError::from(err));
+            return None;
+        }
+    };
+    match AudioSettings::decode(&raw) {
+        Ok(settings) => Some(settings),
+        Err(err) => {
+            device.log_error("audio settings", err);
+            None
+        }
+    }
+}
+
+/// Load the volume of the app set by the user. The file is optional.
+fn load_app_volume(device: &mut DeviceImpl, id: &FullID) -> Option<u8> {
+    let dir_path = &["data", id.author(), id.app()];
+    let mut dir = device.open_dir(dir_path).ok()?;
+    let file = dir.open_file("volume").ok()?;
+    let mut buf = [0u8; 1];
+    let size = read_into(file, &mut buf).ok()?;
+    if size != 1 {
+        return None;
+    }
+    Some(buf[0].min(100))
+}
+
+/// Load the input profile of the app, falling back to the global one.
+///
+/// Both files are optional.
+fn load_input_profile(device: &mut DeviceImpl, id: &FullID) -> Option<InputProfile> {
+    let app_path = &["data", id.author(), id.app()];
+    load_input_profile_from(device, app_path).or_else(|| load_input_profile_from(device, &["sys"]))
+}
+
+fn load_input_profile_from(device: &mut DeviceImpl, dir_path: &[&str]) -> Option<InputProfile> {
+    let mut dir = device.open_dir(dir_path).ok()?;
+    let file = match dir.open_file("input") {
+        Ok(file) => file,
+        Err(FSError::NotFound) => return None,
+        Err(err) => {
+            device.log_error("input profile", err);
+            return None;
+        }
+    };
+    let raw = match read_all(file) {
+        Ok(raw) => raw,
+        Err(err) => {
+            device.log_error("input profile", FSError::from(err));
+            return None;
+        }
+    };
+    match InputProfile::decode(&raw) {
+        Ok(profile) => Some(profile),
+        Err(err) => {
+            device.log_error("input profile", err);
+            None
+        }
+    }
+}
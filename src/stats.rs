@@ -1,3 +1,7 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
 use firefly_hal::{Duration, Instant};
 use firefly_types::serial;
 
@@ -7,6 +11,17 @@ const KB: usize = 1024;
 /// How often (in update cycles) the stats should be emitted.
 const FREQ: u32 = 60;
 
+/// How many of the hottest host functions to report.
+const TOP_HOST_CALLS: usize = 5;
+
+/// How many of the most frequently sampled locations to report.
+const TOP_SAMPLES: usize = 8;
+
+/// How many instances, tables, and memories the store may have.
+///
+/// The same as the default in [`wasmi::StoreLimits`].
+const INSTANCES_LIMIT: usize = 10_000;
+
 pub(crate) struct StatsTracker {
     pub frame: u32,
 
@@ -26,7 +41,15 @@ pub(crate) struct StatsTracker {
     pub lags: Duration,
 
     pub pages: u16,
-    pub last_one: u32,
+
+    /// The biggest size (in bytes) the app ever requested for its memory.
+    pub max_memory: u32,
+
+    /// Bytes of the guest memory read by host functions since the last report.
+    pub reads: u32,
+
+    /// Bytes of the guest memory written by host functions since the last report.
+    pub writes: u32,
 }
 
 impl StatsTracker {
@@ -39,25 +62,28 @@ impl StatsTracker {
             delays: Duration::from_ms(0),
             lags: Duration::from_ms(0),
             pages: 0,
-            last_one: 0,
+            max_memory: 0,
+            reads: 0,
+            writes: 0,
         }
     }
 
-    pub fn analyze_memory(&mut self, data: &[u8]) {
-        let pages = data.len() / (64 * KB);
-        self.pages = pages as u16;
-        if self.frame % FREQ != 10 {
-            return;
-        }
-        for (i, byte) in data.iter().rev().enumerate() {
-            if byte != &0 {
-                self.last_one = (data.len() - i) as u32;
-                return;
-            }
-        }
+    /// Record the current size (in bytes) of the guest memory.
+    pub fn set_memory_size(&mut self, size: usize) {
+        self.pages = (size / (64 * KB)) as u16;
     }
 
-    pub fn as_message(&mut self, now: Instant) -> Option<serial::Response> {
+    /// Account for guest memory access by host functions.
+    pub fn add_memory_access(&mut self, reads: u32, writes: u32) {
+        self.reads = self.reads.saturating_add(reads);
+        self.writes = self.writes.saturating_add(writes);
+    }
+
+    pub fn as_message(
+        &mut self,
+        now: Instant,
+        profiler: Option<&mut HostProfiler>,
+    ) -> Option<serial::Response> {
         self.frame = self.frame.wrapping_add(1);
         // Skip the first period, we don't have enough stats yet.
         if self.frame < FREQ {
@@ -84,13 +110,27 @@ impl StatsTracker {
             12 => {
                 let memory = serial::Memory {
                     pages: self.pages,
-                    last_one: self.last_one,
-                    reads: 0,
-                    writes: 0,
-                    max: 0,
+                    // Finding the last used byte requires scanning the whole memory.
+                    // Instead, host functions count the bytes they access.
+                    last_one: 0,
+                    reads: self.reads,
+                    writes: self.writes,
+                    max: self.max_memory,
                 };
+                self.reads = 0;
+                self.writes = 0;
                 serial::Response::Memory(memory)
             }
+            14 => {
+                let profiler = profiler?;
+                serial::Response::Log(profiler.as_log("update"))
+            }
+            16 => {
+                let profiler = profiler?;
+                let msg = profiler.as_log("render");
+                profiler.reset();
+                serial::Response::Log(msg)
+            }
             _ => return None,
         };
         Some(message)
@@ -107,6 +147,61 @@ impl StatsTracker {
     }
 }
 
+/// Records the biggest memory size the app has ever grown its memory to.
+///
+/// Used as the store resource limiter. It doesn't limit anything:
+/// the memory size is already limited by the module's memory type.
+/// Requests that fail are not recorded.
+#[derive(Default)]
+pub(crate) struct MemoryGrowth {
+    pub max: usize,
+
+    /// The value of `max` before the last growth request.
+    ///
+    /// Restored if the request fails.
+    prev_max: usize,
+}
+
+impl wasmi::ResourceLimiter for MemoryGrowth {
+    fn memory_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> Result<bool, wasmi_core::LimiterError> {
+        self.prev_max = self.max;
+        if maximum.is_none_or(|maximum| desired <= maximum) {
+            self.max = self.max.max(desired);
+        }
+        Ok(true)
+    }
+
+    fn memory_grow_failed(&mut self, _error: &wasmi_core::LimiterError) {
+        self.max = self.prev_max;
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: usize,
+        _desired: usize,
+        _maximum: Option<usize>,
+    ) -> Result<bool, wasmi_core::LimiterError> {
+        Ok(true)
+    }
+
+    fn instances(&self) -> usize {
+        INSTANCES_LIMIT
+    }
+
+    fn tables(&self) -> usize {
+        INSTANCES_LIMIT
+    }
+
+    fn memories(&self) -> usize {
+        INSTANCES_LIMIT
+    }
+}
+
 #[derive(Default)]
 pub(crate) struct CallbackFuel {
     min: Option<u32>,
@@ -161,6 +256,139 @@ impl CallbackFuel {
     }
 }
 
+/// Call counts and time spent in each host function, separately for each callback.
+///
+/// The host function name is taken from `State::called`
+/// when the host function returns.
+#[derive(Default)]
+pub(crate) struct HostProfiler {
+    /// The guest callback that is currently running.
+    pub callback: &'static str,
+    /// The time when the currently running host function was called.
+    pub started: Option<Instant>,
+    calls: Vec<HostCalls>,
+}
+
+struct HostCalls {
+    callback: &'static str,
+    name: &'static str,
+    calls: u32,
+    time: Duration,
+}
+
+impl HostProfiler {
+    pub fn reset(&mut self) {
+        self.calls.clear();
+    }
+
+    /// Record a single call of the given host function from the current callback.
+    pub fn add(&mut self, name: &'static str, elapsed: Duration) {
+        let callback = self.callback;
+        let found = self
+            .calls
+            .iter_mut()
+            .find(|c| c.callback == callback && c.name == name);
+        if let Some(entry) = found {
+            entry.calls += 1;
+            entry.time += elapsed;
+            return;
+        }
+        self.calls.push(HostCalls {
+            callback,
+            name,
+            calls: 1,
+            time: elapsed,
+        });
+    }
+
+    /// Sort the host functions by the total time spent in them, the hottest first.
+    fn sort(&mut self) {
+        self.calls
+            .sort_by(|a, b| b.time.ns().cmp(&a.time.ns()).then(b.calls.cmp(&a.calls)));
+    }
+
+    /// Format the hottest host functions called from the callback as a log message.
+    fn as_log(&mut self, callback: &str) -> alloc::string::String {
+        self.sort();
+        let mut msg = alloc::string::String::from("host calls in ");
+        msg.push_str(callback);
+        msg.push(':');
+        let entries = self.calls.iter().filter(|c| c.callback == callback);
+        for entry in entries.take(TOP_HOST_CALLS) {
+            let us = entry.time.ns() / 1000;
+            _ = write!(msg, " {} x{} {us}us;", entry.name, entry.calls);
+        }
+        msg
+    }
+}
+
+/// A flat profile built by periodically interrupting the guest code.
+///
+/// Each sample is attributed to the running callback and the guest function
+/// running at the moment of the interruption. The function is known
+/// only if the app binary was instrumented (see [`crate::instrument`]).
+#[derive(Default)]
+pub(crate) struct Sampler {
+    frame: u32,
+    samples: Vec<Sample>,
+}
+
+struct Sample {
+    callback: &'static str,
+    /// The index of the running guest function.
+    func: Option<u32>,
+    count: u32,
+}
+
+impl Sampler {
+    /// Record a single sample.
+    pub fn add(&mut self, callback: &'static str, func: Option<u32>) {
+        let found = self
+            .samples
+            .iter_mut()
+            .find(|s| s.callback == callback && s.func == func);
+        if let Some(sample) = found {
+            sample.count += 1;
+            return;
+        }
+        self.samples.push(Sample {
+            callback,
+            func,
+            count: 1,
+        });
+    }
+
+    pub fn as_message(&mut self, names: &BTreeMap<u32, String>) -> Option<serial::Response> {
+        self.frame = self.frame.wrapping_add(1);
+        if !self.frame.is_multiple_of(FREQ) || self.samples.is_empty() {
+            return None;
+        }
+        let msg = self.as_log(names);
+        self.samples.clear();
+        Some(serial::Response::Log(msg))
+    }
+
+    /// Format the most frequently sampled functions as a log message.
+    ///
+    /// Functions missing in the name section are shown by their index.
+    fn as_log(&mut self, names: &BTreeMap<u32, String>) -> String {
+        self.samples.sort_by_key(|s| core::cmp::Reverse(s.count));
+        let mut msg = String::from("samples by guest function:");
+        for sample in self.samples.iter().take(TOP_SAMPLES) {
+            _ = write!(msg, " {} > ", sample.callback);
+            _ = match sample.func {
+                Some(idx) => match names.get(&idx) {
+                    Some(name) => write!(msg, "{name}"),
+                    None => write!(msg, "func[{idx}]"),
+                },
+                None => write!(msg, "<unknown>"),
+            };
+            _ = write!(msg, " x{};", sample.count);
+        }
+        msg
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -178,4 +406,64 @@ mod tests {
         assert_eq!(fuel.mean, 5);
         assert_eq!(fuel.var, 4.);
     }
+
+    #[test]
+    fn test_host_profiler() {
+        let mut profiler = HostProfiler {
+            callback: "render",
+            ..HostProfiler::default()
+        };
+        profiler.add("graphics.draw_image", Duration::from_ms(2));
+        profiler.add("input.read_pad", Duration::from_ms(1));
+        profiler.add("graphics.draw_image", Duration::from_ms(3));
+        profiler.add("input.read_pad", Duration::from_ms(1));
+        profiler.callback = "update";
+        profiler.add("input.read_pad", Duration::from_ms(1));
+        let msg = profiler.as_log("render");
+        assert_eq!(
+            msg,
+            "host calls in render: graphics.draw_image x2 5000us; input.read_pad x2 2000us;"
+        );
+        let msg = profiler.as_log("update");
+        assert_eq!(msg, "host calls in update: input.read_pad x1 1000us;");
+        profiler.reset();
+        assert_eq!(profiler.as_log("render"), "host calls in render:");
+    }
+
+    #[test]
+    fn test_memory_growth() {
+        use wasmi::ResourceLimiter;
+        let mut growth = MemoryGrowth::default();
+        let page = 64 * KB;
+        assert!(growth.memory_growing(0, 2 * page, Some(4 * page)).unwrap());
+        assert_eq!(growth.max, 2 * page);
+        // Above the maximum, will be denied.
+        assert!(growth
+            .memory_growing(2 * page, 5 * page, Some(4 * page))
+            .unwrap());
+        assert_eq!(growth.max, 2 * page);
+        // Allowed but failed to allocate.
+        assert!(growth.memory_growing(2 * page, 3 * page, None).unwrap());
+        let err = wasmi_core::LimiterError::OutOfSystemMemory;
+        growth.memory_grow_failed(&err);
+        assert_eq!(growth.max, 2 * page);
+    }
+
+    #[test]
+    fn test_sampler() {
+        let mut sampler = Sampler::default();
+        sampler.add("update", None);
+        sampler.add("render", Some(3));
+        sampler.add("render", Some(3));
+        sampler.add("update", Some(7));
+        sampler.add("render", Some(3));
+        sampler.add("update", Some(7));
+        let mut names = BTreeMap::new();
+        names.insert(3, String::from("draw_player"));
+        let msg = sampler.as_log(&names);
+        assert_eq!(
+            msg,
+            "samples by guest function: render > draw_player x3; update > func[7] x2; update > <unknown> x1;"
+        );
+    }
 }
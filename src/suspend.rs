@@ -0,0 +1,172 @@
+use crate::config::FullID;
+use crate::error::Error;
+use embedded_io::{Read, ReadExactError, Write};
+use firefly_hal::{Device, DeviceImpl, Dir, FSError};
+use serde::{Deserialize, Serialize};
+
+/// The file in the `sys` dir where the suspended app is saved.
+pub(crate) const SNAPSHOT_FILE: &str = "suspended";
+/// The battery charge (in percents) at which the running app is suspended.
+pub(crate) const CRITICAL_BATTERY: u8 = 3;
+/// The max size of the encoded snapshot header, including the size prefix.
+pub(crate) const HEADER_SIZE: usize = 1024;
+
+/// The value of a mutable global exported by the app.
+///
+/// Compilers use mutable globals only for integers (like the stack pointer),
+/// so floats and references aren't saved.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum SavedVal {
+    I32(i32),
+    I64(i64),
+}
+
+/// Everything besides the frame buffer and the guest memory needed to resume an app.
+///
+/// In the snapshot file, the header is prefixed by its size (2 bytes)
+/// and followed by the frame buffer and then the guest memory.
+///
+/// Host-side resources (audio graph, canvas, custom menu items) aren't saved.
+/// Instead of `boot`, the resumed app gets the `resume` callback to recreate them,
+/// and apps without that callback aren't suspended.
+#[derive(Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct Snapshot {
+    pub id: FullID,
+    /// The size of the app binary, used to detect if the app was updated.
+    pub bin_size: u32,
+    pub seed: u32,
+    pub lock_seed: bool,
+    pub palette: [[u8; 2]; 16],
+    pub globals: heapless::Vec<(heapless::String<32>, SavedVal), 32>,
+    /// The size of the guest memory in bytes.
+    pub memory_size: u32,
+}
+
+impl Snapshot {
+    /// Encode the header into the buffer, prefixed by its size.
+    pub fn encode<'b>(&self, buf: &'b mut [u8; HEADER_SIZE]) -> Result<&'b [u8], Error> {
+        let size = match postcard::to_slice(self, &mut buf[2..]) {
+            Ok(raw) => raw.len(),
+            Err(err) => return Err(Error::EncodeSnapshot(err)),
+        };
+        buf[..2].copy_from_slice(&(size as u16).to_le_bytes());
+        Ok(&buf[..size + 2])
+    }
+
+    /// Read and decode the size-prefixed header from the start of the snapshot file.
+    pub fn read<R>(stream: &mut R) -> Result<Self, Error>
+    where
+        R: Read,
+        FSError: From<R::Error>,
+    {
+        let mut size = [0; 2];
+        read_exact(stream, &mut size)?;
+        let size = usize::from(u16::from_le_bytes(size));
+        let mut buf = [0; HEADER_SIZE];
+        let Some(raw) = buf.get_mut(..size) else {
+            return Err(Error::InvalidSnapshot);
+        };
+        read_exact(stream, raw)?;
+        match postcard::from_bytes(raw) {
+            Ok(snapshot) => Ok(snapshot),
+            Err(err) => Err(Error::DecodeSnapshot(err)),
+        }
+    }
+}
+
+/// Fill the buffer with the next bytes of the snapshot file.
+pub(crate) fn read_exact<R>(stream: &mut R, buf: &mut [u8]) -> Result<(), Error>
+where
+    R: Read,
+    FSError: From<R::Error>,
+{
+    match stream.read_exact(buf) {
+        Ok(()) => Ok(()),
+        Err(ReadExactError::UnexpectedEof) => Err(Error::InvalidSnapshot),
+        Err(ReadExactError::Other(err)) => Err(Error::ReadFile(SNAPSHOT_FILE, err.into())),
+    }
+}
+
+/// Write the snapshot file in the `sys` dir.
+pub(crate) fn save(device: &mut DeviceImpl, parts: &[&[u8]]) -> Result<(), Error> {
+    let mut dir = match device.open_dir(&["sys"]) {
+        Ok(dir) => dir,
+        Err(err) => return Err(Error::OpenDir("sys".into(), err)),
+    };
+    let mut stream = match dir.create_file(SNAPSHOT_FILE) {
+        Ok(stream) => stream,
+        Err(err) => return Err(Error::OpenFile(SNAPSHOT_FILE, err)),
+    };
+    for part in parts {
+        if let Err(err) = stream.write_all(part) {
+            return Err(Error::WriteFile(SNAPSHOT_FILE, err.into()));
+        }
+    }
+    Ok(())
+}
+
+/// Read the header of the suspended app, if any.
+///
+/// Broken snapshots and snapshots of removed apps are discarded.
+pub(crate) fn load(device: &mut DeviceImpl) -> Option<Snapshot> {
+    let mut dir = device.open_dir(&["sys"]).ok()?;
+    let mut stream = dir.open_file(SNAPSHOT_FILE).ok()?;
+    let snapshot = match Snapshot::read(&mut stream) {
+        Ok(snapshot) => snapshot,
+        Err(err) => {
+            device.log_error("suspend", err);
+            remove(device);
+            return None;
+        }
+    };
+    let id = &snapshot.id;
+    if device.open_dir(&["roms", id.author(), id.app()]).is_err() {
+        remove(device);
+        return None;
+    }
+    Some(snapshot)
+}
+
+/// Remove the snapshot file, if any.
+pub(crate) fn remove(device: &mut DeviceImpl) {
+    let mut dir = match device.open_dir(&["sys"]) {
+        Ok(dir) => dir,
+        Err(err) => {
+            device.log_error("suspend", err);
+            return;
+        }
+    };
+    match dir.remove_file(SNAPSHOT_FILE) {
+        Ok(()) | Err(FSError::NotFound) => {}
+        Err(err) => device.log_error("suspend", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let mut globals = heapless::Vec::new();
+        let name = heapless::String::try_from("__stack_pointer").unwrap();
+        globals.push((name, SavedVal::I32(1024))).unwrap();
+        let snapshot = Snapshot {
+            id: FullID::from_str("demo", "snake").unwrap(),
+            bin_size: 4096,
+            seed: 13,
+            lock_seed: true,
+            palette: [[1, 2]; 16],
+            globals,
+            memory_size: 65536,
+        };
+        let mut buf = [0; HEADER_SIZE];
+        let Ok(raw) = snapshot.encode(&mut buf) else {
+            unreachable!()
+        };
+        let size = usize::from(u16::from_le_bytes([raw[0], raw[1]]));
+        assert_eq!(size, raw.len() - 2);
+        let decoded: Snapshot = postcard::from_bytes(&raw[2..]).unwrap();
+        assert!(decoded == snapshot);
+    }
+}
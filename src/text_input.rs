@@ -0,0 +1,69 @@
+use alloc::string::String;
+
+/// How many bytes of typed text can be buffered until the app reads it.
+const MAX_LEN: usize = 256;
+
+/// Text typed on a keyboard and not yet read by the app.
+///
+/// The device itself has no keyboard. The text comes from the emulator
+/// (which can forward the desktop keyboard) or from a serial request.
+/// Control characters (like backspace and newline) are passed as is.
+#[derive(Default)]
+pub(crate) struct TextInput {
+    buf: String,
+}
+
+impl TextInput {
+    /// Add typed text to the end of the buffer.
+    ///
+    /// If the buffer is full, the characters that don't fit are dropped.
+    pub fn push(&mut self, text: &str) {
+        for ch in text.chars() {
+            if self.buf.len() + ch.len_utf8() > MAX_LEN {
+                break;
+            }
+            self.buf.push(ch);
+        }
+    }
+
+    /// Remove and return the oldest typed text that fits into the given number of bytes.
+    ///
+    /// Characters are never split in the middle.
+    pub fn take(&mut self, max_len: usize) -> String {
+        let mut end = max_len.min(self.buf.len());
+        while !self.buf.is_char_boundary(end) {
+            end -= 1;
+        }
+        let rest = self.buf.split_off(end);
+        core::mem::replace(&mut self.buf, rest)
+    }
+
+    /// Drop all the text not yet read by the app.
+    pub fn clear(&mut self) {
+        self.buf.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take() {
+        let mut input = TextInput::default();
+        input.push("héllo");
+        assert_eq!(input.take(2), "h");
+        assert_eq!(input.take(3), "él");
+        assert_eq!(input.take(10), "lo");
+        assert_eq!(input.take(10), "");
+    }
+
+    #[test]
+    fn test_overflow() {
+        let mut input = TextInput::default();
+        for _ in 0..30 {
+            input.push("0123456789");
+        }
+        assert_eq!(input.take(1000).len(), MAX_LEN);
+    }
+}
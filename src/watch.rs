@@ -0,0 +1,178 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+/// How many bytes of the guest memory a single watch can cover.
+const MAX_WATCH_SIZE: u32 = 64;
+
+/// A subscription to a range of the guest memory.
+struct Watch {
+    ptr: u32,
+    len: u32,
+    /// How often (in update cycles) the range should be sent.
+    every: u32,
+}
+
+/// Ranges of the guest memory that should be periodically sent over serial.
+///
+/// Together with the cheat callback, it's a lightweight live debugger
+/// for tuning app variables without recompiling the app.
+#[derive(Default)]
+pub(crate) struct Watches {
+    frame: u32,
+    items: Vec<Watch>,
+}
+
+impl Watches {
+    /// Subscribe to the memory range. Replaces the old watch on the same address.
+    pub fn add(&mut self, ptr: u32, len: u32, every: u32) {
+        self.remove(ptr);
+        self.items.push(Watch {
+            ptr,
+            len: len.min(MAX_WATCH_SIZE),
+            every: every.max(1),
+        });
+    }
+
+    /// Unsubscribe from the memory range starting at the given address.
+    pub fn remove(&mut self, ptr: u32) {
+        self.items.retain(|w| w.ptr != ptr);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Advance the frame counter and get the memory ranges that should be sent now.
+    pub fn due(&mut self) -> impl Iterator<Item = (u32, u32)> + use<'_> {
+        self.frame = self.frame.wrapping_add(1);
+        let frame = self.frame;
+        self.items
+            .iter()
+            .filter(move |w| frame.is_multiple_of(w.every))
+            .map(|w| (w.ptr, w.len))
+    }
+}
+
+/// A debugger command sent over serial as data.
+///
+/// Numbers are decimal or hexadecimal with the `0x` prefix.
+/// The bytes to write are hexadecimal without prefix, like `00ff1a`.
+pub(crate) enum Command<'a> {
+    /// `mem_read:<ptr>:<len>`
+    ReadMemory(u32, u32),
+    /// `mem_write:<ptr>:<bytes>`
+    WriteMemory(u32, Vec<u8>),
+    /// `watch:<ptr>:<len>:<every>`
+    Watch(u32, u32, u32),
+    /// `unwatch:<ptr>`
+    Unwatch(u32),
+    /// `global_read:<name>`
+    ReadGlobal(&'a str),
+    /// `global_write:<name>:<value>`
+    WriteGlobal(&'a str, &'a str),
+}
+
+impl<'a> Command<'a> {
+    /// Parse the command. None if the data is not a valid debugger command.
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        let data = core::str::from_utf8(data).ok()?;
+        let (name, args) = data.split_once(':')?;
+        let mut args = args.split(':');
+        let cmd = match name {
+            "mem_read" => Self::ReadMemory(parse_u32(args.next()?)?, parse_u32(args.next()?)?),
+            "mem_write" => Self::WriteMemory(parse_u32(args.next()?)?, parse_hex(args.next()?)?),
+            "watch" => Self::Watch(
+                parse_u32(args.next()?)?,
+                parse_u32(args.next()?)?,
+                parse_u32(args.next()?)?,
+            ),
+            "unwatch" => Self::Unwatch(parse_u32(args.next()?)?),
+            "global_read" => Self::ReadGlobal(args.next()?),
+            "global_write" => Self::WriteGlobal(args.next()?, args.next()?),
+            _ => return None,
+        };
+        if args.next().is_some() {
+            return None;
+        }
+        Some(cmd)
+    }
+}
+
+fn parse_u32(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    let mut buf = Vec::with_capacity(s.len() / 2);
+    for i in (0..s.len()).step_by(2) {
+        let byte = u8::from_str_radix(s.get(i..i + 2)?, 16).ok()?;
+        buf.push(byte);
+    }
+    Some(buf)
+}
+
+/// Format the content of a memory range as a log message.
+///
+/// The prefix tells if it's a watched range or a response to a read request.
+pub(crate) fn format_memory(prefix: &str, ptr: u32, data: &[u8]) -> String {
+    let mut msg = alloc::format!("{prefix} {ptr:#x}:");
+    for byte in data {
+        _ = write!(msg, " {byte:02x}");
+    }
+    msg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watches_due() {
+        let mut watches = Watches::default();
+        watches.add(0x10, 4, 2);
+        watches.add(0x20, 1000, 3);
+        let due: Vec<_> = watches.due().collect();
+        assert_eq!(due, []);
+        let due: Vec<_> = watches.due().collect();
+        assert_eq!(due, [(0x10, 4)]);
+        let due: Vec<_> = watches.due().collect();
+        assert_eq!(due, [(0x20, MAX_WATCH_SIZE)]);
+        watches.remove(0x20);
+        let due: Vec<_> = watches.due().collect();
+        assert_eq!(due, [(0x10, 4)]);
+    }
+
+    #[test]
+    fn test_format_memory() {
+        let msg = format_memory("watch", 0x1f, &[0, 1, 0xab]);
+        assert_eq!(msg, "watch 0x1f: 00 01 ab");
+    }
+
+    #[test]
+    fn test_parse_command() {
+        let cmd = Command::parse(b"mem_read:0x10:4");
+        assert!(matches!(cmd, Some(Command::ReadMemory(0x10, 4))));
+        let Some(Command::WriteMemory(ptr, buf)) = Command::parse(b"mem_write:16:00ff1a") else {
+            unreachable!()
+        };
+        assert_eq!(ptr, 16);
+        assert_eq!(buf, [0, 0xff, 0x1a]);
+        let cmd = Command::parse(b"watch:0x20:8:60");
+        assert!(matches!(cmd, Some(Command::Watch(0x20, 8, 60))));
+        let cmd = Command::parse(b"global_write:SCORE:-3");
+        assert!(matches!(cmd, Some(Command::WriteGlobal("SCORE", "-3"))));
+
+        assert!(Command::parse(b"text:hello").is_none());
+        assert!(Command::parse(b"mem_read:0x10").is_none());
+        assert!(Command::parse(b"mem_read:0x10:4:5").is_none());
+        assert!(Command::parse(b"mem_write:16:0ff").is_none());
+        assert!(Command::parse(b"unwatch:0xzz").is_none());
+    }
+}